@@ -2,10 +2,12 @@ use cgmath::Vector3;
 
 use crate::utils::vector_utils::{Interval, Ray};
 
+#[allow(dead_code)]
 pub trait HitableAccelStructure {
     fn intersect(&self, ray: &Ray, bounds: Interval) -> bool;
 }
 
+#[derive(Copy, Clone, Debug)]
 pub struct AABB {
     // Axis-Aligned Bounding Box.
     // An AABB is defined by three intervals
@@ -41,6 +43,91 @@ impl AABB {
         };
     }
 
+    pub fn new_empty() -> AABB {
+        // An inverted box, merging anything into it yields that thing.
+        return AABB {
+            x_interval: Interval::new(f32::MAX, f32::MIN),
+            y_interval: Interval::new(f32::MAX, f32::MIN),
+            z_interval: Interval::new(f32::MAX, f32::MIN),
+        };
+    }
+
+    pub fn expand_to_point(&self, point: Vector3<f32>) -> AABB {
+        return AABB::new_from_aabbs(*self, AABB::new_from_diagonals(point, point));
+    }
+
+    pub fn min(&self) -> Vector3<f32> {
+        return Vector3::new(
+            self.x_interval.min,
+            self.y_interval.min,
+            self.z_interval.min,
+        );
+    }
+
+    pub fn max(&self) -> Vector3<f32> {
+        return Vector3::new(
+            self.x_interval.max,
+            self.y_interval.max,
+            self.z_interval.max,
+        );
+    }
+
+    pub fn centroid(&self) -> Vector3<f32> {
+        return (self.min() + self.max()) * 0.5;
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let extent = self.max() - self.min();
+        if extent.x < 0.0 || extent.y < 0.0 || extent.z < 0.0 {
+            return 0.0;
+        }
+        return 2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x);
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let extent = self.max() - self.min();
+        if extent.x > extent.y && extent.x > extent.z {
+            return 0;
+        }
+        if extent.y > extent.z {
+            return 1;
+        }
+        return 2;
+    }
+
+    // Same slab test as `intersect`, but with a precomputed inverse direction
+    // and returning the distance at which the ray enters the box.
+    #[inline]
+    pub fn hit_distance(
+        &self,
+        ray: &Ray,
+        inv_direction: Vector3<f32>,
+        bounds: Interval,
+    ) -> Option<f32> {
+        let mut t_min = bounds.min;
+        let mut t_max = bounds.max;
+
+        for i in 0..3 {
+            let axis = self.get_axis(i);
+            let mut t0 = (axis.min - ray.origin[i]) * inv_direction[i];
+            let mut t1 = (axis.max - ray.origin[i]) * inv_direction[i];
+
+            if inv_direction[i] < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            // Written so that NaNs (0 * inf) keep the previous bounds.
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        return Some(t_min);
+    }
+
     pub fn get_axis(&self, i: usize) -> &Interval {
         match i {
             0 => &self.x_interval,
@@ -63,9 +150,7 @@ impl HitableAccelStructure for AABB {
             let mut t1 = (self.get_axis(i).max - orig) * inv_d;
 
             if inv_d < 0.0 {
-                let temp = t0;
-                t0 = t1;
-                t1 = temp;
+                std::mem::swap(&mut t0, &mut t1);
             }
 
             if t0 > temp_bounds.min {
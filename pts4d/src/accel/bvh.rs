@@ -1,2 +1,488 @@
-#[allow(dead_code)]
-struct BVH {}
+use cgmath::Vector3;
+
+use crate::accel::aabb::AABB;
+use crate::object::object::Hit;
+use crate::utils::vector_utils::{Interval, Ray};
+
+// Number of buckets the centroids are binned into when evaluating the SAH.
+const BIN_COUNT: usize = 12;
+// Leaves are never split below this many primitives.
+const MIN_LEAF_SIZE: usize = 2;
+// Leaves are forced to split above this many primitives, whatever the SAH says.
+const MAX_LEAF_SIZE: usize = 8;
+// Relative cost of visiting a node vs. intersecting a primitive.
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECTION_COST: f32 = 1.0;
+
+pub struct BVHNode {
+    pub bbox: AABB,
+    // Interior nodes: index of the second child, the first one is always right after its parent.
+    // Leaves: offset of the first primitive in `primitive_indices`.
+    offset: usize,
+    // 0 for interior nodes.
+    primitive_count: usize,
+}
+
+impl BVHNode {
+    pub fn is_leaf(&self) -> bool {
+        return self.primitive_count > 0;
+    }
+}
+
+/**
+ * Bounding Volume Hierarchy, built with a binned Surface Area Heuristic and
+ * stored as a flat, depth-first array of nodes.
+ * The BVH does not own the primitives, it only knows their bounding boxes and
+ * hands out their indices to the caller during traversal.
+ */
+pub struct BVH {
+    nodes: Vec<BVHNode>,
+    primitive_indices: Vec<usize>,
+}
+
+#[derive(Copy, Clone)]
+struct BuildPrimitive {
+    index: usize,
+    bbox: AABB,
+    centroid: Vector3<f32>,
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    bbox: AABB,
+    count: usize,
+}
+
+impl BVH {
+    pub fn build(bounding_boxes: &[AABB]) -> BVH {
+        let mut primitives: Vec<BuildPrimitive> = bounding_boxes
+            .iter()
+            .enumerate()
+            .map(|(index, bbox)| BuildPrimitive {
+                index,
+                bbox: *bbox,
+                centroid: bbox.centroid(),
+            })
+            .collect();
+
+        let mut bvh = BVH {
+            nodes: Vec::with_capacity(2 * bounding_boxes.len()),
+            primitive_indices: Vec::with_capacity(bounding_boxes.len()),
+        };
+
+        if primitives.is_empty() {
+            bvh.nodes.push(BVHNode {
+                bbox: AABB::new_empty(),
+                offset: 0,
+                primitive_count: 0,
+            });
+            return bvh;
+        }
+
+        bvh.build_recursive(primitives.as_mut_slice());
+        return bvh;
+    }
+
+    fn build_recursive(&mut self, primitives: &mut [BuildPrimitive]) -> usize {
+        let node_index = self.nodes.len();
+
+        let mut bbox = AABB::new_empty();
+        let mut centroid_bbox = AABB::new_empty();
+        for primitive in primitives.iter() {
+            bbox = AABB::new_from_aabbs(bbox, primitive.bbox);
+            centroid_bbox = centroid_bbox.expand_to_point(primitive.centroid);
+        }
+
+        self.nodes.push(BVHNode {
+            bbox,
+            offset: 0,
+            primitive_count: 0,
+        });
+
+        let split = if primitives.len() <= MIN_LEAF_SIZE {
+            None
+        } else {
+            find_sah_split(primitives, &bbox, &centroid_bbox)
+        };
+
+        let mid = match split {
+            Some((axis, split_bin)) => {
+                let axis_min = centroid_bbox.get_axis(axis).min;
+                let extent = centroid_bbox.get_axis(axis).max - axis_min;
+                partition(primitives, |p| {
+                    bin_of(p.centroid[axis], axis_min, extent) <= split_bin
+                })
+            }
+            None if primitives.len() > MAX_LEAF_SIZE => {
+                // The SAH prefers a leaf (or all centroids coincide), but the leaf would be
+                // too large. Fall back to splitting the primitives in half along the longest axis.
+                let axis = centroid_bbox.longest_axis();
+                primitives.sort_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+                primitives.len() / 2
+            }
+            None => 0,
+        };
+
+        if mid == 0 || mid == primitives.len() {
+            let offset = self.primitive_indices.len();
+            self.primitive_indices
+                .extend(primitives.iter().map(|p| p.index));
+            self.nodes[node_index].offset = offset;
+            self.nodes[node_index].primitive_count = primitives.len();
+            return node_index;
+        }
+
+        let (left, right) = primitives.split_at_mut(mid);
+        self.build_recursive(left);
+        let right_index = self.build_recursive(right);
+        self.nodes[node_index].offset = right_index;
+
+        return node_index;
+    }
+
+    // Traverses the hierarchy front to back, calling `intersect_primitive` on every primitive
+    // whose leaf is reached. The closure receives the primitive index and the current bounds,
+    // which shrink as closer hits are found, so far away subtrees get skipped altogether.
+    pub fn intersect<'a, F>(
+        &self,
+        ray: &Ray,
+        bounds: Interval,
+        mut intersect_primitive: F,
+    ) -> Option<Hit<'a>>
+    where
+        F: FnMut(usize, Interval) -> Option<Hit<'a>>,
+    {
+        let inv_direction = Vector3::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+
+        let mut closest_hit: Option<Hit<'a>> = None;
+        let mut closest_t = bounds.max;
+
+        self.nodes[0]
+            .bbox
+            .hit_distance(ray, inv_direction, bounds)?;
+
+        // (node index, distance at which the ray enters the node)
+        let mut stack: Vec<(usize, f32)> = Vec::with_capacity(64);
+        stack.push((0, bounds.min));
+
+        while let Some((node_index, entry_t)) = stack.pop() {
+            if entry_t > closest_t {
+                continue;
+            }
+
+            let node = &self.nodes[node_index];
+            if node.is_leaf() {
+                for i in node.offset..node.offset + node.primitive_count {
+                    let primitive_index = self.primitive_indices[i];
+                    if let Some(hit) =
+                        intersect_primitive(primitive_index, Interval::new(bounds.min, closest_t))
+                    {
+                        if hit.point_at_intersection < closest_t {
+                            closest_t = hit.point_at_intersection;
+                            closest_hit = Some(hit);
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let current_bounds = Interval::new(bounds.min, closest_t);
+            let left_index = node_index + 1;
+            let right_index = node.offset;
+            let left_t =
+                self.nodes[left_index]
+                    .bbox
+                    .hit_distance(ray, inv_direction, current_bounds);
+            let right_t =
+                self.nodes[right_index]
+                    .bbox
+                    .hit_distance(ray, inv_direction, current_bounds);
+
+            // Push the farther child first, so the closer one gets popped first.
+            match (left_t, right_t) {
+                (Some(l), Some(r)) => {
+                    if l <= r {
+                        stack.push((right_index, r));
+                        stack.push((left_index, l));
+                    } else {
+                        stack.push((left_index, l));
+                        stack.push((right_index, r));
+                    }
+                }
+                (Some(l), None) => stack.push((left_index, l)),
+                (None, Some(r)) => stack.push((right_index, r)),
+                (None, None) => {}
+            }
+        }
+
+        return closest_hit;
+    }
+
+    // Counts how many nodes a ray passes through, ignoring the primitives.
+    // Used to visualise how well the hierarchy fits the scene.
+    pub fn count_node_hits(&self, ray: &Ray, bounds: Interval) -> usize {
+        let inv_direction = Vector3::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+
+        let mut count = 0;
+        let mut stack: Vec<usize> = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.bbox.hit_distance(ray, inv_direction, bounds).is_none() {
+                continue;
+            }
+
+            count += 1;
+            if !node.is_leaf() {
+                stack.push(node_index + 1);
+                stack.push(node.offset);
+            }
+        }
+
+        return count;
+    }
+}
+
+// Evaluates the SAH over BIN_COUNT buckets on every axis and returns the cheapest split,
+// as (axis, last bin of the left side), or None if keeping the primitives in a single leaf is cheaper.
+fn find_sah_split(
+    primitives: &[BuildPrimitive],
+    bbox: &AABB,
+    centroid_bbox: &AABB,
+) -> Option<(usize, usize)> {
+    let parent_area = bbox.surface_area();
+    let leaf_cost = INTERSECTION_COST * primitives.len() as f32;
+
+    let mut best_cost = leaf_cost;
+    let mut best_split: Option<(usize, usize)> = None;
+
+    for axis in 0..3 {
+        let axis_min = centroid_bbox.get_axis(axis).min;
+        let axis_max = centroid_bbox.get_axis(axis).max;
+        let extent = axis_max - axis_min;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut bins = [Bin {
+            bbox: AABB::new_empty(),
+            count: 0,
+        }; BIN_COUNT];
+
+        for primitive in primitives {
+            let bin_index = bin_of(primitive.centroid[axis], axis_min, extent);
+            bins[bin_index].count += 1;
+            bins[bin_index].bbox = AABB::new_from_aabbs(bins[bin_index].bbox, primitive.bbox);
+        }
+
+        // Sweep from the right to get the area and count of everything right of each plane.
+        let mut right_areas = [0.0; BIN_COUNT - 1];
+        let mut right_counts = [0; BIN_COUNT - 1];
+        let mut right_bbox = AABB::new_empty();
+        let mut right_count = 0;
+        for i in (1..BIN_COUNT).rev() {
+            right_bbox = AABB::new_from_aabbs(right_bbox, bins[i].bbox);
+            right_count += bins[i].count;
+            right_areas[i - 1] = right_bbox.surface_area();
+            right_counts[i - 1] = right_count;
+        }
+
+        let mut left_bbox = AABB::new_empty();
+        let mut left_count = 0;
+        for i in 0..BIN_COUNT - 1 {
+            left_bbox = AABB::new_from_aabbs(left_bbox, bins[i].bbox);
+            left_count += bins[i].count;
+
+            if left_count == 0 || right_counts[i] == 0 {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST
+                + INTERSECTION_COST
+                    * (left_bbox.surface_area() * left_count as f32
+                        + right_areas[i] * right_counts[i] as f32)
+                    / parent_area;
+
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = Some((axis, i));
+            }
+        }
+    }
+
+    return best_split;
+}
+
+#[inline]
+fn bin_of(value: f32, axis_min: f32, extent: f32) -> usize {
+    let bin = ((value - axis_min) / extent * BIN_COUNT as f32) as usize;
+    return bin.min(BIN_COUNT - 1);
+}
+
+// In-place partition, returns the number of elements for which the predicate holds.
+fn partition<F>(primitives: &mut [BuildPrimitive], predicate: F) -> usize
+where
+    F: Fn(&BuildPrimitive) -> bool,
+{
+    let mut mid = 0;
+    for i in 0..primitives.len() {
+        if predicate(&primitives[i]) {
+            primitives.swap(i, mid);
+            mid += 1;
+        }
+    }
+    return mid;
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2, Vector3};
+    use rand::Rng;
+
+    use super::*;
+    use crate::materials::material::Material;
+    use crate::object::object::PrimitiveId;
+    use crate::object::triangle::intersect_moller_trumbore;
+
+    type Triangle = [Vector3<f32>; 3];
+
+    fn random_point(rng: &mut impl Rng, size: f32) -> Vector3<f32> {
+        return Vector3::new(
+            rng.gen_range(-size..size),
+            rng.gen_range(-size..size),
+            rng.gen_range(-size..size),
+        );
+    }
+
+    fn hit_triangle<'a>(
+        ray: &Ray,
+        triangles: &[Triangle],
+        index: usize,
+        bounds: Interval,
+        material: &'a Material,
+    ) -> Option<Hit<'a>> {
+        let [a, b, c] = triangles[index];
+        let t = intersect_moller_trumbore(ray, a, b, c, bounds)?.t;
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        return Some(Hit {
+            point_at_intersection: t,
+            point: ray.point_at(t),
+            normal: zero,
+            geometric_normal: zero,
+            is_facing_you: true,
+            uv: Vector2::new(0.0, 0.0),
+            dpdu: zero,
+            dpdv: zero,
+            material,
+            primitive: PrimitiveId {
+                object: 0,
+                triangle: index,
+            },
+        });
+    }
+
+    fn build(triangles: &[Triangle]) -> BVH {
+        let boxes: Vec<AABB> = triangles
+            .iter()
+            .map(|[a, b, c]| AABB::new_from_diagonals(*a, *b).expand_to_point(*c))
+            .collect();
+        return BVH::build(&boxes);
+    }
+
+    #[test]
+    fn finds_the_same_closest_hits_as_testing_every_triangle() {
+        let mut rng = rand::thread_rng();
+        let material = Material::Diffuse(Vector3::new(0.5, 0.5, 0.5));
+        let bounds = Interval::new(0.001, f32::MAX);
+
+        let triangles: Vec<Triangle> = (0..500)
+            .map(|_| {
+                let center = random_point(&mut rng, 10.0);
+                [
+                    center + random_point(&mut rng, 1.0),
+                    center + random_point(&mut rng, 1.0),
+                    center + random_point(&mut rng, 1.0),
+                ]
+            })
+            .collect();
+        let bvh = build(&triangles);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // Aimed close to a random triangle, so that most rays hit something.
+            let [a, b, c] = triangles[rng.gen_range(0..triangles.len())];
+            let origin = random_point(&mut rng, 15.0);
+            let ray = Ray {
+                origin,
+                direction: (a + b + c) / 3.0 + random_point(&mut rng, 0.5) - origin,
+            };
+            let expected = (0..triangles.len())
+                .filter_map(|index| hit_triangle(&ray, &triangles, index, bounds, &material))
+                .min_by(|a, b| a.point_at_intersection.total_cmp(&b.point_at_intersection));
+            let found = bvh.intersect(&ray, bounds, |index, bounds| {
+                hit_triangle(&ray, &triangles, index, bounds, &material)
+            });
+
+            match (expected, found) {
+                (None, None) => {}
+                (Some(expected), Some(found)) => {
+                    hits += 1;
+                    assert_eq!(expected.primitive, found.primitive);
+                    assert_eq!(expected.point_at_intersection, found.point_at_intersection);
+                }
+                _ => panic!(
+                    "The BVH and the brute force disagree on whether {:?} hits",
+                    ray
+                ),
+            }
+        }
+        assert!(hits > 500, "Only {} rays hit a triangle", hits);
+    }
+
+    #[test]
+    fn handles_empty_and_single_primitive_trees() {
+        let material = Material::Diffuse(Vector3::new(0.5, 0.5, 0.5));
+        let bounds = Interval::new(0.001, f32::MAX);
+        let ray = Ray {
+            origin: Vector3::new(0.25, 0.25, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+
+        let empty = BVH::build(&[]);
+        assert!(empty
+            .intersect(&ray, bounds, |_, _| panic!("There is nothing to intersect"))
+            .is_none());
+        assert_eq!(empty.count_node_hits(&ray, bounds), 0);
+
+        let triangle = [[
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        ]];
+        let single = build(&triangle);
+        let hit = single
+            .intersect(&ray, bounds, |index, bounds| {
+                hit_triangle(&ray, &triangle, index, bounds, &material)
+            })
+            .unwrap();
+        assert_eq!(hit.primitive.triangle, 0);
+        assert!((hit.point_at_intersection - 1.0).abs() < 1e-6);
+
+        let miss = Ray {
+            origin: Vector3::new(2.0, 2.0, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        assert!(single
+            .intersect(&miss, bounds, |index, bounds| {
+                hit_triangle(&miss, &triangle, index, bounds, &material)
+            })
+            .is_none());
+    }
+}
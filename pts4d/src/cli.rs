@@ -0,0 +1,210 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::scene::settings::RenderSettings;
+use crate::utils::image_output::image_format;
+
+pub const USAGE: &str = "Usage: pts4d [OPTIONS]
+
+Opens a window and progressively renders the scene, unless --headless is given.
+
+Options:
+  --scene <NAME|FILE>  Scene to render: cornell-box (default), polygon, or a .scene file
+  --obj <PATH>         OBJ model to load into the polygon scene
+  --headless           Render without a window and write the result to --output
+  --width <PIXELS>     Width of the rendered image (default: 400)
+  --height <PIXELS>    Height of the rendered image (default: 400)
+  --max-depth <N>      Maximum number of bounces of a path (default: 8)
+  --samples-per-pass <SAMPLES>
+                       Samples per pixel taken in every render pass (default: 35)
+  --debug-aabb         Render a heatmap of the bounding boxes rays go through
+  --spectral           Trace wavelengths instead of RGB colors, for dispersion
+  --spp <SAMPLES>      Stop once this many samples per pixel have been accumulated (headless only)
+  --time <SECONDS>     Stop once this much time has been spent rendering (headless only)
+  --output <PATH>      Where to write the image, as .png or .ppm (default: render.png)
+  --help               Print this message";
+
+pub struct CliOptions {
+    pub scene: String,
+    pub obj_path: Option<String>,
+    pub headless: bool,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub max_depth: Option<i32>,
+    pub samples_per_pass: Option<i32>,
+    pub debug_aabb: bool,
+    pub spectral: bool,
+    pub samples_per_pixel: Option<i32>,
+    pub time_budget: Option<Duration>,
+    pub output: String,
+    pub help: bool,
+}
+
+impl Default for CliOptions {
+    fn default() -> CliOptions {
+        return CliOptions {
+            scene: "cornell-box".to_string(),
+            obj_path: None,
+            headless: false,
+            width: None,
+            height: None,
+            max_depth: None,
+            samples_per_pass: None,
+            debug_aabb: false,
+            spectral: false,
+            samples_per_pixel: None,
+            time_budget: None,
+            output: "render.png".to_string(),
+            help: false,
+        };
+    }
+}
+
+impl CliOptions {
+    // The given render settings, overridden by whatever was passed on the command line.
+    pub fn render_settings(&self, base: RenderSettings) -> RenderSettings {
+        return RenderSettings {
+            width: self.width.unwrap_or(base.width),
+            height: self.height.unwrap_or(base.height),
+            max_depth: self.max_depth.unwrap_or(base.max_depth),
+            samples_per_pass: self.samples_per_pass.unwrap_or(base.samples_per_pass),
+            debug_aabb: self.debug_aabb || base.debug_aabb,
+            spectral: self.spectral || base.spectral,
+        };
+    }
+}
+
+// Parses the command line arguments, without the program name.
+pub fn parse_args<I>(args: I) -> Result<CliOptions, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = CliOptions::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => options.headless = true,
+            "--help" | "-h" => options.help = true,
+            "--scene" => options.scene = next_value(&mut args, &arg)?,
+            "--obj" => options.obj_path = Some(next_value(&mut args, &arg)?),
+            "--output" | "-o" => {
+                options.output = next_value(&mut args, &arg)?;
+                // Checked now rather than after what may be hours of rendering.
+                image_format(&options.output)?;
+            }
+            "--width" => options.width = Some(parse_positive(&mut args, &arg)?),
+            "--height" => options.height = Some(parse_positive(&mut args, &arg)?),
+            "--max-depth" => options.max_depth = Some(parse_positive(&mut args, &arg)?),
+            "--samples-per-pass" => {
+                options.samples_per_pass = Some(parse_positive(&mut args, &arg)?)
+            }
+            "--debug-aabb" => options.debug_aabb = true,
+            "--spectral" => options.spectral = true,
+            "--spp" => options.samples_per_pixel = Some(parse_positive(&mut args, &arg)?),
+            "--time" => {
+                let value = next_value(&mut args, &arg)?;
+                let seconds = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|seconds| *seconds > 0.0 && seconds.is_finite())
+                    .ok_or(format!(
+                        "Expected a positive number of seconds for --time, got '{}'",
+                        value
+                    ))?;
+                options.time_budget = Some(Duration::from_secs_f64(seconds));
+            }
+            _ => return Err(format!("Unknown argument '{}'\n\n{}", arg, USAGE)),
+        }
+    }
+
+    return Ok(options);
+}
+
+fn next_value<I>(args: &mut I, flag: &str) -> Result<String, String>
+where
+    I: Iterator<Item = String>,
+{
+    return args
+        .next()
+        .ok_or(format!("Missing value for {}\n\n{}", flag, USAGE));
+}
+
+// A positive integer, which also has to fit in the type it is parsed into.
+fn parse_positive<T, I>(args: &mut I, flag: &str) -> Result<T, String>
+where
+    T: FromStr + PartialOrd + Default,
+    I: Iterator<Item = String>,
+{
+    let value = next_value(args, flag)?;
+    return value
+        .parse::<T>()
+        .ok()
+        .filter(|number| *number > T::default())
+        .ok_or(format!(
+            "Expected a positive integer for {}, got '{}'",
+            flag, value
+        ));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::scene::scene_loader::parse_scene;
+
+    fn parse(args: &str) -> Result<CliOptions, String> {
+        return parse_args(args.split_whitespace().map(str::to_string));
+    }
+
+    #[test]
+    fn parses_options_and_rejects_bad_values() {
+        let options = parse("--headless --width 640 --spp 100 --time 2.5 -o out.ppm").unwrap();
+        assert!(options.headless);
+        assert_eq!(options.width, Some(640));
+        assert_eq!(options.height, None);
+        assert_eq!(options.samples_per_pixel, Some(100));
+        assert_eq!(options.time_budget, Some(Duration::from_millis(2500)));
+        assert_eq!(options.output, "out.ppm");
+        assert_eq!(options.scene, "cornell-box");
+
+        for bad in [
+            "--width 0",
+            "--height -3",
+            "--spp 3000000000",
+            "--max-depth many",
+            "--time 0",
+            "--output render.jpg",
+            "--scene",
+            "--fast",
+        ] {
+            assert!(parse(bad).is_err(), "'{}' should have been rejected", bad);
+        }
+    }
+
+    #[test]
+    fn command_line_and_scene_settings_override_the_defaults() {
+        let defaults = RenderSettings::default();
+        let scene = parse_scene(
+            "settings width 320 samples-per-pass 2\ncamera from 0 1 5 at 0 1 0",
+            "test.scene",
+            Path::new(""),
+        )
+        .unwrap();
+        let options = parse("--height 100 --samples-per-pass 8 --spp 64").unwrap();
+        let settings = options.render_settings(scene.settings);
+
+        assert_eq!(settings.width, 320);
+        assert_eq!(settings.height, 100);
+        assert_eq!(settings.samples_per_pass, 8);
+        assert_eq!(settings.max_depth, defaults.max_depth);
+        assert!(!settings.spectral);
+
+        // Without a scene file, the command line applies on top of the defaults.
+        let settings = parse("--spectral").unwrap().render_settings(defaults);
+        assert_eq!(settings.width, defaults.width);
+        assert_eq!(settings.samples_per_pass, defaults.samples_per_pass);
+        assert!(settings.spectral);
+    }
+}
@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+
+use crate::cli::CliOptions;
+use crate::renderer::render_pass;
+use crate::scene::scene::Scene;
+use crate::scene::settings::RenderSettings;
+use crate::utils::image_output::save_screen;
+use crate::utils::rendering_utils::add_screens;
+
+// Renders the scene without a window, accumulating passes until either the
+// samples per pixel target or the time budget is reached, then writes the image to disk.
+pub fn render_to_file(
+    scene: &Scene,
+    settings: &RenderSettings,
+    options: &CliOptions,
+) -> Result<(), String> {
+    let samples_per_pass = settings.samples_per_pass;
+
+    // Without any stopping criteria a single pass is rendered.
+    let target_passes = match (options.samples_per_pixel, options.time_budget) {
+        // Both are positive, so neither the conversion nor the division can overflow.
+        (Some(samples), _) => (samples as u32).div_ceil(samples_per_pass as u32) as i32,
+        (None, Some(_)) => i32::MAX,
+        (None, None) => 1,
+    };
+    let time_budget = options.time_budget.unwrap_or(Duration::MAX);
+
+    let start_time = Instant::now();
+    let mut all_frames = render_pass(scene, settings);
+    let mut passes = 1;
+
+    while passes < target_passes && start_time.elapsed() < time_budget {
+        all_frames = add_screens(all_frames, render_pass(scene, settings));
+        passes += 1;
+        println!(
+            "Pass {} - {:?} elapsed - Acc. {} SPP",
+            passes,
+            start_time.elapsed(),
+            passes * samples_per_pass
+        );
+    }
+
+    save_screen(&all_frames, passes, &options.output)?;
+    println!(
+        "Wrote '{}' ({} SPP in {:?})",
+        options.output,
+        passes * samples_per_pass,
+        start_time.elapsed()
+    );
+
+    return Ok(());
+}
@@ -0,0 +1,525 @@
+use std::f32::consts::PI;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Vector3};
+use image::codecs::hdr::HdrDecoder;
+use rand::Rng;
+
+use crate::utils::vector_utils::orthonormal_basis;
+
+use super::sky::Sky;
+
+// How often the sun is sampled rather than the rest of the sky.
+const SUN_SAMPLING_PROBABILITY: f32 = 0.5;
+
+/**
+ * Light arriving from infinitely far away, seen by every ray which escapes the scene.
+ */
+#[derive(Clone, Debug)]
+pub enum Background {
+    Constant(Vector3<f32>),
+    // Blends from the bottom color straight down to the top color straight up.
+    Gradient {
+        bottom: Vector3<f32>,
+        top: Vector3<f32>,
+    },
+    // A latitude-longitude (equirectangular) panorama.
+    Image(Arc<EnvironmentImage>),
+    // A daylight sky, along with its sun.
+    Sky(Arc<Sky>),
+}
+
+/**
+ * The background of a scene, turned and scaled, along with what it takes to sample it.
+ */
+#[derive(Clone, Debug)]
+pub struct Environment {
+    background: Background,
+    intensity: f32,
+    // Rotation around the vertical axis, in radians.
+    rotation: f32,
+    // Only images and skies are bright enough in places to be worth importance sampling.
+    distribution: Option<Arc<Distribution2D>>,
+    // The sun is too small to be found in a table, so it is sampled on its own.
+    sun: Option<Sun>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Sun {
+    // In world space, after rotating the sky.
+    direction: Vector3<f32>,
+    cos_theta_max: f32,
+    radiance: Vector3<f32>,
+}
+
+impl Sun {
+    #[inline]
+    fn contains(&self, direction: Vector3<f32>) -> bool {
+        return direction.dot(self.direction) >= self.cos_theta_max;
+    }
+
+    #[inline]
+    fn pdf(&self) -> f32 {
+        return 1.0 / (2.0 * PI * (1.0 - self.cos_theta_max));
+    }
+}
+
+impl Environment {
+    pub fn new(background: Background, intensity: f32, rotation_degrees: f32) -> Environment {
+        let rotation = rotation_degrees.to_radians();
+        let distribution = match &background {
+            Background::Image(image) => Some(Arc::new(image.distribution())),
+            Background::Sky(sky) => Some(Arc::new(Distribution2D::tabulate(128, 64, |u, v| {
+                return luminance(sky.radiance(uv_to_direction(u, v)));
+            }))),
+            _ => None,
+        };
+        let sun = match &background {
+            Background::Sky(sky) if luminance(sky.sun_radiance) > 0.0 => Some(Sun {
+                direction: rotate_y(sky.sun_direction, rotation),
+                cos_theta_max: sky.sun_cos_theta_max,
+                radiance: sky.sun_radiance,
+            }),
+            _ => None,
+        };
+
+        return Environment {
+            background,
+            intensity,
+            rotation,
+            distribution,
+            sun,
+        };
+    }
+
+    // Radiance arriving from the unit `direction`, i.e. seen when looking that way.
+    pub fn radiance(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let color = match &self.background {
+            Background::Constant(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let t = 0.5 * (direction.y + 1.0);
+                (1.0 - t) * bottom + t * top
+            }
+            Background::Image(image) => {
+                let (u, v) = direction_to_uv(rotate_y(direction, -self.rotation));
+                image.texel(u, v)
+            }
+            Background::Sky(sky) => sky.radiance(rotate_y(direction, -self.rotation)),
+        };
+        let sun = match self.sun {
+            Some(sun) if sun.contains(direction) => sun.radiance,
+            _ => Vector3::new(0.0, 0.0, 0.0),
+        };
+        return self.intensity * (color + sun);
+    }
+
+    // Samples a unit direction towards the environment, along with its solid angle density.
+    pub fn sample(&self) -> Option<(Vector3<f32>, f32)> {
+        let mut rng = rand::thread_rng();
+
+        if let Some(sun) = self.sun {
+            if rng.gen::<f32>() < SUN_SAMPLING_PROBABILITY {
+                let cos_theta = 1.0 - rng.gen::<f32>() * (1.0 - sun.cos_theta_max);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * rng.gen::<f32>();
+                let (u, v) = orthonormal_basis(sun.direction);
+                let direction =
+                    sin_theta * (phi.cos() * u + phi.sin() * v) + cos_theta * sun.direction;
+                return Some((direction, self.pdf(direction)));
+            }
+        }
+
+        let Some(distribution) = &self.distribution else {
+            let direction = uniform_sphere(rng.gen(), rng.gen());
+            return Some((direction, 1.0 / (4.0 * PI)));
+        };
+
+        let (u, v, uv_pdf) = distribution.sample(rng.gen(), rng.gen());
+        let sin_theta = (v * PI).sin();
+        if uv_pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+
+        let direction = rotate_y(uv_to_direction(u, v), self.rotation);
+        return Some((direction, self.pdf(direction)));
+    }
+
+    // Solid angle density with which `sample` picks the unit `direction`.
+    pub fn pdf(&self, direction: Vector3<f32>) -> f32 {
+        let background_pdf = match &self.distribution {
+            None => 1.0 / (4.0 * PI),
+            Some(distribution) => {
+                let (u, v) = direction_to_uv(rotate_y(direction, -self.rotation));
+                let sin_theta = (v * PI).sin();
+                if sin_theta <= 0.0 {
+                    0.0
+                } else {
+                    distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+                }
+            }
+        };
+
+        let Some(sun) = self.sun else {
+            return background_pdf;
+        };
+        let sun_pdf = if sun.contains(direction) {
+            sun.pdf()
+        } else {
+            0.0
+        };
+        return SUN_SAMPLING_PROBABILITY * sun_pdf
+            + (1.0 - SUN_SAMPLING_PROBABILITY) * background_pdf;
+    }
+}
+
+/**
+ * A high dynamic range image, with linear colors.
+ */
+#[derive(Debug)]
+pub struct EnvironmentImage {
+    width: usize,
+    height: usize,
+    // Row by row, starting at the top of the image.
+    pixels: Vec<Vector3<f32>>,
+}
+
+impl EnvironmentImage {
+    // Radiance HDR (.hdr) or portable float map (.pfm) images.
+    pub fn load(path: &Path) -> Result<EnvironmentImage, String> {
+        let is_pfm = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("pfm"));
+        if is_pfm {
+            let bytes = fs::read(path)
+                .map_err(|e| format!("There was an error opening '{}': {}", path.display(), e))?;
+            return parse_pfm(&bytes)
+                .map_err(|e| format!("Invalid PFM file '{}': {}", path.display(), e));
+        }
+
+        // Going through `image::open` would tone map the image down to 8 bits.
+        let file = File::open(path)
+            .map_err(|e| format!("There was an error opening '{}': {}", path.display(), e))?;
+        let decoder = HdrDecoder::new(BufReader::new(file))
+            .map_err(|e| format!("There was an error loading '{}': {}", path.display(), e))?;
+        let metadata = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()
+            .map_err(|e| format!("There was an error loading '{}': {}", path.display(), e))?
+            .iter()
+            .map(|pixel| Vector3::new(pixel[0], pixel[1], pixel[2]))
+            .collect();
+
+        return Ok(EnvironmentImage {
+            width: metadata.width as usize,
+            height: metadata.height as usize,
+            pixels,
+        });
+    }
+
+    // The texel containing (u, v), v goes from the top of the image to the bottom.
+    // No filtering, so that radiance is constant wherever the sampling density is.
+    #[inline]
+    fn texel(&self, u: f32, v: f32) -> Vector3<f32> {
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        return self.pixels[y * self.width + x];
+    }
+
+    // Texels are picked by how bright they are, weighted by the solid angle they cover,
+    // which shrinks towards the poles.
+    fn distribution(&self) -> Distribution2D {
+        return Distribution2D::tabulate(self.width, self.height, |u, v| {
+            return luminance(self.texel(u, v));
+        });
+    }
+}
+
+// PFM: a "PF" (color) or "Pf" (grayscale) line, the size, and a scale whose sign gives the byte
+// order, followed by binary floats with the rows going from the bottom of the image to the top.
+fn parse_pfm(bytes: &[u8]) -> Result<EnvironmentImage, String> {
+    let mut header = Vec::new();
+    let mut position = 0;
+    while header.len() < 4 {
+        let start = position;
+        while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if start == position {
+            return Err("The header is incomplete".to_string());
+        }
+        header.push(String::from_utf8_lossy(&bytes[start..position]).to_string());
+        // A single whitespace character separates the header from the data.
+        position += 1;
+        if header.len() < 4 {
+            while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+                position += 1;
+            }
+        }
+    }
+
+    let channels = match header[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        other => return Err(format!("Unknown format '{}', expected PF or Pf", other)),
+    };
+    let parse_size = |word: &str| {
+        word.parse::<usize>()
+            .ok()
+            .filter(|size| *size > 0)
+            .ok_or(format!("Invalid image size '{}'", word))
+    };
+    let width = parse_size(&header[1])?;
+    let height = parse_size(&header[2])?;
+    let scale = header[3]
+        .parse::<f32>()
+        .map_err(|_| format!("Invalid scale '{}'", header[3]))?;
+    let little_endian = scale < 0.0;
+
+    let data = bytes.get(position..).unwrap_or(&[]);
+    let count = width * height * channels;
+    if data.len() < count * 4 {
+        return Err(format!(
+            "Expected {} floats, found {} bytes of them",
+            count,
+            data.len()
+        ));
+    }
+
+    let values: Vec<f32> = data[..count * 4]
+        .chunks_exact(4)
+        .map(|chunk| {
+            let bytes = [chunk[0], chunk[1], chunk[2], chunk[3]];
+            if little_endian {
+                return f32::from_le_bytes(bytes);
+            }
+            return f32::from_be_bytes(bytes);
+        })
+        .collect();
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let index = (y * width + x) * channels;
+            pixels.push(match channels {
+                3 => Vector3::new(values[index], values[index + 1], values[index + 2]),
+                _ => Vector3::new(values[index], values[index], values[index]),
+            });
+        }
+    }
+
+    return Ok(EnvironmentImage {
+        width,
+        height,
+        pixels,
+    });
+}
+
+/**
+ * Picks positions on [0, 1) proportionally to a piecewise constant function.
+ */
+#[derive(Debug)]
+struct Distribution1D {
+    weights: Vec<f32>,
+    // Running sum of the weights, normalized to end at 1.
+    cdf: Vec<f32>,
+    total: f32,
+}
+
+impl Distribution1D {
+    fn new(weights: Vec<f32>) -> Distribution1D {
+        let mut cdf = Vec::with_capacity(weights.len());
+        let mut total = 0.0;
+        for weight in weights.iter() {
+            total += weight;
+            cdf.push(total);
+        }
+        if total > 0.0 {
+            for value in cdf.iter_mut() {
+                *value /= total;
+            }
+        } else {
+            // Nothing stands out, fall back to picking uniformly.
+            let count = cdf.len() as f32;
+            for (index, value) in cdf.iter_mut().enumerate() {
+                *value = (index + 1) as f32 / count;
+            }
+        }
+
+        return Distribution1D {
+            weights,
+            cdf,
+            total,
+        };
+    }
+
+    // Returns the position, the index of the piece it lies in and its density.
+    fn sample(&self, random: f32) -> (f32, usize, f32) {
+        let index = self
+            .cdf
+            .partition_point(|value| *value <= random)
+            .min(self.cdf.len() - 1);
+        let start = if index == 0 { 0.0 } else { self.cdf[index - 1] };
+        let width = self.cdf[index] - start;
+        let offset = if width > 0.0 {
+            (random - start) / width
+        } else {
+            0.5
+        };
+
+        let count = self.cdf.len() as f32;
+        return ((index as f32 + offset) / count, index, width * count);
+    }
+
+    fn pdf(&self, index: usize) -> f32 {
+        let count = self.weights.len() as f32;
+        if self.total <= 0.0 {
+            return 1.0;
+        }
+        return self.weights[index] * count / self.total;
+    }
+}
+
+/**
+ * Picks (u, v) on the unit square proportionally to a piecewise constant function,
+ * first the row from how much it weighs in total, then the column within it.
+ */
+#[derive(Debug)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // `weights` are given row by row.
+    fn new(weights: Vec<Vec<f32>>) -> Distribution2D {
+        let rows: Vec<Distribution1D> = weights.into_iter().map(Distribution1D::new).collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.total).collect());
+        return Distribution2D { rows, marginal };
+    }
+
+    // Weights every texel of a lat-long map by `brightness` at its center, and the solid angle
+    // it covers, which shrinks towards the poles.
+    fn tabulate<F>(width: usize, height: usize, brightness: F) -> Distribution2D
+    where
+        F: Fn(f32, f32) -> f32,
+    {
+        let weights = (0..height)
+            .map(|y| {
+                let v = (y as f32 + 0.5) / height as f32;
+                let sin_theta = (v * PI).sin();
+                return (0..width)
+                    .map(|x| brightness((x as f32 + 0.5) / width as f32, v).max(0.0) * sin_theta)
+                    .collect();
+            })
+            .collect();
+        return Distribution2D::new(weights);
+    }
+
+    // Returns u, v and the density over the unit square.
+    fn sample(&self, random_u: f32, random_v: f32) -> (f32, f32, f32) {
+        let (v, row, row_pdf) = self.marginal.sample(random_v);
+        let (u, _, column_pdf) = self.rows[row].sample(random_u);
+        return (u, v, row_pdf * column_pdf);
+    }
+
+    fn pdf(&self, u: f32, v: f32) -> f32 {
+        let row = ((v * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        let columns = self.rows[row].weights.len();
+        let column = ((u * columns as f32) as usize).min(columns - 1);
+        return self.marginal.pdf(row) * self.rows[row].pdf(column);
+    }
+}
+
+// u goes around the horizon, starting and ending behind -Z, v from straight up to straight down.
+#[inline]
+fn direction_to_uv(direction: Vector3<f32>) -> (f32, f32) {
+    let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+    let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+    return (u.clamp(0.0, 1.0), v);
+}
+
+#[inline]
+fn uv_to_direction(u: f32, v: f32) -> Vector3<f32> {
+    let phi = (u - 0.5) * 2.0 * PI;
+    let theta = v * PI;
+    return Vector3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    );
+}
+
+#[inline]
+fn luminance(color: Vector3<f32>) -> f32 {
+    return 0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z;
+}
+
+#[inline]
+fn rotate_y(direction: Vector3<f32>, angle: f32) -> Vector3<f32> {
+    let (sin, cos) = angle.sin_cos();
+    return Vector3::new(
+        cos * direction.x + sin * direction.z,
+        direction.y,
+        -sin * direction.x + cos * direction.z,
+    );
+}
+
+#[inline]
+fn uniform_sphere(u: f32, v: f32) -> Vector3<f32> {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    return Vector3::new(r * phi.cos(), r * phi.sin(), z).normalize();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampled_directions_have_the_reported_density() {
+        // A dim sky with a small, bright sun.
+        let (width, height) = (16, 8);
+        let mut pixels = vec![Vector3::new(0.1, 0.1, 0.1); width * height];
+        pixels[2 * width + 5] = Vector3::new(50.0, 40.0, 30.0);
+        let image = EnvironmentImage {
+            width,
+            height,
+            pixels,
+        };
+        let environment = Environment::new(Background::Image(Arc::new(image)), 1.0, 30.0);
+
+        let mut hits_on_sun = 0;
+        let mut mismatches = 0;
+        for _ in 0..2000 {
+            let (direction, pdf) = environment.sample().unwrap();
+            assert!((direction.magnitude() - 1.0).abs() < 1e-4);
+            // Directions right on the edge of a texel may round into its neighbour.
+            let expected = environment.pdf(direction);
+            if (pdf - expected).abs() > 1e-2 * expected {
+                mismatches += 1;
+            }
+            if environment.radiance(direction).x > 1.0 {
+                hits_on_sun += 1;
+            }
+        }
+        assert!(mismatches < 10);
+        // Most samples should go towards the sun.
+        assert!(hits_on_sun > 1000);
+    }
+
+    #[test]
+    fn parses_pfm_bottom_to_top() {
+        let mut bytes = b"Pf\n1 2\n-1.0\n".to_vec();
+        bytes.extend_from_slice(&1.0_f32.to_le_bytes());
+        bytes.extend_from_slice(&2.0_f32.to_le_bytes());
+
+        let image = parse_pfm(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (1, 2));
+        assert_eq!(image.pixels[0], Vector3::new(2.0, 2.0, 2.0));
+        assert_eq!(image.pixels[1], Vector3::new(1.0, 1.0, 1.0));
+    }
+}
@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector3};
+use rand::Rng;
+
+use crate::object::area_light::AreaLightShape;
+use crate::object::object::{Hit, PrimitiveId};
+use crate::utils::vector_utils::{orthonormal_basis, random_unit_vector};
+
+use super::environment::Environment;
+
+/**
+ * Emitters which can be sampled directly with shadow rays.
+ * Lights with a primitive only describe the shape of an emitter, the emitted radiance
+ * comes from the material of whatever a shadow ray ends up hitting.
+ * The others aren't part of the scene's geometry, shadow rays towards them must not hit anything.
+ */
+pub enum Light {
+    Sphere {
+        center: Vector3<f32>,
+        radius: f32,
+        primitive: PrimitiveId,
+    },
+    // In world space.
+    Triangle {
+        vertices: [Vector3<f32>; 3],
+        primitive: PrimitiveId,
+    },
+    // One-sided, see `AreaLightShape`.
+    Area {
+        shape: AreaLightShape,
+        primitive: PrimitiveId,
+    },
+    Environment(Environment),
+    // Shines `intensity` (color times power per solid angle) evenly in all directions.
+    Point {
+        position: Vector3<f32>,
+        intensity: Vector3<f32>,
+    },
+    // A point light restricted to a cone around the unit `direction`, fading out
+    // between the two angles.
+    Spot {
+        position: Vector3<f32>,
+        direction: Vector3<f32>,
+        intensity: Vector3<f32>,
+        cos_falloff_start: f32,
+        cos_cutoff: f32,
+    },
+    // Light from infinitely far away, travelling along the unit `direction`, with
+    // `irradiance` on surfaces facing it.
+    Directional {
+        direction: Vector3<f32>,
+        irradiance: Vector3<f32>,
+    },
+}
+
+pub struct LightSample {
+    // Unit vector from the shaded point towards the sampled point.
+    pub direction: Vector3<f32>,
+    pub distance: f32,
+    // Solid angle density, as seen from the shaded point.
+    // Lights which can only be reached by shadow rays have a delta distribution, the pdf is then
+    // only the probability of having picked the light.
+    pub pdf: f32,
+    pub is_delta: bool,
+}
+
+impl Light {
+    // The primitive of the scene the light is, if any.
+    pub fn primitive(&self) -> Option<PrimitiveId> {
+        match self {
+            Light::Sphere { primitive, .. } => Some(*primitive),
+            Light::Triangle { primitive, .. } => Some(*primitive),
+            Light::Area { primitive, .. } => Some(*primitive),
+            _ => None,
+        }
+    }
+
+    // Samples a point on the light, as seen from `from`.
+    pub fn sample(&self, from: Vector3<f32>) -> Option<LightSample> {
+        match self {
+            Light::Sphere { center, radius, .. } => sample_sphere(*center, *radius, from),
+            Light::Triangle { vertices, .. } => sample_triangle(vertices, from),
+            Light::Area { shape, .. } => sample_area(shape, from),
+            Light::Environment(environment) => {
+                let (direction, pdf) = environment.sample()?;
+                Some(LightSample {
+                    direction,
+                    distance: f32::INFINITY,
+                    pdf,
+                    is_delta: false,
+                })
+            }
+            Light::Point { position, .. } | Light::Spot { position, .. } => {
+                let mut sample = to_sample(from, *position, 1.0)?;
+                sample.is_delta = true;
+                Some(sample)
+            }
+            Light::Directional { direction, .. } => Some(LightSample {
+                direction: -*direction,
+                distance: f32::INFINITY,
+                pdf: 1.0,
+                is_delta: true,
+            }),
+        }
+    }
+
+    // Light arriving at a point `distance` away from the light, along the unit `direction` the
+    // light travels in. Only for lights which aren't geometry, in the directions they were sampled.
+    pub fn emitted(&self, direction: Vector3<f32>, distance: f32) -> Option<Vector3<f32>> {
+        match self {
+            Light::Environment(environment) => Some(environment.radiance(-direction)),
+            Light::Point { intensity, .. } => Some(intensity / (distance * distance)),
+            Light::Spot {
+                direction: spot_direction,
+                intensity,
+                cos_falloff_start,
+                cos_cutoff,
+                ..
+            } => {
+                let cos_theta = direction.dot(*spot_direction);
+                let falloff = smooth_step(*cos_cutoff, *cos_falloff_start, cos_theta);
+                Some(intensity * (falloff / (distance * distance)))
+            }
+            Light::Directional { irradiance, .. } => Some(*irradiance),
+            _ => None,
+        }
+    }
+
+    // Solid angle density of `sample` picking the point `hit` as seen from `from`.
+    pub fn pdf(&self, from: Vector3<f32>, hit: &Hit) -> f32 {
+        match self {
+            Light::Sphere { center, radius, .. } => {
+                let distance_2 = (center - from).magnitude2();
+                if distance_2 <= radius * radius {
+                    return area_to_solid_angle_pdf(
+                        1.0 / (4.0 * PI * radius * radius),
+                        from,
+                        hit.point,
+                        hit.geometric_normal,
+                    );
+                }
+                return 1.0 / cone_solid_angle(*radius, distance_2);
+            }
+            Light::Triangle { vertices, .. } => area_to_solid_angle_pdf(
+                1.0 / triangle_area(vertices),
+                from,
+                hit.point,
+                hit.geometric_normal,
+            ),
+            Light::Area { shape, .. } => {
+                // Nothing is sampled on the dark side.
+                if shape.normal().dot(from - hit.point) <= 0.0 {
+                    return 0.0;
+                }
+                area_to_solid_angle_pdf(1.0 / area_of(shape), from, hit.point, hit.geometric_normal)
+            }
+            // The environment is never hit, see `LightList::environment_pdf`,
+            // and delta lights can't be found by chance.
+            _ => 0.0,
+        }
+    }
+}
+
+/**
+ * All the lights of a scene. Lights are picked uniformly.
+ */
+pub struct LightList {
+    lights: Vec<Light>,
+    // Which light (if any) a primitive is.
+    by_primitive: HashMap<PrimitiveId, usize>,
+    // Which light is the environment, if the scene has one.
+    environment: Option<usize>,
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Light>) -> LightList {
+        let by_primitive = lights
+            .iter()
+            .enumerate()
+            .filter_map(|(index, light)| Some((light.primitive()?, index)))
+            .collect();
+        let environment = lights
+            .iter()
+            .position(|light| matches!(light, Light::Environment(_)));
+
+        return LightList {
+            lights,
+            by_primitive,
+            environment,
+        };
+    }
+
+    // Adds a light which isn't part of the scene's geometry.
+    pub fn add(&mut self, light: Light) {
+        if let Some(primitive) = light.primitive() {
+            self.by_primitive.insert(primitive, self.lights.len());
+        }
+        if let Light::Environment(_) = light {
+            self.environment = Some(self.lights.len());
+        }
+        self.lights.push(light);
+    }
+
+    // Replaces the environment of the scene.
+    pub fn set_environment(&mut self, environment: Environment) {
+        match self.environment {
+            Some(index) => self.lights[index] = Light::Environment(environment),
+            None => self.add(Light::Environment(environment)),
+        }
+    }
+
+    pub fn environment(&self) -> Option<&Environment> {
+        return match self.environment.map(|index| &self.lights[index]) {
+            Some(Light::Environment(environment)) => Some(environment),
+            _ => None,
+        };
+    }
+
+    // Density with which `sample` would have picked the unit `direction` towards the environment.
+    pub fn environment_pdf(&self, direction: Vector3<f32>) -> f32 {
+        return match self.environment() {
+            Some(environment) => environment.pdf(direction) / self.lights.len() as f32,
+            None => 0.0,
+        };
+    }
+
+    // Picks a light and samples it. The returned pdf includes picking the light.
+    pub fn sample(&self, from: Vector3<f32>) -> Option<(&Light, LightSample)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        let light = &self.lights[rng.gen_range(0..self.lights.len())];
+        let mut sample = light.sample(from)?;
+        sample.pdf /= self.lights.len() as f32;
+
+        return Some((light, sample));
+    }
+
+    // Density with which `sample` would have picked the emitter `hit`, as seen from `from`.
+    // Zero for anything which isn't one of the lights.
+    pub fn pdf(&self, from: Vector3<f32>, hit: &Hit) -> f32 {
+        return match self.by_primitive.get(&hit.primitive) {
+            Some(index) => self.lights[*index].pdf(from, hit) / self.lights.len() as f32,
+            None => 0.0,
+        };
+    }
+}
+
+// Samples the cone of directions under which the sphere is seen, or its whole surface
+// if the shaded point happens to be inside of it.
+fn sample_sphere(center: Vector3<f32>, radius: f32, from: Vector3<f32>) -> Option<LightSample> {
+    let to_center = center - from;
+    let distance_2 = to_center.magnitude2();
+
+    if distance_2 <= radius * radius {
+        let point = center + radius * random_unit_vector();
+        let normal = (point - center) / radius;
+        let pdf = area_to_solid_angle_pdf(1.0 / (4.0 * PI * radius * radius), from, point, normal);
+        return to_sample(from, point, pdf);
+    }
+
+    let mut rng = rand::thread_rng();
+    let distance = distance_2.sqrt();
+    let sin_theta_max_2 = radius * radius / distance_2;
+    let cos_theta_max = f32::max(0.0, 1.0 - sin_theta_max_2).sqrt();
+
+    let cos_theta = 1.0 - rng.gen::<f32>() * (1.0 - cos_theta_max);
+    let sin_theta_2 = f32::max(0.0, 1.0 - cos_theta * cos_theta);
+    let phi = 2.0 * PI * rng.gen::<f32>();
+
+    let w = to_center / distance;
+    let (u, v) = orthonormal_basis(w);
+    let direction = sin_theta_2.sqrt() * (phi.cos() * u + phi.sin() * v) + cos_theta * w;
+
+    // Closest intersection of the sampled direction with the sphere.
+    let hit_distance =
+        distance * cos_theta - f32::max(0.0, radius * radius - distance_2 * sin_theta_2).sqrt();
+
+    return Some(LightSample {
+        direction,
+        distance: hit_distance,
+        pdf: 1.0 / cone_solid_angle(radius, distance_2),
+        is_delta: false,
+    });
+}
+
+fn sample_triangle(vertices: &[Vector3<f32>; 3], from: Vector3<f32>) -> Option<LightSample> {
+    let mut rng = rand::thread_rng();
+    let [a, b, c] = *vertices;
+
+    // Uniform barycentrics.
+    let su0 = rng.gen::<f32>().sqrt();
+    let b0 = 1.0 - su0;
+    let b1 = rng.gen::<f32>() * su0;
+    let point = b0 * a + b1 * b + (1.0 - b0 - b1) * c;
+
+    let normal = (b - a).cross(c - a).normalize();
+    let pdf = area_to_solid_angle_pdf(1.0 / triangle_area(vertices), from, point, normal);
+    return to_sample(from, point, pdf);
+}
+
+// Uniformly samples the area of the light, as long as `from` is on its bright side.
+fn sample_area(shape: &AreaLightShape, from: Vector3<f32>) -> Option<LightSample> {
+    let mut rng = rand::thread_rng();
+    let normal = shape.normal();
+
+    let point = match *shape {
+        AreaLightShape::Quad {
+            corner,
+            edge_u,
+            edge_v,
+        } => corner + rng.gen::<f32>() * edge_u + rng.gen::<f32>() * edge_v,
+        AreaLightShape::Disk { center, radius, .. } => {
+            let r = radius * rng.gen::<f32>().sqrt();
+            let phi = 2.0 * PI * rng.gen::<f32>();
+            let (u, v) = orthonormal_basis(normal);
+            center + r * (phi.cos() * u + phi.sin() * v)
+        }
+    };
+    if normal.dot(from - point) <= 0.0 {
+        return None;
+    }
+
+    let pdf = area_to_solid_angle_pdf(1.0 / area_of(shape), from, point, normal);
+    return to_sample(from, point, pdf);
+}
+
+fn to_sample(from: Vector3<f32>, point: Vector3<f32>, pdf: f32) -> Option<LightSample> {
+    let to_point = point - from;
+    let distance = to_point.magnitude();
+    if pdf <= 0.0 || !pdf.is_finite() || distance <= 0.0 {
+        return None;
+    }
+
+    return Some(LightSample {
+        direction: to_point / distance,
+        distance,
+        pdf,
+        is_delta: false,
+    });
+}
+
+#[inline]
+fn cone_solid_angle(radius: f32, distance_2: f32) -> f32 {
+    let cos_theta_max = f32::max(0.0, 1.0 - radius * radius / distance_2).sqrt();
+    return 2.0 * PI * (1.0 - cos_theta_max);
+}
+
+#[inline]
+fn area_of(shape: &AreaLightShape) -> f32 {
+    return match shape {
+        AreaLightShape::Quad { edge_u, edge_v, .. } => edge_u.cross(*edge_v).magnitude(),
+        AreaLightShape::Disk { radius, .. } => PI * radius * radius,
+    };
+}
+
+// Hermite interpolation from 0 at `edge_0` to 1 at `edge_1`.
+#[inline]
+fn smooth_step(edge_0: f32, edge_1: f32, x: f32) -> f32 {
+    if edge_0 >= edge_1 {
+        return if x >= edge_1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    return t * t * (3.0 - 2.0 * t);
+}
+
+#[inline]
+fn triangle_area(vertices: &[Vector3<f32>; 3]) -> f32 {
+    let [a, b, c] = *vertices;
+    return 0.5 * (b - a).cross(c - a).magnitude();
+}
+
+// Converts a density per unit area on the light to a density per unit solid angle at `from`.
+#[inline]
+fn area_to_solid_angle_pdf(
+    area_pdf: f32,
+    from: Vector3<f32>,
+    point: Vector3<f32>,
+    normal: Vector3<f32>,
+) -> f32 {
+    let to_point = point - from;
+    let distance_2 = to_point.magnitude2();
+    let cos_light = normal.dot(to_point).abs() / distance_2.sqrt();
+    if cos_light <= 0.0 {
+        return 0.0;
+    }
+    return area_pdf * distance_2 / cos_light;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::material::Material;
+    use crate::object::area_light::AreaLight;
+    use crate::object::mesh::Mesh;
+    use crate::object::object::Hitable;
+    use crate::object::sphere::Sphere;
+    use crate::utils::vector_utils::{Interval, Ray};
+
+    // Samples the light from `from`, finds the sampled point by intersecting the emitter's geometry
+    // and checks that `pdf` gives the same density for it.
+    fn check_pdfs(light: &Light, emitter: &dyn Hitable, from: Vector3<f32>) {
+        let bounds = Interval::new(1e-4, f32::MAX);
+        let mut checked = 0;
+        for _ in 0..1000 {
+            let Some(sample) = light.sample(from) else {
+                continue;
+            };
+            let ray = Ray {
+                origin: from,
+                direction: sample.direction,
+            };
+            let hit = emitter.intersect(&ray, bounds).unwrap();
+            assert!((hit.point_at_intersection - sample.distance).abs() < 1e-3 * sample.distance);
+
+            let pdf = light.pdf(from, &hit);
+            assert!(
+                (pdf - sample.pdf).abs() <= 1e-3 * sample.pdf,
+                "Sampled with a pdf of {}, but pdf() gives {}",
+                sample.pdf,
+                pdf
+            );
+            checked += 1;
+        }
+        assert!(checked > 100);
+    }
+
+    fn white() -> Material {
+        return Material::Diffuse(Vector3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn sphere_samples_agree_with_pdf() {
+        let (center, radius) = (Vector3::new(0.0, 2.0, 0.0), 1.5);
+        let sphere = Sphere::new(center, radius, white());
+        let light = Light::Sphere {
+            center,
+            radius,
+            primitive: PrimitiveId::default(),
+        };
+
+        check_pdfs(&light, &sphere, Vector3::new(0.3, -3.0, 1.0));
+        check_pdfs(&light, &sphere, Vector3::new(0.3, 2.5, -0.4));
+    }
+
+    #[test]
+    fn triangle_samples_agree_with_pdf() {
+        let obj = wavefront_obj::obj::parse("v -1 3 -1\nv 2 3 0\nv 0 4 2\nf 1 2 3\n").unwrap();
+        let mesh = Mesh::new(obj).unwrap();
+        let light = Light::Triangle {
+            vertices: mesh.triangle_vertices(0),
+            primitive: PrimitiveId::default(),
+        };
+
+        check_pdfs(&light, &mesh, Vector3::new(0.5, 0.0, 0.2));
+        check_pdfs(&light, &mesh, Vector3::new(-2.0, 6.0, 1.0));
+    }
+
+    #[test]
+    fn area_samples_agree_with_pdf() {
+        let shapes = [
+            AreaLightShape::Quad {
+                corner: Vector3::new(-1.0, 3.0, -1.0),
+                edge_u: Vector3::new(2.0, 0.0, 0.5),
+                edge_v: Vector3::new(0.0, 0.0, 2.0),
+            },
+            AreaLightShape::Disk {
+                center: Vector3::new(0.0, 3.0, 0.0),
+                normal: Vector3::new(0.3, -1.0, 0.1).normalize(),
+                radius: 1.2,
+            },
+        ];
+
+        for shape in shapes {
+            let emitter = AreaLight::new(shape, Vector3::new(1.0, 1.0, 1.0), 1.0);
+            let light = Light::Area {
+                shape,
+                primitive: PrimitiveId::default(),
+            };
+            check_pdfs(&light, &emitter, Vector3::new(0.5, 0.0, 0.2));
+
+            // Nothing is sampled behind the light.
+            assert!(light.sample(Vector3::new(0.0, 6.0, 0.0)).is_none());
+        }
+    }
+}
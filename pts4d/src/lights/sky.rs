@@ -0,0 +1,179 @@
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector3};
+
+// Apparent angular radius of the sun, in radians.
+const SUN_ANGULAR_RADIUS: f32 = 0.00465;
+// Irradiance of the sun at the top of the atmosphere, relative to the brightness of the sky.
+const SUN_ILLUMINANCE: f32 = 2.5;
+// Sky luminances come in kcd/m², this keeps a white surface in daylight from burning out.
+const SKY_SCALE: f32 = 0.04;
+
+/**
+ * The daylight sky of Preetham et al. 1999, "A Practical Analytic Model for Daylight".
+ * Luminance and chromaticity follow the Perez formula, fitted to how hazy the air is.
+ */
+#[derive(Debug)]
+pub struct Sky {
+    // Unit vector towards the sun.
+    pub sun_direction: Vector3<f32>,
+    // Perez coefficients A to E, of the luminance Y and the chromaticities x and y.
+    perez: [[f32; 5]; 3],
+    // Y, x and y straight up.
+    zenith: [f32; 3],
+    // Perez formula towards the zenith, for normalizing.
+    zenith_perez: [f32; 3],
+    // Radiance of the sun disk, after making it through the atmosphere.
+    pub sun_radiance: Vector3<f32>,
+    pub sun_cos_theta_max: f32,
+}
+
+impl Sky {
+    // `turbidity` goes from 2 for a very clear sky to 10 for a hazy one. Angles are in degrees,
+    // the azimuth turns from -Z towards +X.
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32, sun_size: f32) -> Sky {
+        let t = turbidity.clamp(1.7, 10.0);
+        let elevation = elevation.to_radians();
+        let azimuth = azimuth.to_radians();
+        let sun_direction = Vector3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        // The fits only hold with the sun above the horizon.
+        let theta_s = (PI / 2.0 - elevation).clamp(0.0, PI / 2.0 - 0.01);
+        let (theta_2, theta_3) = (theta_s * theta_s, theta_s * theta_s * theta_s);
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t * t * (0.00166 * theta_3 - 0.00375 * theta_2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta_3 + 0.06377 * theta_2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta_3 - 0.21196 * theta_2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta_3 - 0.00610 * theta_2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta_3 + 0.08970 * theta_2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta_3 - 0.26756 * theta_2 + 0.06670 * theta_s + 0.26688);
+
+        let zenith_perez = [0, 1, 2].map(|channel| perez_function(&perez[channel], 0.0, theta_s));
+
+        let sun_cos_theta_max = (SUN_ANGULAR_RADIUS * sun_size.max(0.01)).cos();
+        let sun_solid_angle = 2.0 * PI * (1.0 - sun_cos_theta_max);
+        let sun_radiance = if elevation > 0.0 {
+            sun_transmittance(theta_s, t) * (SUN_ILLUMINANCE / sun_solid_angle)
+        } else {
+            Vector3::new(0.0, 0.0, 0.0)
+        };
+
+        return Sky {
+            sun_direction,
+            perez,
+            zenith: [zenith_luminance.max(0.0), zenith_x, zenith_y],
+            zenith_perez,
+            sun_radiance,
+            sun_cos_theta_max,
+        };
+    }
+
+    // Radiance of the sky, without the sun, arriving from the unit `direction`.
+    // Below the horizon the sky keeps the color it has at the horizon.
+    pub fn radiance(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let cos_theta = direction.y.max(0.01);
+        let theta = cos_theta.acos();
+        let gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let [luminance, x, y] = [0, 1, 2].map(|channel| {
+            self.zenith[channel] * perez_function(&self.perez[channel], theta, gamma)
+                / self.zenith_perez[channel]
+        });
+        return SKY_SCALE * xyy_to_rgb(x, y, luminance);
+    }
+}
+
+// Relative distribution of light over the sky, for a direction at `theta` from the zenith
+// and `gamma` from the sun.
+#[inline]
+fn perez_function(coefficients: &[f32; 5], theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    let cos_gamma = gamma.cos();
+    return (1.0 + a * (b / theta.cos().max(0.01)).exp())
+        * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma);
+}
+
+// How much of the sunlight scatters out on its way down, by Rayleigh (molecules) and
+// Mie (haze) scattering, at wavelengths standing for red, green and blue.
+fn sun_transmittance(theta_s: f32, turbidity: f32) -> Vector3<f32> {
+    // Relative optical air mass (Kasten and Young).
+    let theta_degrees = theta_s.to_degrees();
+    let air_mass = 1.0 / (theta_s.cos() + 0.50572 * (96.07995 - theta_degrees).powf(-1.6364));
+    let angstrom_beta = 0.04608 * turbidity - 0.04586;
+
+    let transmittance = |wavelength_um: f32| {
+        let rayleigh = 0.008735 * wavelength_um.powf(-4.08);
+        let mie = angstrom_beta * wavelength_um.powf(-1.3);
+        return (-air_mass * (rayleigh + mie)).exp();
+    };
+    return Vector3::new(
+        transmittance(0.65),
+        transmittance(0.57),
+        transmittance(0.475),
+    );
+}
+
+// CIE xyY to linear sRGB.
+#[inline]
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vector3<f32> {
+    if y <= 0.0 {
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+    let big_x = x * luminance / y;
+    let big_z = (1.0 - x - y) * luminance / y;
+    let rgb = Vector3::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    );
+    return Vector3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sun_reddens_towards_the_horizon() {
+        let noon = Sky::new(70.0, 0.0, 3.0, 1.0);
+        let sunset = Sky::new(3.0, 0.0, 3.0, 1.0);
+        let redness = |sky: &Sky| sky.sun_radiance.x / sky.sun_radiance.z;
+        assert!(redness(&sunset) > 2.0 * redness(&noon));
+        assert!(sunset.sun_radiance.y < noon.sun_radiance.y);
+        assert_eq!(Sky::new(-5.0, 0.0, 3.0, 1.0).sun_radiance.y, 0.0);
+
+        // The sky is brighter around the sun than opposite of it.
+        let towards = noon.radiance(Vector3::new(0.0, 0.5, -0.866));
+        let away = noon.radiance(Vector3::new(0.0, 0.5, 0.866));
+        assert!(towards.y > away.y);
+    }
+}
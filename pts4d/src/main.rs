@@ -1,19 +1,50 @@
+// Functions end with an explicit return, and modules are named after what they hold.
+#![allow(
+    clippy::needless_return,
+    clippy::module_inception,
+    clippy::upper_case_acronyms
+)]
+
 extern crate sdl2;
 
 mod object {
+    pub mod area_light;
+    pub mod instance;
     pub mod mesh;
     pub mod object;
     pub mod sphere;
+    pub mod triangle;
+    pub mod volume;
 }
 
 mod materials {
+    pub mod bsdf;
+    pub mod ior;
     pub mod material;
+    pub mod medium;
+    pub mod microfacet;
+    pub mod mtl;
+    pub mod normal_map;
+    pub mod principled;
+    pub mod procedural;
+    pub mod subsurface;
+    pub mod texture;
+    pub mod voxel_grid;
+}
+
+mod lights {
+    pub mod environment;
+    pub mod light;
+    pub mod sky;
 }
 
 mod scene {
+    pub mod aperture;
     pub mod camera;
     pub mod scene;
+    pub mod scene_loader;
     pub mod screen;
+    pub mod settings;
 }
 
 mod accel {
@@ -22,16 +53,22 @@ mod accel {
 }
 
 mod utils {
+    pub mod gltf_loader;
+    pub mod image_output;
     pub mod rendering_utils;
     pub mod scene_builders;
+    pub mod spectrum;
     pub mod vector_utils;
 }
 
+mod cli;
+mod headless;
 mod renderer;
 
-use crate::renderer::SAMPLES_PER_PIXEL;
+use crate::cli::{CliOptions, USAGE};
 use crate::scene::scene::Scene;
-use crate::scene::screen::{HEIGHT, WIDTH};
+use crate::scene::scene_loader;
+use crate::scene::settings::RenderSettings;
 use crate::utils::rendering_utils::{add_screens, handle_input, initialize_screen, present_screen};
 use crate::utils::scene_builders;
 
@@ -39,16 +76,65 @@ use renderer::render_pass;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
+use std::env;
+use std::process::ExitCode;
 use std::time::Instant;
 
-pub fn main() -> Result<(), String> {
+pub fn main() -> ExitCode {
     println!("Welcome to PTS4D!");
 
+    // Failures are reported and turned into a non-zero exit code, rather than panicking.
+    match cli::parse_args(env::args().skip(1)).and_then(run) {
+        Ok(()) => return ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error);
+            return ExitCode::FAILURE;
+        }
+    }
+}
+
+fn run(options: CliOptions) -> Result<(), String> {
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    // Scene files carry their own render settings, which the command line can still override.
+    let (scene, settings) = if scene_loader::is_scene_file(&options.scene) {
+        let description = scene_loader::load_scene_file(&options.scene)?;
+        let settings = options.render_settings(description.settings);
+        (description.build(&settings)?, settings)
+    } else {
+        let settings = options.render_settings(RenderSettings::default());
+        let scene = scene_builders::generate_named_scene(
+            &options.scene,
+            options.obj_path.as_deref(),
+            &settings,
+        )?;
+        (scene, settings)
+    };
+
+    if options.headless {
+        return headless::render_to_file(&scene, &settings, &options);
+    }
+
+    if options.samples_per_pixel.is_some() || options.time_budget.is_some() {
+        return Err(format!(
+            "--spp and --time only apply with --headless\n\n{}",
+            USAGE
+        ));
+    }
+
+    return run_interactive(scene, &settings);
+}
+
+// scene is mutable, so the camera can be modified during runtime.
+fn run_interactive(mut scene: Scene, settings: &RenderSettings) -> Result<(), String> {
     // SDL Boilerplate
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
     let window = video_subsystem
-        .window("PTS4D", WIDTH as u32, HEIGHT as u32)
+        .window("PTS4D", settings.width as u32, settings.height as u32)
         .position_centered()
         .opengl()
         .build()
@@ -64,14 +150,11 @@ pub fn main() -> Result<(), String> {
 
     let mut event_pump = sdl_context.event_pump()?;
 
-    // scene is mutable, so the camera can be modified during runtime.
-    let mut scene: Scene = scene_builders::generate_cornell_box_scene();
-
     // Keep track of iterations
     let mut curr_samples_per_pixel = 0;
 
     // Keeps the sum of all colors across all iterations
-    let mut all_frames = initialize_screen();
+    let mut all_frames = initialize_screen(settings);
 
     'running: loop {
         let start_time = Instant::now();
@@ -86,14 +169,14 @@ pub fn main() -> Result<(), String> {
                     if handle_input(event, &mut scene) {
                         // If the camera has changed something,
                         // Delete the frame and start rendering a new one.
-                        all_frames = initialize_screen();
+                        all_frames = initialize_screen(settings);
                         curr_samples_per_pixel = 0; // Set the current samples per pixel to 0;
                     };
                 }
             }
         }
         curr_samples_per_pixel += 1;
-        all_frames = add_screens(all_frames, render_pass(&scene));
+        all_frames = add_screens(all_frames, render_pass(&scene, settings));
         present_screen(&all_frames, &mut canvas, curr_samples_per_pixel);
 
         let end_time = Instant::now() - start_time;
@@ -102,7 +185,7 @@ pub fn main() -> Result<(), String> {
             curr_samples_per_pixel,
             end_time,
             1000.0 / end_time.as_millis() as f32,
-            curr_samples_per_pixel * SAMPLES_PER_PIXEL
+            curr_samples_per_pixel * settings.samples_per_pass
         );
     }
 
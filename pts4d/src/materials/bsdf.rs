@@ -0,0 +1,143 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::object::object::Hit;
+use crate::utils::vector_utils::{orthonormal_basis, Ray};
+
+/**
+ * How a surface scatters light, in its local shading frame: the shading normal is +Z and `wo`,
+ * the direction back towards where the light ends up, is on its side.
+ * Directions are unit vectors pointing away from the surface.
+ */
+pub trait Bsdf {
+    fn flags(&self) -> BsdfFlags;
+
+    // Picks the direction light arrives from, None if the path ends (absorbed or sampled below
+    // a reflective surface).
+    fn sample(&self, wo: Vector3<f32>) -> Option<BsdfSample>;
+
+    // BSDF * |cos| of light arriving from `wi`, zero for delta lobes.
+    fn eval(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> Vector3<f32>;
+
+    // Solid angle density with which `sample` would have picked `wi`, zero for delta lobes.
+    fn pdf(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> f32;
+}
+
+/**
+ * The kinds of lobes a BSDF is made of.
+ */
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BsdfFlags {
+    // Lobes which can be evaluated for any direction, so they can be lit by shadow rays.
+    pub non_delta: bool,
+    // Perfect mirrors and glass, which can only be sampled.
+    pub delta: bool,
+    // Whether light can go through the surface.
+    pub transmission: bool,
+    // Whether the directions light is scattered to depend on its wavelength.
+    pub dispersion: bool,
+}
+
+pub struct BsdfSample {
+    pub wi: Vector3<f32>,
+    // BSDF * |cos| / pdf of the sampled direction.
+    pub weight: Vector3<f32>,
+    // Meaningless for delta lobes.
+    pub pdf: f32,
+    pub is_delta: bool,
+}
+
+/**
+ * The outcome of sampling a BSDF at a hit: where the ray goes next and how much it is attenuated.
+ */
+pub struct Scatter {
+    pub ray: Ray,
+    // BSDF * cos / pdf of the sampled direction.
+    pub attenuation: Vector3<f32>,
+    // Solid angle density the direction was sampled with, meaningless for delta lobes.
+    pub pdf: f32,
+    // Delta lobes cannot be evaluated for arbitrary directions, so they can't be lit by shadow rays.
+    pub is_delta: bool,
+}
+
+// Shading frame of a hit, the normal is +Z and points to the side the ray came from.
+pub struct Frame {
+    tangent: Vector3<f32>,
+    bitangent: Vector3<f32>,
+    normal: Vector3<f32>,
+}
+
+impl Frame {
+    pub fn new(normal: Vector3<f32>) -> Frame {
+        let (tangent, bitangent) = orthonormal_basis(normal);
+        return Frame {
+            tangent,
+            bitangent,
+            normal,
+        };
+    }
+
+    #[inline]
+    pub fn to_local(&self, v: Vector3<f32>) -> Vector3<f32> {
+        return Vector3::new(
+            v.dot(self.tangent),
+            v.dot(self.bitangent),
+            v.dot(self.normal),
+        );
+    }
+
+    #[inline]
+    pub fn to_world(&self, v: Vector3<f32>) -> Vector3<f32> {
+        return v.x * self.tangent + v.y * self.bitangent + v.z * self.normal;
+    }
+}
+
+/**
+ * A BSDF placed at a hit, seen from the incoming ray. Takes and returns world space directions.
+ */
+pub struct SurfaceBsdf<'a> {
+    bsdf: Box<dyn Bsdf + 'a>,
+    frame: Frame,
+    point: Vector3<f32>,
+    wo: Vector3<f32>,
+}
+
+impl<'a> SurfaceBsdf<'a> {
+    pub fn new(bsdf: Box<dyn Bsdf + 'a>, ray: &Ray, hit: &Hit) -> SurfaceBsdf<'a> {
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.normalize());
+        return SurfaceBsdf {
+            bsdf,
+            frame,
+            point: hit.point,
+            wo,
+        };
+    }
+
+    pub fn flags(&self) -> BsdfFlags {
+        return self.bsdf.flags();
+    }
+
+    pub fn sample(&self) -> Option<Scatter> {
+        let sample = self.bsdf.sample(self.wo)?;
+        return Some(Scatter {
+            ray: Ray {
+                origin: self.point,
+                direction: self.frame.to_world(sample.wi),
+            },
+            attenuation: sample.weight,
+            pdf: sample.pdf,
+            is_delta: sample.is_delta,
+        });
+    }
+
+    // BSDF * cos for light arriving from `direction`.
+    pub fn eval(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let wi = self.frame.to_local(direction.normalize());
+        return self.bsdf.eval(self.wo, wi);
+    }
+
+    pub fn pdf(&self, direction: Vector3<f32>) -> f32 {
+        let wi = self.frame.to_local(direction.normalize());
+        return self.bsdf.pdf(self.wo, wi);
+    }
+}
@@ -0,0 +1,70 @@
+// Wavelength refraction indices are usually given at, the sodium D line, in nanometers.
+const NOMINAL_WAVELENGTH: f32 = 589.3;
+
+/**
+ * Index of refraction of a dielectric, which may vary with the wavelength of light (dispersion).
+ * Wavelengths are given in nanometers, the formulas take micrometers.
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Ior {
+    Constant(f32),
+    // n = a + b / λ²
+    Cauchy(f32, f32),
+    // n² = 1 + Σ b λ² / (λ² - c)
+    Sellmeier([f32; 3], [f32; 3]),
+}
+
+impl Ior {
+    // Sellmeier coefficients of common materials.
+    pub fn preset(name: &str) -> Result<Ior, String> {
+        let (b, c) = match name {
+            // Borosilicate crown glass, most lenses.
+            "bk7" => (
+                [1.039_612, 0.231_792_34, 1.010_469_5],
+                [0.006_000_699, 0.020_017_914, 103.560_65],
+            ),
+            "fused-silica" => (
+                [0.696_166_3, 0.407_942_6, 0.897_479_4],
+                [0.004_679_148, 0.013_512_063, 97.934_003],
+            ),
+            // Dense flint glass (SF11), as in prisms.
+            "flint" => (
+                [1.737_597, 0.313_747_35, 1.898_781],
+                [0.013_188_707, 0.062_306_814, 155.236_3],
+            ),
+            "diamond" => ([4.3356, 0.3306, 0.0], [0.011_236, 0.030_625, 0.0]),
+            _ => {
+                return Err(format!(
+                    "Unknown glass '{}', expected bk7, fused-silica, flint or diamond",
+                    name
+                ))
+            }
+        };
+        return Ok(Ior::Sellmeier(b, c));
+    }
+
+    pub fn at(&self, wavelength: f32) -> f32 {
+        let micrometers = wavelength / 1000.0;
+        let squared = micrometers * micrometers;
+        return match self {
+            Ior::Constant(ior) => *ior,
+            Ior::Cauchy(a, b) => a + b / squared,
+            Ior::Sellmeier(b, c) => {
+                let mut n_squared = 1.0;
+                for (b, c) in b.iter().zip(c) {
+                    n_squared += b * squared / (squared - c);
+                }
+                n_squared.max(1.0).sqrt()
+            }
+        };
+    }
+
+    // The index when light isn't split up by wavelength.
+    pub fn nominal(&self) -> f32 {
+        return self.at(NOMINAL_WAVELENGTH);
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        return !matches!(self, Ior::Constant(_));
+    }
+}
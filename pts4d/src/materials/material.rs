@@ -1,22 +1,36 @@
 use std::collections::HashMap;
+use std::f32::consts::PI;
 
 use cgmath::{InnerSpace, Vector3};
 use rand::Rng;
-use wavefront_obj::mtl::{Color, Material as WavefrontObjMaterial};
 use crate::{
     object::object::Hit,
     utils::vector_utils::{is_close_to_zero, random_point_in_unit_sphere, Ray},
 };
 
-#[derive(Debug)]
-#[allow(dead_code)]
+use super::bsdf::{Bsdf, BsdfFlags, BsdfSample, SurfaceBsdf};
+use super::ior::Ior;
+use super::microfacet::{Conductor, RoughDielectric};
+use super::mtl::MtlMaterial;
+use super::normal_map::NormalMap;
+use super::principled::Principled;
+use super::subsurface::Subsurface;
+use super::texture::Texture;
+
+#[derive(Clone, Debug)]
+#[allow(dead_code, clippy::enum_variant_names)]
 pub enum Material {
-    Diffuse(Vector3<f32>),       // albedo
-    Metallic(Vector3<f32>, f32), // albedo, fuzz
-    Dielectric(f32),             // refraction index
-    Emissive(Vector3<f32>, f32), // albedo, intensity
-    Texture(),                   // TODO Implement
-    WavefrontObjMaterial(WavefrontObjMaterial), // everything
+    Diffuse(Vector3<f32>),                 // albedo
+    Metallic(Texture, Texture),            // albedo, fuzz
+    Dielectric(Ior),                       // refraction index
+    Conductor(Conductor, Option<Texture>), // rough metal, roughness map
+    RoughDielectric(RoughDielectric),      // frosted glass
+    Principled(Principled),                // physically based uber material
+    Subsurface(Subsurface),                // skin, wax, marble
+    Emissive(Texture, f32),                // albedo, intensity
+    Texture(Texture),                      // albedo
+    WavefrontObjMaterial(MtlMaterial),     // everything
+    Interface,                             // invisible, only bounds a medium
 }
 
 /**
@@ -52,123 +66,305 @@ impl MaterialSet {
 }
 
 impl Material {
-    pub fn scatter(&self, ray_in: &Ray, hit: &Hit) -> Option<(Ray, Vector3<f32>)> {
-        match self {
+    // How the material scatters light at the hit, in the hit's shading frame.
+    // None if it absorbs everything, like emitters. Dispersive materials scatter light of the given
+    // wavelength (in nanometers) when there is one.
+    pub fn local_bsdf(&self, hit: &Hit, wavelength: Option<f32>) -> Option<Box<dyn Bsdf + '_>> {
+        return match self {
             Material::Emissive(_, _) => None,
-            Material::Diffuse(albedo) => lambertian_shading(ray_in, hit, *albedo),
-            Material::Metallic(albedo, fuzz) => metallic_shading(ray_in, hit, *albedo, *fuzz),
-            Material::Dielectric(refraction_index) => {
-                dielectric_shading(ray_in, hit, *refraction_index)
+            Material::Diffuse(albedo) => Some(Box::new(Lambertian { albedo: *albedo })),
+            Material::Texture(texture) => Some(Box::new(Lambertian {
+                albedo: texture.value(hit),
+            })),
+            Material::Metallic(albedo, fuzz) => Some(Box::new(FuzzyMirror {
+                albedo: albedo.value(hit),
+                fuzz: fuzz.scalar(hit),
+            })),
+            Material::Dielectric(ior) => Some(Box::new(SmoothDielectric {
+                refraction_index: match wavelength {
+                    Some(wavelength) => ior.at(wavelength),
+                    None => ior.nominal(),
+                },
+                is_dispersive: ior.is_dispersive(),
+                is_facing_you: hit.is_facing_you,
+            })),
+            Material::Conductor(conductor, roughness_map) => {
+                Some(Box::new(conductor.textured(roughness_map, hit)))
             }
-            Material::WavefrontObjMaterial(wavefront_mat) => {
-                // TODO: Implement complex wavefront materials / phong / specular / emissive properties.
-                return lambertian_shading(
-                    ray_in,
-                    hit,
-                    wavefront_color_to_vector(wavefront_mat.color_diffuse),
-                );
-            }
-            Material::Texture() => todo!(),
+            Material::RoughDielectric(dielectric) => Some(Box::new(dielectric.seen_from(hit))),
+            Material::Principled(principled) => Some(Box::new(principled.bsdf(hit))),
+            Material::Subsurface(subsurface) => Some(Box::new(subsurface.bsdf())),
+            Material::WavefrontObjMaterial(wavefront_mat) => Some(Box::new(wavefront_mat.bsdf(hit))),
+            Material::Interface => Some(Box::new(PassThrough)),
+        };
+    }
+
+    // The BSDF at the hit, as seen by the incoming ray.
+    pub fn bsdf(
+        &self,
+        ray_in: &Ray,
+        hit: &Hit,
+        wavelength: Option<f32>,
+    ) -> Option<SurfaceBsdf<'_>> {
+        let bsdf = self.local_bsdf(hit, wavelength)?;
+        let Some(normal_map) = self.normal_map() else {
+            return Some(SurfaceBsdf::new(bsdf, ray_in, hit));
+        };
+
+        // Normals tilted away from the ray would leave the surface black, those are ignored.
+        let normal = normal_map.apply(hit);
+        if normal.dot(ray_in.direction) >= 0.0 {
+            return Some(SurfaceBsdf::new(bsdf, ray_in, hit));
+        }
+        let mapped_hit = Hit { normal, ..*hit };
+        return Some(SurfaceBsdf::new(bsdf, ray_in, &mapped_hit));
+    }
+
+    // Whether a ray hitting the surface stops there, rather than going through a cut out part of
+    // its alpha map. Where the alpha is in between, rays stop with a probability of the alpha.
+    pub fn is_opaque_at(&self, hit: &Hit) -> bool {
+        let alpha_map = match self {
+            Material::Principled(principled) => principled.alpha_map.as_ref(),
+            Material::WavefrontObjMaterial(wavefront_mat) => wavefront_mat.alpha_map.as_ref(),
+            _ => None,
+        };
+        let Some(alpha_map) = alpha_map else {
+            return true;
+        };
+
+        let alpha = alpha_map.scalar(hit);
+        if alpha >= 1.0 {
+            return true;
         }
+        return alpha > 0.0 && rand::thread_rng().gen::<f32>() < alpha;
     }
 
-    pub fn emit(&self, _ray_in: &Ray) -> Vector3<f32> {
+    pub fn normal_map(&self) -> Option<&NormalMap> {
+        return match self {
+            Material::Principled(principled) => principled.normal_map.as_ref(),
+            Material::WavefrontObjMaterial(wavefront_mat) => wavefront_mat.normal_map.as_ref(),
+            _ => None,
+        };
+    }
+
+    pub fn is_emissive(&self) -> bool {
         match self {
-            Material::Emissive(color, intensity) => *intensity * *color,
-            Material::WavefrontObjMaterial(wavefront_mat) => {
-                if wavefront_mat.name != "Light" {
-                    return Vector3::new(0.0, 0.0, 0.0)
-                }
+            Material::Emissive(_, _) => true,
+            Material::WavefrontObjMaterial(wavefront_mat) => wavefront_mat.is_emissive(),
+            Material::Principled(principled) => principled.is_emissive(),
+            _ => false,
+        }
+    }
 
-                return wavefront_color_to_vector(wavefront_mat.color_ambient) * 20.0;
-            }
+    pub fn emit(&self, _ray_in: &Ray, hit: &Hit) -> Vector3<f32> {
+        match self {
+            Material::Emissive(color, intensity) => *intensity * color.value(hit),
+            Material::WavefrontObjMaterial(wavefront_mat) => wavefront_mat.emit(hit),
+            Material::Principled(principled) => principled.emit(hit),
             _ => Vector3::new(0.0, 0.0, 0.0),
         }
     }
 }
 
-fn lambertian_shading(_ray: &Ray, hit: &Hit, albedo: Vector3<f32>) -> Option<(Ray, Vector3<f32>)> {
-    let mut scatter_direction = hit.normal + random_point_in_unit_sphere().normalize();
+// Perfectly matte surfaces.
+pub struct Lambertian {
+    pub albedo: Vector3<f32>,
+}
+
+impl Bsdf for Lambertian {
+    fn flags(&self) -> BsdfFlags {
+        return BsdfFlags {
+            non_delta: true,
+            ..BsdfFlags::default()
+        };
+    }
+
+    fn sample(&self, _wo: Vector3<f32>) -> Option<BsdfSample> {
+        // A point on the unit sphere tangent to the surface gives a cosine weighted direction.
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let mut wi = normal + random_point_in_unit_sphere().normalize();
 
-    if is_close_to_zero(scatter_direction) {
-        scatter_direction = hit.normal;
+        if is_close_to_zero(wi) {
+            wi = normal;
+        }
+        let wi = wi.normalize();
+
+        return Some(BsdfSample {
+            wi,
+            weight: self.albedo,
+            pdf: lambertian_pdf(wi),
+            is_delta: false,
+        });
+    }
+
+    fn eval(&self, _wo: Vector3<f32>, wi: Vector3<f32>) -> Vector3<f32> {
+        return lambertian_eval(self.albedo, wi);
+    }
+
+    fn pdf(&self, _wo: Vector3<f32>, wi: Vector3<f32>) -> f32 {
+        return lambertian_pdf(wi);
+    }
+}
+
+#[inline]
+pub fn lambertian_eval(albedo: Vector3<f32>, wi: Vector3<f32>) -> Vector3<f32> {
+    if wi.z <= 0.0 {
+        return Vector3::new(0.0, 0.0, 0.0);
     }
+    return albedo * wi.z / PI;
+}
 
-    return Some((
-        Ray {
-            origin: hit.point,
-            direction: scatter_direction,
-        },
-        albedo,
-    ));
+#[inline]
+pub fn lambertian_pdf(wi: Vector3<f32>) -> f32 {
+    return f32::max(wi.z, 0.0) / PI;
 }
 
-fn metallic_shading(
-    ray: &Ray,
-    hit: &Hit,
+// A mirror blurred by jittering the reflected direction within a sphere of radius `fuzz`.
+// The jitter has no closed form density, so it is treated as a delta lobe.
+struct FuzzyMirror {
     albedo: Vector3<f32>,
     fuzz: f32,
-) -> Option<(Ray, Vector3<f32>)> {
-    let reflected = reflect_vector(ray.direction.normalize(), hit.normal);
-    let scattered = Ray {
-        origin: hit.point,
-        direction: (reflected + fuzz * random_point_in_unit_sphere()).normalize(),
-    };
+}
+
+impl Bsdf for FuzzyMirror {
+    fn flags(&self) -> BsdfFlags {
+        return BsdfFlags {
+            delta: true,
+            ..BsdfFlags::default()
+        };
+    }
+
+    fn sample(&self, wo: Vector3<f32>) -> Option<BsdfSample> {
+        let wi = (mirror(wo) + self.fuzz * random_point_in_unit_sphere()).normalize();
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        return Some(BsdfSample {
+            wi,
+            weight: self.albedo,
+            pdf: 0.0,
+            is_delta: true,
+        });
+    }
+
+    fn eval(&self, _wo: Vector3<f32>, _wi: Vector3<f32>) -> Vector3<f32> {
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+
+    fn pdf(&self, _wo: Vector3<f32>, _wi: Vector3<f32>) -> f32 {
+        return 0.0;
+    }
+}
+
+// Smooth glass, reflecting or refracting by Schlick's Fresnel approximation.
+struct SmoothDielectric {
+    refraction_index: f32,
+    // Whether the index depends on the wavelength.
+    is_dispersive: bool,
+    // Whether the ray is coming from outside.
+    is_facing_you: bool,
+}
+
+impl Bsdf for SmoothDielectric {
+    fn flags(&self) -> BsdfFlags {
+        return BsdfFlags {
+            delta: true,
+            transmission: true,
+            dispersion: self.is_dispersive,
+            ..BsdfFlags::default()
+        };
+    }
 
-    if scattered.direction.dot(hit.normal) > 0.0 {
-        return Some((scattered, albedo));
+    fn sample(&self, wo: Vector3<f32>) -> Option<BsdfSample> {
+        // Tinted glass absorbs along the way through, in its interior medium.
+        return Some(BsdfSample {
+            wi: dielectric_direction(wo, self.refraction_index, self.is_facing_you),
+            weight: Vector3::new(1.0, 1.0, 1.0),
+            pdf: 0.0,
+            is_delta: true,
+        });
     }
 
-    return None;
+    fn eval(&self, _wo: Vector3<f32>, _wi: Vector3<f32>) -> Vector3<f32> {
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+
+    fn pdf(&self, _wo: Vector3<f32>, _wi: Vector3<f32>) -> f32 {
+        return 0.0;
+    }
 }
 
-fn dielectric_shading(ray: &Ray, hit: &Hit, refraction_index: f32) -> Option<(Ray, Vector3<f32>)> {
+// Interfaces aren't really there, light just carries on.
+struct PassThrough;
+
+impl Bsdf for PassThrough {
+    fn flags(&self) -> BsdfFlags {
+        return BsdfFlags {
+            delta: true,
+            transmission: true,
+            ..BsdfFlags::default()
+        };
+    }
+
+    fn sample(&self, wo: Vector3<f32>) -> Option<BsdfSample> {
+        return Some(BsdfSample {
+            wi: -wo,
+            weight: Vector3::new(1.0, 1.0, 1.0),
+            pdf: 0.0,
+            is_delta: true,
+        });
+    }
+
+    fn eval(&self, _wo: Vector3<f32>, _wi: Vector3<f32>) -> Vector3<f32> {
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+
+    fn pdf(&self, _wo: Vector3<f32>, _wi: Vector3<f32>) -> f32 {
+        return 0.0;
+    }
+}
+
+// Either reflects or refracts `wo` in the shading frame, picked by Fresnel.
+pub fn dielectric_direction(
+    wo: Vector3<f32>,
+    refraction_index: f32,
+    is_facing_you: bool,
+) -> Vector3<f32> {
     let mut rng = rand::thread_rng();
 
-    let attenuation = Vector3::new(0.99, 0.99, 0.99);
-    let refraction_ratio = if hit.is_facing_you {
+    let refraction_ratio = if is_facing_you {
         1.0 / refraction_index
     } else {
         refraction_index
     };
 
-    let ray_direction_unit = ray.direction.normalize();
-    let cos_theta = -(ray_direction_unit.dot(hit.normal).min(1.0));
+    let cos_theta = wo.z.min(1.0);
     let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
-    let scattered: Vector3<f32>;
-
     let cant_refract = refraction_ratio * sin_theta > 1.0;
     let fresnel_reflection =
         reflectance_schlick_approx(cos_theta, refraction_index) > rng.gen::<f32>();
 
     if cant_refract || fresnel_reflection {
-        scattered = reflect_vector(ray_direction_unit, hit.normal);
-    } else {
-        scattered = refract_vector(ray_direction_unit, hit.normal, refraction_ratio)
+        return mirror(wo);
     }
+    return refract_vector(-wo, Vector3::new(0.0, 0.0, 1.0), refraction_ratio);
+}
 
-    return Some((
-        Ray {
-            origin: hit.point,
-            direction: scattered,
-        },
-        attenuation,
-    ));
+// The mirror direction of `wo` in the shading frame.
+#[inline]
+pub fn mirror(wo: Vector3<f32>) -> Vector3<f32> {
+    return Vector3::new(-wo.x, -wo.y, wo.z);
 }
 
 #[inline]
-fn reflectance_schlick_approx(cos: f32, refraction_index: f32) -> f32 {
+pub fn reflectance_schlick_approx(cos: f32, refraction_index: f32) -> f32 {
     let r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
     let r0_2 = r0.powi(2);
     return r0_2 + (1.0 - r0_2) * f32::powf(1.0 - cos, 5.0);
 }
 
-#[inline]
-fn reflect_vector(vec: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
-    return vec - 2.0 * vec.dot(normal) * normal;
-}
-
 #[inline]
 fn refract_vector(vec: Vector3<f32>, normal: Vector3<f32>, refraction_ratio: f32) -> Vector3<f32> {
     // All vectors must be unit vectors
@@ -179,7 +375,160 @@ fn refract_vector(vec: Vector3<f32>, normal: Vector3<f32>, refraction_ratio: f32
     return r_out_perp + r_out_parallel;
 }
 
-#[inline]
-fn wavefront_color_to_vector(color: Color) -> Vector3<f32> {
-    return Vector3::new(color.r as f32, color.g as f32, color.b as f32);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::object::PrimitiveId;
+    use crate::utils::vector_utils::random_unit_vector;
+    use cgmath::Vector2;
+
+    fn hit_on(normal: Vector3<f32>, is_facing_you: bool, material: &Material) -> Hit<'_> {
+        return Hit {
+            point_at_intersection: 1.0,
+            point: Vector3::new(0.0, 0.0, 0.0),
+            normal,
+            geometric_normal: normal,
+            is_facing_you,
+            uv: Vector2::new(0.0, 0.0),
+            dpdu: Vector3::new(0.0, 0.0, 0.0),
+            dpdv: Vector3::new(0.0, 0.0, 0.0),
+            material,
+            primitive: PrimitiveId::default(),
+        };
+    }
+
+    // Samples the material `count` times, returns the mean of the scattered directions and how
+    // many of them were absorbed or left on the side of the normal.
+    fn statistics(
+        mut scatter: impl FnMut() -> Option<Vector3<f32>>,
+        normal: Vector3<f32>,
+        count: usize,
+    ) -> (Vector3<f32>, usize, usize) {
+        let (mut mean, mut absorbed, mut reflected) = (Vector3::new(0.0, 0.0, 0.0), 0, 0);
+        for _ in 0..count {
+            let Some(direction) = scatter() else {
+                absorbed += 1;
+                continue;
+            };
+            let direction = direction.normalize();
+            mean += direction / count as f32;
+            if direction.dot(normal) > 0.0 {
+                reflected += 1;
+            }
+        }
+        return (mean, absorbed, reflected);
+    }
+
+    fn reflect_vector(vec: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+        return vec - 2.0 * vec.dot(normal) * normal;
+    }
+
+    #[test]
+    fn lambertian_samples_agree_with_eval_and_pdf() {
+        let lambertian = Lambertian {
+            albedo: Vector3::new(0.8, 0.5, 0.2),
+        };
+        let wo = Vector3::new(0.3, -0.5, 0.8).normalize();
+        for _ in 0..10000 {
+            let sample = lambertian.sample(wo).unwrap();
+            let pdf = lambertian.pdf(wo, sample.wi);
+            assert!((pdf - sample.pdf).abs() <= 1e-5);
+            if pdf > 1e-3 {
+                let expected = lambertian.eval(wo, sample.wi) / pdf;
+                assert!((sample.weight - expected).magnitude() < 1e-4);
+            }
+        }
+
+        // Averaged over uniformly picked directions, the pdf is one over the sphere's area.
+        let samples = 200000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            total += lambertian.pdf(wo, random_unit_vector());
+        }
+        let integral = total / samples as f32 * 4.0 * PI;
+        assert!((integral - 1.0).abs() < 0.02, "The pdf integrates to {}", integral);
+    }
+
+    // Compares the shading frame BSDFs with how `Material::scatter` used to work in world space.
+    #[test]
+    fn fuzzy_mirrors_scatter_like_before() {
+        let normal = Vector3::new(0.2, 1.0, -0.4).normalize();
+        let ray = Ray {
+            origin: Vector3::new(-1.0, 1.0, 0.5),
+            direction: Vector3::new(1.0, -1.0, -0.5),
+        };
+        let material = Material::Metallic(Texture::uniform(0.9), Texture::uniform(0.6));
+        let hit = hit_on(normal, true, &material);
+        let count = 100000;
+
+        let new = statistics(
+            || {
+                let scatter = material.bsdf(&ray, &hit, None)?.sample()?;
+                return Some(scatter.ray.direction);
+            },
+            normal,
+            count,
+        );
+        let old = statistics(
+            || {
+                let reflected = reflect_vector(ray.direction.normalize(), normal);
+                let direction = (reflected + 0.6 * random_point_in_unit_sphere()).normalize();
+                if direction.dot(normal) > 0.0 {
+                    return Some(direction);
+                }
+                return None;
+            },
+            normal,
+            count,
+        );
+
+        assert!((new.0 - old.0).magnitude() < 0.01);
+        assert!((new.1 as f32 - old.1 as f32).abs() < 0.01 * count as f32);
+        assert_eq!(new.2, count - new.1);
+    }
+
+    #[test]
+    fn glass_scatters_like_before() {
+        let normal = Vector3::new(0.2, 1.0, -0.4).normalize();
+        let direction = Vector3::new(1.0, -0.6, -0.5).normalize();
+        let ray = Ray {
+            origin: -direction,
+            direction,
+        };
+        let material = Material::Dielectric(Ior::Constant(1.5));
+        let count = 100000;
+
+        // Entering the glass, and leaving it at a grazing angle, where it is totally reflected.
+        for is_facing_you in [true, false] {
+            let hit = hit_on(normal, is_facing_you, &material);
+            let new = statistics(
+                || {
+                    let scatter = material.bsdf(&ray, &hit, None)?.sample()?;
+                    return Some(scatter.ray.direction);
+                },
+                normal,
+                count,
+            );
+            let old = statistics(
+                || {
+                    let ratio = if is_facing_you { 1.0 / 1.5 } else { 1.5 };
+                    let cos_theta = -(direction.dot(normal).min(1.0));
+                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                    let cant_refract = ratio * sin_theta > 1.0;
+                    let fresnel_reflection = reflectance_schlick_approx(cos_theta, 1.5)
+                        > rand::thread_rng().gen::<f32>();
+                    if cant_refract || fresnel_reflection {
+                        return Some(reflect_vector(direction, normal));
+                    }
+                    return Some(refract_vector(direction, normal, ratio));
+                },
+                normal,
+                count,
+            );
+
+            assert_eq!(new.1, 0);
+            assert!((new.0 - old.0).magnitude() < 0.01);
+            assert!((new.2 as f32 - old.2 as f32).abs() < 0.01 * count as f32);
+        }
+    }
 }
@@ -0,0 +1,305 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use cgmath::{ElementWise, InnerSpace, Vector3};
+use rand::Rng;
+
+use crate::accel::aabb::AABB;
+use crate::utils::vector_utils::orthonormal_basis;
+
+use super::voxel_grid::VoxelGrid;
+
+/**
+ * A participating medium, like fog, smoke or the inside of tinted glass.
+ * Coefficients are per unit of distance, light travelling `d` through a homogeneous medium
+ * is attenuated by exp(-(absorption + scattering) * d) (Beer-Lambert).
+ * With a density field, the coefficients are scaled by the density at each point.
+ */
+#[derive(Clone, Debug)]
+pub struct Medium {
+    pub absorption: Vector3<f32>,
+    pub scattering: Vector3<f32>,
+    // Henyey-Greenstein asymmetry, -1 scatters everything back, 0 evenly, 1 straight ahead.
+    pub anisotropy: f32,
+    pub density: Option<DensityField>,
+}
+
+/**
+ * A voxel grid stretched over a box of the scene.
+ */
+#[derive(Clone, Debug)]
+pub struct DensityField {
+    pub grid: Arc<VoxelGrid>,
+    pub bounds: AABB,
+    // Multiplies the densities of the grid.
+    pub scale: f32,
+}
+
+impl DensityField {
+    #[inline]
+    fn density(&self, point: Vector3<f32>) -> f32 {
+        let min = self.bounds.min();
+        let size = self.bounds.max() - min;
+        let local = (point - min).div_element_wise(size);
+        return self.scale * self.grid.density(local);
+    }
+
+    // How far along the ray its box ends, everything past that is empty.
+    fn exit_distance(&self, origin: Vector3<f32>, direction: Vector3<f32>) -> f32 {
+        let mut exit = f32::INFINITY;
+        for axis in 0..3 {
+            let interval = self.bounds.get_axis(axis);
+            let boundary = if direction[axis] < 0.0 {
+                interval.min
+            } else {
+                interval.max
+            };
+            let t = (boundary - origin[axis]) / direction[axis];
+            if t < exit {
+                exit = t;
+            }
+        }
+        return exit.max(0.0);
+    }
+}
+
+// What happened to a ray on its way through a medium.
+pub enum MediumEvent {
+    // Scattered `distance` along the ray.
+    Scattered { distance: f32, weight: Vector3<f32> },
+    // Made it through to whatever ended the segment.
+    Passed { weight: Vector3<f32> },
+}
+
+impl Medium {
+    // A medium which only absorbs, tinting light to `color` after travelling `distance`.
+    pub fn from_color_at_distance(color: Vector3<f32>, distance: f32) -> Medium {
+        let absorb = |value: f32| -value.clamp(1e-6, 1.0).ln() / distance;
+        return Medium {
+            absorption: Vector3::new(absorb(color.x), absorb(color.y), absorb(color.z)),
+            scattering: Vector3::new(0.0, 0.0, 0.0),
+            anisotropy: 0.0,
+            density: None,
+        };
+    }
+
+    #[inline]
+    pub fn extinction(&self) -> Vector3<f32> {
+        return self.absorption + self.scattering;
+    }
+
+    // Fraction of the light which makes it `distance` along the unit `direction` from `origin`.
+    pub fn transmittance(
+        &self,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        distance: f32,
+    ) -> Vector3<f32> {
+        let Some(field) = &self.density else {
+            return self.homogeneous_transmittance(distance);
+        };
+
+        // Ratio tracking: every tentative collision lets through what isn't really there.
+        let mut rng = rand::thread_rng();
+        let majorant = self.majorant(field);
+        let mut transmittance = Vector3::new(1.0, 1.0, 1.0);
+        if majorant <= 0.0 {
+            return transmittance;
+        }
+
+        let distance = distance.min(field.exit_distance(origin, direction));
+        let mut t = 0.0;
+        loop {
+            t -= (1.0 - rng.gen::<f32>()).ln() / majorant;
+            if t >= distance {
+                return transmittance;
+            }
+            let extinction = field.density(origin + t * direction) * self.extinction();
+            transmittance =
+                transmittance.mul_element_wise(Vector3::new(1.0, 1.0, 1.0) - extinction / majorant);
+        }
+    }
+
+    fn homogeneous_transmittance(&self, distance: f32) -> Vector3<f32> {
+        let extinction = self.extinction();
+        let attenuate = |coefficient: f32| {
+            if coefficient <= 0.0 {
+                return 1.0;
+            }
+            return (-coefficient * distance).exp();
+        };
+        return Vector3::new(
+            attenuate(extinction.x),
+            attenuate(extinction.y),
+            attenuate(extinction.z),
+        );
+    }
+
+    // Samples where along a segment of length `max_distance`, starting at `origin` and going along
+    // the unit `direction`, the ray scatters, if at all.
+    pub fn sample_distance(
+        &self,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> MediumEvent {
+        if let Some(field) = &self.density {
+            return self.track_delta(field, origin, direction, max_distance);
+        }
+
+        // The distance is picked for one of the color channels at random, the weight accounts
+        // for the density of having picked it through any of them.
+        let mut rng = rand::thread_rng();
+        let extinction = self.extinction();
+        let channel = extinction[rng.gen_range(0..3)];
+
+        let distance = if channel > 0.0 {
+            -(1.0 - rng.gen::<f32>()).ln() / channel
+        } else {
+            f32::INFINITY
+        };
+
+        if distance < max_distance {
+            let transmittance = self.homogeneous_transmittance(distance);
+            let pdf = average(extinction.mul_element_wise(transmittance));
+            if pdf <= 0.0 {
+                return MediumEvent::Passed {
+                    weight: Vector3::new(0.0, 0.0, 0.0),
+                };
+            }
+            return MediumEvent::Scattered {
+                distance,
+                weight: transmittance.mul_element_wise(self.scattering) / pdf,
+            };
+        }
+
+        let transmittance = self.homogeneous_transmittance(max_distance);
+        let pdf = average(transmittance);
+        if pdf <= 0.0 {
+            return MediumEvent::Passed {
+                weight: Vector3::new(0.0, 0.0, 0.0),
+            };
+        }
+        return MediumEvent::Passed {
+            weight: transmittance / pdf,
+        };
+    }
+
+    // Delta tracking: the medium is made homogeneous by filling it up with fictitious particles,
+    // which don't do anything when a ray collides with them. Collisions are real with the
+    // probability of the average extinction, the weights correct for the colors.
+    fn track_delta(
+        &self,
+        field: &DensityField,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> MediumEvent {
+        let mut rng = rand::thread_rng();
+        let majorant = self.majorant(field);
+        let mut weight = Vector3::new(1.0, 1.0, 1.0);
+        if majorant <= 0.0 {
+            return MediumEvent::Passed { weight };
+        }
+
+        let max_distance = max_distance.min(field.exit_distance(origin, direction));
+        let mut t = 0.0;
+        loop {
+            t -= (1.0 - rng.gen::<f32>()).ln() / majorant;
+            if t >= max_distance {
+                return MediumEvent::Passed { weight };
+            }
+
+            let density = field.density(origin + t * direction);
+            let extinction = density * self.extinction();
+            let real_probability = average(extinction) / majorant;
+
+            if rng.gen::<f32>() < real_probability {
+                return MediumEvent::Scattered {
+                    distance: t,
+                    weight: weight.mul_element_wise(density * self.scattering)
+                        / (majorant * real_probability),
+                };
+            }
+            let null = Vector3::new(majorant, majorant, majorant) - extinction;
+            weight = weight.mul_element_wise(null) / (majorant * (1.0 - real_probability));
+        }
+    }
+
+    // Upper bound of the extinction anywhere in the field, in every channel.
+    fn majorant(&self, field: &DensityField) -> f32 {
+        let extinction = self.extinction();
+        return field.scale
+            * field.grid.max_value
+            * extinction.x.max(extinction.y).max(extinction.z);
+    }
+
+    // Henyey-Greenstein phase function, for light travelling along `direction_in`
+    // and scattering into `direction_out`, both unit vectors.
+    pub fn phase(&self, direction_in: Vector3<f32>, direction_out: Vector3<f32>) -> f32 {
+        let g = self.anisotropy;
+        let cos_theta = direction_in.dot(direction_out);
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        return (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt());
+    }
+
+    // Samples a new unit direction proportionally to the phase function,
+    // which is therefore also its pdf.
+    pub fn sample_phase(&self, direction_in: Vector3<f32>) -> Vector3<f32> {
+        let mut rng = rand::thread_rng();
+        let g = self.anisotropy;
+        let u = rng.gen::<f32>();
+
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - term * term) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f32>();
+
+        let (tangent, bitangent) = orthonormal_basis(direction_in);
+        return (sin_theta * phi.cos() * tangent
+            + sin_theta * phi.sin() * bitangent
+            + cos_theta * direction_in)
+            .normalize();
+    }
+}
+
+#[inline]
+fn average(vector: Vector3<f32>) -> f32 {
+    return (vector.x + vector.y + vector.z) / 3.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phase_samples_follow_the_anisotropy() {
+        // The mean cosine of Henyey-Greenstein is its asymmetry parameter.
+        for anisotropy in [-0.7, 0.0, 0.5, 0.9] {
+            let medium = Medium {
+                absorption: Vector3::new(0.0, 0.0, 0.0),
+                scattering: Vector3::new(1.0, 1.0, 1.0),
+                anisotropy,
+                density: None,
+            };
+            let direction = Vector3::new(0.0, 0.6, 0.8);
+            let count = 100_000;
+            let mean_cosine: f32 = (0..count)
+                .map(|_| medium.sample_phase(direction).dot(direction))
+                .sum::<f32>()
+                / count as f32;
+            assert!((mean_cosine - anisotropy).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn absorbs_to_the_given_color() {
+        let medium = Medium::from_color_at_distance(Vector3::new(0.5, 0.25, 1.0), 2.0);
+        let transmittance = medium.homogeneous_transmittance(2.0);
+        assert!((transmittance - Vector3::new(0.5, 0.25, 1.0)).magnitude() < 1e-5);
+    }
+}
@@ -0,0 +1,518 @@
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector3};
+use rand::Rng;
+
+use crate::object::object::Hit;
+
+use super::bsdf::{Bsdf, BsdfFlags, BsdfSample};
+use super::texture::Texture;
+
+// Below this GGX roughness the lobes are too sharp to evaluate, surfaces are treated as smooth.
+pub const MIN_ALPHA: f32 = 1e-3;
+// Light refracted closer than this (cosine) to the plane of its microfacet is negligible, and its
+// density too sensitive to round-off in the direction to come out the same twice, it's dropped.
+const MIN_REFRACTED_COS: f32 = 1e-3;
+
+/**
+ * A metal with microscopically rough surface (GGX / Trowbridge-Reitz microfacets).
+ * The complex index of refraction, per color channel, gives it its color through Fresnel.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Conductor {
+    pub eta: Vector3<f32>,
+    pub k: Vector3<f32>,
+    // GGX alpha, the squared perceptual roughness.
+    pub alpha: f32,
+}
+
+/**
+ * Frosted glass: a dielectric with a rough surface, reflecting and refracting off GGX microfacets.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct RoughDielectric {
+    pub ior: f32,
+    pub alpha: f32,
+}
+
+// Complex indices of refraction (eta, k) of metals, at wavelengths standing for red, green and blue.
+pub fn metal_preset(name: &str) -> Result<(Vector3<f32>, Vector3<f32>), String> {
+    let (eta, k) = match name {
+        "gold" => ([0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+        "silver" => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+        "copper" => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+        "aluminium" | "aluminum" => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+        "iron" => ([2.912, 2.950, 2.585], [3.089, 2.932, 2.767]),
+        _ => {
+            return Err(format!(
+                "Unknown metal '{}', expected gold, silver, copper, aluminium or iron",
+                name
+            ))
+        }
+    };
+    return Ok((Vector3::from(eta), Vector3::from(k)));
+}
+
+impl Conductor {
+    pub fn new(eta: Vector3<f32>, k: Vector3<f32>, roughness: f32) -> Conductor {
+        return Conductor {
+            eta,
+            k,
+            alpha: roughness.clamp(0.0, 1.0).powi(2),
+        };
+    }
+
+    // The conductor at a hit, its roughness multiplied by the map there.
+    pub fn textured(&self, roughness_map: &Option<Texture>, hit: &Hit) -> Conductor {
+        let Some(map) = roughness_map else {
+            return *self;
+        };
+        let roughness = self.alpha.sqrt() * map.scalar(hit);
+        return Conductor::new(self.eta, self.k, roughness);
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        return self.alpha < MIN_ALPHA;
+    }
+
+    #[inline]
+    fn fresnel(&self, cos_theta: f32) -> Vector3<f32> {
+        return Vector3::new(
+            fresnel_conductor(cos_theta, self.eta.x, self.k.x),
+            fresnel_conductor(cos_theta, self.eta.y, self.k.y),
+            fresnel_conductor(cos_theta, self.eta.z, self.k.z),
+        );
+    }
+}
+
+impl Bsdf for Conductor {
+    fn flags(&self) -> BsdfFlags {
+        return BsdfFlags {
+            non_delta: !self.is_smooth(),
+            delta: self.is_smooth(),
+            transmission: false,
+            dispersion: false,
+        };
+    }
+
+    fn sample(&self, wo: Vector3<f32>) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        if self.is_smooth() {
+            return Some(BsdfSample {
+                wi: Vector3::new(-wo.x, -wo.y, wo.z),
+                weight: self.fresnel(wo.z),
+                pdf: 0.0,
+                is_delta: true,
+            });
+        }
+
+        let wi = sample_ggx_reflection(wo, self.alpha)?;
+        let wm = (wo + wi).normalize();
+
+        // With visible normals, everything but Fresnel and the masking of the outgoing
+        // direction cancels out.
+        return Some(BsdfSample {
+            wi,
+            weight: self.fresnel(wo.dot(wm))
+                * (smith_g2(wo, wi, self.alpha) / smith_g1(wo, self.alpha)),
+            pdf: ggx_reflection_pdf(wo, wi, self.alpha),
+            is_delta: false,
+        });
+    }
+
+    fn eval(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> Vector3<f32> {
+        if self.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+
+        let wm = (wo + wi).normalize();
+        return self.fresnel(wo.dot(wm)) * ggx_reflection_eval(wo, wi, self.alpha);
+    }
+
+    fn pdf(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> f32 {
+        if self.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        return ggx_reflection_pdf(wo, wi, self.alpha);
+    }
+}
+
+impl RoughDielectric {
+    pub fn new(ior: f32, roughness: f32) -> RoughDielectric {
+        return RoughDielectric {
+            ior,
+            alpha: roughness.clamp(0.0, 1.0).powi(2).max(MIN_ALPHA),
+        };
+    }
+
+    // The surface as seen from the side of the hit, where `ior` is the ratio of the index of
+    // refraction on the other side of the surface to this side's.
+    pub fn seen_from(&self, hit: &Hit) -> RoughDielectric {
+        let ior = if hit.is_facing_you {
+            self.ior
+        } else {
+            1.0 / self.ior
+        };
+        return RoughDielectric {
+            ior,
+            alpha: self.alpha,
+        };
+    }
+
+    // BSDF * |cos|, the same for all colors.
+    pub fn eval_scalar(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> f32 {
+        let Some(wm) = generalized_half_vector(wo, wi, self.ior) else {
+            return 0.0;
+        };
+
+        let masking = ggx_d(wm, self.alpha) * smith_g2(wo, wi, self.alpha);
+        if wi.z > 0.0 {
+            return fresnel_dielectric(wo.dot(wm), self.ior) * masking / (4.0 * wo.z);
+        }
+        let Some(jacobian) = refraction_jacobian(wo, wi, wm, self.ior) else {
+            return 0.0;
+        };
+        return fresnel_transmittance(wo, wi, wm, self.ior) * masking * wo.dot(wm) * jacobian
+            / wo.z;
+    }
+
+    // Density of sampling `wi` through the microfacet `wm` half way between it and `wo`.
+    fn density(&self, wo: Vector3<f32>, wi: Vector3<f32>, wm: Vector3<f32>) -> f32 {
+        let normal_pdf = visible_normal_pdf(wo, wm, self.alpha);
+        if wi.z > 0.0 {
+            return fresnel_dielectric(wo.dot(wm), self.ior) * normal_pdf / (4.0 * wo.dot(wm));
+        }
+        let Some(jacobian) = refraction_jacobian(wo, wi, wm, self.ior) else {
+            return 0.0;
+        };
+        return fresnel_transmittance(wo, wi, wm, self.ior) * normal_pdf * jacobian;
+    }
+}
+
+// Like the smooth dielectric, light isn't scaled by the change of the index of refraction,
+// tinting comes from the interior medium. Only meant to be used `seen_from` a hit.
+impl Bsdf for RoughDielectric {
+    fn flags(&self) -> BsdfFlags {
+        return BsdfFlags {
+            non_delta: true,
+            delta: false,
+            transmission: true,
+            dispersion: false,
+        };
+    }
+
+    fn sample(&self, wo: Vector3<f32>) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        let wm = sample_visible_normal(wo, self.alpha, rng.gen(), rng.gen());
+        let reflectance = fresnel_dielectric(wo.dot(wm), self.ior);
+
+        let is_reflected = rng.gen::<f32>() < reflectance;
+        let wi = if is_reflected {
+            reflect(wo, wm)
+        } else {
+            refract(wo, wm, self.ior)?
+        }
+        .normalize();
+        // Bounced off or through the microfacet but to the wrong side of the surface.
+        if (wi.z > 0.0) != is_reflected {
+            return None;
+        }
+
+        // The density goes by the half vector `pdf` finds for wo and wi rather than by the
+        // sampled normal, so that the two agree on it.
+        let half = generalized_half_vector(wo, wi, self.ior)?;
+        let pdf = self.density(wo, wi, half);
+
+        // Fresnel picks between reflection and refraction, it cancels out with their pdfs.
+        let weight = smith_g2(wo, wi, self.alpha) / smith_g1(wo, self.alpha);
+        return Some(BsdfSample {
+            wi,
+            weight: Vector3::new(weight, weight, weight),
+            pdf,
+            is_delta: false,
+        });
+    }
+
+    fn eval(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> Vector3<f32> {
+        let value = self.eval_scalar(wo, wi);
+        return Vector3::new(value, value, value);
+    }
+
+    fn pdf(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> f32 {
+        let Some(wm) = generalized_half_vector(wo, wi, self.ior) else {
+            return 0.0;
+        };
+        return self.density(wo, wi, wm);
+    }
+}
+
+// Samples a direction reflected off a GGX microsurface, in the local frame.
+pub fn sample_ggx_reflection(wo: Vector3<f32>, alpha: f32) -> Option<Vector3<f32>> {
+    let mut rng = rand::thread_rng();
+    let wm = sample_visible_normal(wo, alpha, rng.gen(), rng.gen());
+    let wi = reflect(wo, wm);
+    if wi.z <= 0.0 {
+        return None;
+    }
+    return Some(wi);
+}
+
+// BSDF * cos of GGX reflection, before Fresnel, in the local frame.
+pub fn ggx_reflection_eval(wo: Vector3<f32>, wi: Vector3<f32>, alpha: f32) -> f32 {
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+        return 0.0;
+    }
+    let wm = (wo + wi).normalize();
+    return ggx_d(wm, alpha) * smith_g2(wo, wi, alpha) / (4.0 * wo.z);
+}
+
+pub fn ggx_reflection_pdf(wo: Vector3<f32>, wi: Vector3<f32>, alpha: f32) -> f32 {
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+        return 0.0;
+    }
+    let wm = (wo + wi).normalize();
+    return visible_normal_pdf(wo, wm, alpha) / (4.0 * wo.dot(wm));
+}
+
+// The microfacet normal which reflects or refracts `wo` into `wi`, if any faces both of them.
+fn generalized_half_vector(wo: Vector3<f32>, wi: Vector3<f32>, eta: f32) -> Option<Vector3<f32>> {
+    if wo.z <= 0.0 || wi.z == 0.0 {
+        return None;
+    }
+    let half = if wi.z > 0.0 {
+        wo + wi
+    } else {
+        -(eta * wi + wo)
+    };
+    let length_2 = half.magnitude2();
+    if length_2 < 1e-12 {
+        return None;
+    }
+    let wm = half / length_2.sqrt();
+    let wm = if wm.z < 0.0 { -wm } else { wm };
+    // Microfacets seen from behind can't do either.
+    if wo.dot(wm) <= 0.0
+        || (wi.z < 0.0 && wi.dot(wm) > -MIN_REFRACTED_COS)
+        || (wi.z > 0.0 && wi.dot(wm) <= 0.0)
+    {
+        return None;
+    }
+    return Some(wm);
+}
+
+// |dwm / dwi| of refracting through `wm`, None where it blows up.
+#[inline]
+fn refraction_jacobian(
+    wo: Vector3<f32>,
+    wi: Vector3<f32>,
+    wm: Vector3<f32>,
+    eta: f32,
+) -> Option<f32> {
+    let denominator = wi.dot(wm) + wo.dot(wm) / eta;
+    let denominator_2 = denominator * denominator;
+    if denominator_2 < 1e-12 {
+        return None;
+    }
+    return Some(wi.dot(wm).abs() / denominator_2);
+}
+
+// The part of the light refracted between `wo` and `wi` through `wm`. Fresnel is the same seen
+// from either side, but close to total internal reflection it only stays accurate seen from the
+// side with the lower index of refraction, where the angle is grazing instead of critical.
+#[inline]
+fn fresnel_transmittance(wo: Vector3<f32>, wi: Vector3<f32>, wm: Vector3<f32>, eta: f32) -> f32 {
+    let (cos_i, eta) = if eta < 1.0 {
+        (-wi.dot(wm), 1.0 / eta)
+    } else {
+        (wo.dot(wm), eta)
+    };
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos_t = (1.0 - (1.0 - cos_i * cos_i) / (eta * eta)).sqrt();
+    // 1 - r² of both polarizations, written out so that nothing cancels at grazing angles.
+    let parallel = eta * cos_i + cos_t;
+    let perpendicular = cos_i + eta * cos_t;
+    return 2.0
+        * eta
+        * cos_i
+        * cos_t
+        * (1.0 / (parallel * parallel) + 1.0 / (perpendicular * perpendicular));
+}
+
+// GGX distribution of microfacet normals, in the local frame.
+#[inline]
+fn ggx_d(wm: Vector3<f32>, alpha: f32) -> f32 {
+    let alpha_2 = alpha * alpha;
+    let t = (wm.x * wm.x + wm.y * wm.y) / alpha_2 + wm.z * wm.z;
+    return 1.0 / (PI * alpha_2 * t * t);
+}
+
+// Smith's auxiliary function, how much of the microsurface hides itself seen from `w`.
+#[inline]
+fn smith_lambda(w: Vector3<f32>, alpha: f32) -> f32 {
+    let cos_2 = w.z * w.z;
+    if cos_2 == 0.0 {
+        return f32::INFINITY;
+    }
+    let tan_2 = (w.x * w.x + w.y * w.y) / cos_2;
+    return ((1.0 + alpha * alpha * tan_2).sqrt() - 1.0) / 2.0;
+}
+
+#[inline]
+fn smith_g1(w: Vector3<f32>, alpha: f32) -> f32 {
+    return 1.0 / (1.0 + smith_lambda(w, alpha));
+}
+
+// Height correlated masking and shadowing.
+#[inline]
+fn smith_g2(wo: Vector3<f32>, wi: Vector3<f32>, alpha: f32) -> f32 {
+    return 1.0 / (1.0 + smith_lambda(wo, alpha) + smith_lambda(wi, alpha));
+}
+
+// Density of the normals visible from `wo`, which `sample_visible_normal` follows.
+#[inline]
+fn visible_normal_pdf(wo: Vector3<f32>, wm: Vector3<f32>, alpha: f32) -> f32 {
+    return smith_g1(wo, alpha) * wo.dot(wm).max(0.0) * ggx_d(wm, alpha) / wo.z;
+}
+
+// Samples a microfacet normal visible from `wo` (Heitz 2018, "Sampling the GGX Distribution
+// of Visible Normals"): the view is stretched to where the microsurface is a hemisphere, and
+// the part of the projected disk facing the view is sampled.
+fn sample_visible_normal(wo: Vector3<f32>, alpha: f32, u1: f32, u2: f32) -> Vector3<f32> {
+    let view = Vector3::new(alpha * wo.x, alpha * wo.y, wo.z).normalize();
+
+    let length_2 = view.x * view.x + view.y * view.y;
+    let t1 = if length_2 > 0.0 {
+        Vector3::new(-view.y, view.x, 0.0) / length_2.sqrt()
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = view.cross(t1);
+
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + view.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+    let normal = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * view;
+
+    return Vector3::new(alpha * normal.x, alpha * normal.y, normal.z.max(1e-6)).normalize();
+}
+
+#[inline]
+fn reflect(wo: Vector3<f32>, wm: Vector3<f32>) -> Vector3<f32> {
+    return 2.0 * wo.dot(wm) * wm - wo;
+}
+
+// Refracts `wo` through the microfacet `wm`, `eta` being the ratio of the index of refraction
+// past the surface to the one `wo` is in. None on total internal reflection.
+fn refract(wo: Vector3<f32>, wm: Vector3<f32>, eta: f32) -> Option<Vector3<f32>> {
+    let cos_i = wo.dot(wm);
+    let sin_2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin_2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin_2_t).sqrt();
+    return Some(-wo / eta + (cos_i / eta - cos_t) * wm);
+}
+
+// Unpolarized Fresnel reflectance of a dielectric, light arriving at `cos_i` from the side
+// where the ratio of indices of refraction (other side / this side) is `eta`.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin_2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin_2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    return (parallel * parallel + perpendicular * perpendicular) / 2.0;
+}
+
+// Unpolarized Fresnel reflectance of a conductor with complex index of refraction eta + ik,
+// in air.
+pub fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos_2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin_2 = 1.0 - cos_2;
+    let eta_2 = eta * eta;
+    let k_2 = k * k;
+
+    let t0 = eta_2 - k_2 - sin_2;
+    let a_2_plus_b_2 = (t0 * t0 + 4.0 * eta_2 * k_2).sqrt();
+    let t1 = a_2_plus_b_2 + cos_2;
+    let a = (0.5 * (a_2_plus_b_2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_2.sqrt() * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos_2 * a_2_plus_b_2 + sin_2 * sin_2;
+    let t4 = t2 * sin_2;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+    return (parallel + perpendicular) / 2.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks the sampled directions on the `side` of the surface (+1 reflected, -1 refracted)
+    // against eval and pdf, returns how many there were.
+    fn check_samples(bsdf: &dyn Bsdf, side: f32, samples: usize) -> usize {
+        let wo = Vector3::new(0.3, -0.5, 0.8).normalize();
+        let mut checked = 0;
+        for _ in 0..samples {
+            let Some(sample) = bsdf.sample(wo) else {
+                continue;
+            };
+            if sample.wi.z * side <= 0.0 {
+                continue;
+            }
+            let pdf = bsdf.pdf(wo, sample.wi);
+            let expected = bsdf.eval(wo, sample.wi) / pdf;
+            assert!((pdf - sample.pdf).abs() <= 1e-3 * pdf);
+            assert!((sample.weight - expected).magnitude() < 1e-3);
+            checked += 1;
+        }
+        return checked;
+    }
+
+    #[test]
+    fn samples_agree_with_eval_and_pdf() {
+        let (eta, k) = metal_preset("gold").unwrap();
+        assert!(check_samples(&Conductor::new(eta, k, 0.5), 1.0, 1000) > 0);
+    }
+
+    #[test]
+    fn rough_dielectric_reflections_agree_with_eval_and_pdf() {
+        // Entering and leaving glass.
+        for ior in [1.5, 1.0 / 1.5] {
+            assert!(check_samples(&RoughDielectric::new(ior, 0.4), 1.0, 10000) > 100);
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_refractions_agree_with_eval_and_pdf() {
+        for ior in [1.5, 1.0 / 1.5] {
+            assert!(check_samples(&RoughDielectric::new(ior, 0.4), -1.0, 10000) > 100);
+        }
+    }
+
+    #[test]
+    fn gold_reflects_more_red_than_blue() {
+        let (eta, k) = metal_preset("gold").unwrap();
+        let red = fresnel_conductor(1.0, eta.x, k.x);
+        let blue = fresnel_conductor(1.0, eta.z, k.z);
+        assert!(red > 0.9 && blue < 0.5);
+        // At grazing angles every metal becomes a perfect mirror.
+        assert!(fresnel_conductor(0.0, eta.z, k.z) > 0.999);
+        // Glass reflects 4% head on.
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,602 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::{ElementWise, InnerSpace, Vector3};
+use rand::Rng;
+
+use crate::materials::bsdf::{Bsdf, BsdfFlags, BsdfSample};
+use crate::materials::material::{
+    dielectric_direction, lambertian_eval, lambertian_pdf, mirror, reflectance_schlick_approx,
+};
+use crate::materials::normal_map::NormalMap;
+use crate::materials::texture::{ImageTexture, Texture, WrapMode};
+use crate::object::object::Hit;
+use crate::utils::vector_utils::{orthonormal_basis, random_point_in_unit_sphere};
+
+/**
+ * A material from a Wavefront .mtl library.
+ * Only the parts of the format that describe how light is scattered are kept.
+ */
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub diffuse: Vector3<f32>,             // Kd
+    pub specular: Vector3<f32>,            // Ks
+    pub specular_exponent: f32,            // Ns
+    pub emission: Vector3<f32>,            // Ke
+    pub ior: f32,                          // Ni
+    pub dissolve: f32,                     // d, or 1 - Tr
+    pub transmission_filter: Vector3<f32>, // Tf
+    pub illumination: u32,                 // illum
+    // Physically based extension, materials using it are rendered as principled ones.
+    pub roughness: Option<f32>,           // Pr
+    pub metallic: Option<f32>,            // Pm
+    pub sheen: Option<f32>,               // Ps
+    pub clearcoat: Option<f32>,           // Pc
+    pub clearcoat_roughness: Option<f32>, // Pcr
+    // Texture maps, multiplied with the matching color.
+    pub diffuse_map: Option<Texture>,  // map_Kd
+    pub specular_map: Option<Texture>, // map_Ks
+    pub emission_map: Option<Texture>, // map_Ke
+    pub normal_map: Option<NormalMap>, // norm, map_Bump
+    pub alpha_map: Option<Texture>,    // map_d, cutouts rather than a dissolve
+}
+
+impl MtlMaterial {
+    pub fn new(name: &str) -> MtlMaterial {
+        return MtlMaterial {
+            name: name.to_string(),
+            diffuse: Vector3::new(0.8, 0.8, 0.8),
+            specular: Vector3::new(0.0, 0.0, 0.0),
+            specular_exponent: 1.0,
+            emission: Vector3::new(0.0, 0.0, 0.0),
+            ior: 1.5,
+            dissolve: 1.0,
+            transmission_filter: Vector3::new(1.0, 1.0, 1.0),
+            illumination: 2,
+            roughness: None,
+            metallic: None,
+            sheen: None,
+            clearcoat: None,
+            clearcoat_roughness: None,
+            diffuse_map: None,
+            specular_map: None,
+            emission_map: None,
+            normal_map: None,
+            alpha_map: None,
+        };
+    }
+
+    pub fn is_physically_based(&self) -> bool {
+        return self.roughness.is_some()
+            || self.metallic.is_some()
+            || self.sheen.is_some()
+            || self.clearcoat.is_some()
+            || self.clearcoat_roughness.is_some();
+    }
+}
+
+// Parses the contents of a .mtl file, errors are reported as `file:line: message`.
+// Statements which don't affect rendering (Ka, ...) are skipped.
+// Texture maps are loaded relative to `base_directory`.
+pub fn parse_mtl(
+    source: &str,
+    file_name: &str,
+    base_directory: &Path,
+) -> Result<Vec<MtlMaterial>, String> {
+    let mut parser = MtlParser {
+        base_directory,
+        materials: Vec::new(),
+        images: HashMap::new(),
+    };
+
+    for (index, line) in source.lines().enumerate() {
+        let statement = line.split('#').next().unwrap_or("");
+        parser
+            .parse_statement(statement)
+            .map_err(|e| format!("{}:{}: {}", file_name, index + 1, e))?;
+    }
+
+    return Ok(parser.materials);
+}
+
+struct MtlParser<'a> {
+    base_directory: &'a Path,
+    materials: Vec<MtlMaterial>,
+    // Images already loaded, by file name and what they hold, materials often share them.
+    images: HashMap<(String, MapKind), Arc<ImageTexture>>,
+}
+
+// What the image of a map holds. Only colors are gamma decoded, and opacities come from the alpha
+// channel when there is one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum MapKind {
+    Color,
+    Data,
+    Opacity,
+}
+
+impl MtlParser<'_> {
+    fn parse_statement(&mut self, statement: &str) -> Result<(), String> {
+        let mut tokens = statement.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return Ok(()),
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            let name = arguments.join(" ");
+            if name.is_empty() {
+                return Err("Expected a material name".to_string());
+            }
+            self.materials.push(MtlMaterial::new(&name));
+            return Ok(());
+        }
+
+        if self.materials.is_empty() {
+            return Err(format!("'{}' appears before any newmtl", keyword));
+        }
+
+        let map = match keyword {
+            "map_Kd" | "map_Ks" | "map_Ke" => {
+                self.parse_map(keyword, &arguments, MapKind::Color)?
+            }
+            "norm" | "map_Bump" | "map_bump" | "bump" => {
+                self.parse_map(keyword, &arguments, MapKind::Data)?
+            }
+            "map_d" => self.parse_map(keyword, &arguments, MapKind::Opacity)?,
+            _ => None,
+        };
+
+        let material = self.materials.last_mut().unwrap();
+        match keyword {
+            "Kd" => material.diffuse = parse_color(keyword, &arguments)?,
+            "Ks" => material.specular = parse_color(keyword, &arguments)?,
+            "Ke" => material.emission = parse_color(keyword, &arguments)?,
+            "Tf" => material.transmission_filter = parse_color(keyword, &arguments)?,
+            "Ns" => material.specular_exponent = parse_number(keyword, &arguments)?.max(0.0),
+            "Ni" => material.ior = parse_number(keyword, &arguments)?,
+            "d" => material.dissolve = parse_number(keyword, &arguments)?.clamp(0.0, 1.0),
+            "Tr" => material.dissolve = 1.0 - parse_number(keyword, &arguments)?.clamp(0.0, 1.0),
+            "Pr" => material.roughness = Some(parse_number(keyword, &arguments)?.clamp(0.0, 1.0)),
+            "Pm" => material.metallic = Some(parse_number(keyword, &arguments)?.clamp(0.0, 1.0)),
+            "Ps" => material.sheen = Some(parse_number(keyword, &arguments)?.max(0.0)),
+            "Pc" => material.clearcoat = Some(parse_number(keyword, &arguments)?.clamp(0.0, 1.0)),
+            "Pcr" => {
+                material.clearcoat_roughness =
+                    Some(parse_number(keyword, &arguments)?.clamp(0.0, 1.0))
+            }
+            "illum" => {
+                let value = arguments.first().copied().unwrap_or("");
+                material.illumination = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|model| *model <= 10)
+                    .ok_or(format!(
+                        "Expected an illumination model between 0 and 10, got '{}'",
+                        value
+                    ))?;
+            }
+            "map_Kd" => material.diffuse_map = map,
+            "map_Ks" => material.specular_map = map,
+            "map_Ke" => material.emission_map = map,
+            "map_d" => material.alpha_map = map,
+            "norm" => material.normal_map = map.map(|map| NormalMap::Normal(map, 1.0)),
+            // Bump maps are heights, but many exporters put normal maps there too.
+            "map_Bump" | "map_bump" | "bump" => {
+                let multiplier = bump_multiplier(&arguments);
+                material.normal_map = map.map(|map| match &map {
+                    Texture::Image(image, _) if image.looks_like_normal_map() => {
+                        NormalMap::Normal(map, multiplier)
+                    }
+                    _ => NormalMap::Bump(map, multiplier),
+                });
+            }
+            _ => {}
+        }
+
+        return Ok(());
+    }
+
+    // `map_xx [-option values...] file`. Only -clamp is used, the other options are skipped
+    // (apart from -bm, see `bump_multiplier`).
+    // Images which can't be loaded are reported and left out, like missing material libraries.
+    fn parse_map(
+        &mut self,
+        keyword: &str,
+        arguments: &[&str],
+        kind: MapKind,
+    ) -> Result<Option<Texture>, String> {
+        let mut wrap = WrapMode::Repeat;
+        let mut index = 0;
+        while index < arguments.len() && arguments[index].starts_with('-') {
+            let option = arguments[index];
+            index += 1;
+            match option {
+                "-clamp" => {
+                    if arguments.get(index) == Some(&"on") {
+                        wrap = WrapMode::Clamp;
+                    }
+                    index += 1;
+                }
+                "-blendu" | "-blendv" | "-cc" | "-boost" | "-bm" | "-texres" | "-imfchan" => {
+                    index += 1
+                }
+                "-mm" => index += 2,
+                // Up to three numbers.
+                "-o" | "-s" | "-t" => {
+                    let mut count = 0;
+                    while count < 3
+                        && index < arguments.len() - 1
+                        && arguments[index].parse::<f32>().is_ok()
+                    {
+                        index += 1;
+                        count += 1;
+                    }
+                }
+                _ => return Err(format!("Unknown option '{}' for {}", option, keyword)),
+            }
+        }
+
+        let file = arguments[index.min(arguments.len())..].join(" ");
+        if file.is_empty() {
+            return Err(format!("Expected a file name for {}", keyword));
+        }
+
+        let key = (file, kind);
+        if !self.images.contains_key(&key) {
+            let path = self.base_directory.join(&key.0);
+            let image = match kind {
+                MapKind::Color => ImageTexture::load(&path, true),
+                MapKind::Data => ImageTexture::load(&path, false),
+                MapKind::Opacity => ImageTexture::load_opacity(&path),
+            };
+            match image {
+                Ok(image) => {
+                    self.images.insert(key.clone(), Arc::new(image));
+                }
+                Err(e) => {
+                    eprintln!("{}, rendering without it", e);
+                    return Ok(None);
+                }
+            }
+        }
+
+        return Ok(Some(Texture::Image(self.images[&key].clone(), wrap)));
+    }
+}
+
+// The `-bm` option of a bump map, how far the heights move the surface.
+fn bump_multiplier(arguments: &[&str]) -> f32 {
+    return arguments
+        .iter()
+        .position(|argument| *argument == "-bm")
+        .and_then(|index| arguments.get(index + 1)?.parse::<f32>().ok())
+        .unwrap_or(1.0);
+}
+
+// Colors are either `r g b`, or a single value for grey.
+fn parse_color(keyword: &str, arguments: &[&str]) -> Result<Vector3<f32>, String> {
+    let values = arguments
+        .iter()
+        .map(|argument| argument.parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|_| {
+            format!(
+                "Expected a color for {}, got '{}'",
+                keyword,
+                arguments.join(" ")
+            )
+        })?;
+
+    return match values[..] {
+        [r, g, b] => Ok(Vector3::new(r, g, b)),
+        [grey] => Ok(Vector3::new(grey, grey, grey)),
+        _ => Err(format!(
+            "Expected a color for {}, got '{}'",
+            keyword,
+            arguments.join(" ")
+        )),
+    };
+}
+
+fn parse_number(keyword: &str, arguments: &[&str]) -> Result<f32, String> {
+    return match arguments {
+        [value] => value
+            .parse::<f32>()
+            .map_err(|_| format!("Expected a number for {}, got '{}'", keyword, value)),
+        _ => Err(format!(
+            "Expected a number for {}, got '{}'",
+            keyword,
+            arguments.join(" ")
+        )),
+    };
+}
+
+// How the illumination models map onto scattering:
+//  0, 1     diffuse only
+//  2, 10    diffuse and a glossy (Phong) highlight
+//  3, 8     diffuse and a mirror reflection
+//  5        diffuse and a mirror reflection weighted by Fresnel
+//  4, 9     like 3, but the transparent part of the surface is glass
+//  6, 7     like 3 and 5, but the transparent part of the surface is glass
+// Whatever is not dissolved (`d`) is diffuse and specular, Kd and Ks are rescaled
+// if they add up to more than one. The rest passes through the surface, straight
+// for most models, refracted by Ni for the glass ones.
+struct Lobes {
+    diffuse: Vector3<f32>,
+    specular: Vector3<f32>,
+    diffuse_probability: f32,
+    specular_probability: f32,
+    mirror: bool,
+    fresnel: bool,
+    refractive: bool,
+}
+
+impl MtlMaterial {
+    fn lobes(&self, hit: &Hit) -> Lobes {
+        let diffuse = textured(self.diffuse, &self.diffuse_map, hit);
+        let specular = match self.illumination {
+            0 | 1 => Vector3::new(0.0, 0.0, 0.0),
+            _ => textured(self.specular, &self.specular_map, hit),
+        };
+
+        // Energy conservation, the surface can't reflect more than it receives.
+        let total = diffuse + specular;
+        let max_total = total.x.max(total.y).max(total.z);
+        let scale = if max_total > 1.0 {
+            1.0 / max_total
+        } else {
+            1.0
+        };
+        let diffuse = diffuse * scale * self.dissolve;
+        let specular = specular * scale * self.dissolve;
+
+        let diffuse_weight = luminance(diffuse);
+        let specular_weight = luminance(specular);
+        let total_weight = diffuse_weight + specular_weight;
+        let (diffuse_probability, specular_probability) = if total_weight > 0.0 {
+            (
+                self.dissolve * diffuse_weight / total_weight,
+                self.dissolve * specular_weight / total_weight,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+
+        return Lobes {
+            diffuse,
+            specular,
+            diffuse_probability,
+            specular_probability,
+            mirror: !matches!(self.illumination, 0 | 1 | 2 | 10),
+            fresnel: matches!(self.illumination, 5 | 7),
+            refractive: matches!(self.illumination, 4 | 6 | 7 | 9),
+        };
+    }
+
+    // The lobes of the material at a hit.
+    pub fn bsdf(&self, hit: &Hit) -> MtlBsdf<'_> {
+        return MtlBsdf {
+            material: self,
+            lobes: self.lobes(hit),
+            is_facing_you: hit.is_facing_you,
+        };
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        return self.emission != Vector3::new(0.0, 0.0, 0.0);
+    }
+
+    pub fn emit(&self, hit: &Hit) -> Vector3<f32> {
+        return textured(self.emission, &self.emission_map, hit);
+    }
+}
+
+// The lobes of an MTL material at a hit, see `MtlMaterial::bsdf`.
+pub struct MtlBsdf<'a> {
+    material: &'a MtlMaterial,
+    lobes: Lobes,
+    // Whether the ray comes from outside, for refracting into glass.
+    is_facing_you: bool,
+}
+
+impl Bsdf for MtlBsdf<'_> {
+    fn flags(&self) -> BsdfFlags {
+        let lobes = &self.lobes;
+        let is_transparent = self.material.dissolve < 1.0;
+        return BsdfFlags {
+            non_delta: lobes.diffuse_probability > 0.0
+                || (!lobes.mirror && lobes.specular_probability > 0.0),
+            delta: is_transparent || (lobes.mirror && lobes.specular_probability > 0.0),
+            transmission: is_transparent,
+            dispersion: false,
+        };
+    }
+
+    fn sample(&self, wo: Vector3<f32>) -> Option<BsdfSample> {
+        let material = self.material;
+        let lobes = &self.lobes;
+        let mut rng = rand::thread_rng();
+        let choice = rng.gen::<f32>();
+
+        if choice >= lobes.diffuse_probability + lobes.specular_probability {
+            // The transparent part of the surface.
+            let transparency = 1.0 - material.dissolve;
+            if transparency <= 0.0 {
+                return None;
+            }
+            let probability = 1.0 - lobes.diffuse_probability - lobes.specular_probability;
+            let wi = if lobes.refractive {
+                dielectric_direction(wo, material.ior, self.is_facing_you)
+            } else {
+                -wo
+            };
+            return Some(delta_sample(
+                wi,
+                material.transmission_filter * (transparency / probability),
+            ));
+        }
+
+        if lobes.mirror && choice >= lobes.diffuse_probability {
+            let mut weight = lobes.specular / lobes.specular_probability;
+            if lobes.fresnel {
+                weight *= reflectance_schlick_approx(wo.z.min(1.0), material.ior);
+            }
+            return Some(delta_sample(mirror(wo), weight));
+        }
+
+        let wi = if choice < lobes.diffuse_probability {
+            (Vector3::new(0.0, 0.0, 1.0) + random_point_in_unit_sphere().normalize()).normalize()
+        } else {
+            sample_phong_lobe(mirror(wo), material.specular_exponent)
+        };
+
+        let pdf = self.pdf(wo, wi);
+        if wi.z <= 0.0 || pdf <= 0.0 {
+            return None;
+        }
+
+        return Some(BsdfSample {
+            wi,
+            weight: self.eval(wo, wi) / pdf,
+            pdf,
+            is_delta: false,
+        });
+    }
+
+    // The diffuse and glossy parts, mirrors and transparency can't be evaluated.
+    fn eval(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> Vector3<f32> {
+        let lobes = &self.lobes;
+        let mut value = lambertian_eval(lobes.diffuse, wi);
+        if !lobes.mirror && wi.z > 0.0 {
+            value += lobes.specular
+                * (phong_lobe_value(mirror(wo), wi, self.material.specular_exponent) * wi.z);
+        }
+        return value;
+    }
+
+    fn pdf(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> f32 {
+        let lobes = &self.lobes;
+        let mut pdf = lobes.diffuse_probability * lambertian_pdf(wi);
+        if !lobes.mirror {
+            pdf += lobes.specular_probability
+                * phong_lobe_pdf(mirror(wo), wi, self.material.specular_exponent);
+        }
+        return pdf;
+    }
+}
+
+fn delta_sample(wi: Vector3<f32>, weight: Vector3<f32>) -> BsdfSample {
+    return BsdfSample {
+        wi,
+        weight,
+        pdf: 0.0,
+        is_delta: true,
+    };
+}
+
+// Normalized modified Phong lobe around the mirror direction (Lafortune and Willems 1994),
+// to be multiplied by Ks.
+#[inline]
+fn phong_lobe_value(reflected: Vector3<f32>, direction: Vector3<f32>, exponent: f32) -> f32 {
+    let cos_alpha = reflected.dot(direction.normalize());
+    if cos_alpha <= 0.0 {
+        return 0.0;
+    }
+    return (exponent + 2.0) / (2.0 * PI) * cos_alpha.powf(exponent);
+}
+
+#[inline]
+fn phong_lobe_pdf(reflected: Vector3<f32>, direction: Vector3<f32>, exponent: f32) -> f32 {
+    let cos_alpha = reflected.dot(direction.normalize());
+    if cos_alpha <= 0.0 {
+        return 0.0;
+    }
+    return (exponent + 1.0) / (2.0 * PI) * cos_alpha.powf(exponent);
+}
+
+// Samples a direction proportionally to cos^exponent around the mirror direction.
+fn sample_phong_lobe(reflected: Vector3<f32>, exponent: f32) -> Vector3<f32> {
+    let mut rng = rand::thread_rng();
+    let cos_alpha = rng.gen::<f32>().powf(1.0 / (exponent + 1.0));
+    let sin_alpha = f32::max(0.0, 1.0 - cos_alpha * cos_alpha).sqrt();
+    let phi = 2.0 * PI * rng.gen::<f32>();
+
+    let (u, v) = orthonormal_basis(reflected);
+    return sin_alpha * (phi.cos() * u + phi.sin() * v) + cos_alpha * reflected;
+}
+
+#[inline]
+pub fn textured(color: Vector3<f32>, map: &Option<Texture>, hit: &Hit) -> Vector3<f32> {
+    return match map {
+        Some(texture) => color.mul_element_wise(texture.value(hit)),
+        None => color,
+    };
+}
+
+#[inline]
+pub fn luminance(color: Vector3<f32>) -> f32 {
+    return 0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_materials_in_any_order() {
+        let materials = parse_mtl(
+            "# Exported by hand
+            newmtl Glass
+            illum 7
+            Tr 0.9
+            Ni 1.45
+            Ks 1
+            map_Kd glass.png
+
+            newmtl Lamp
+            Ke 10 8 6
+            Kd 0.5 0.5 0.5
+
+            newmtl Brass
+            Kd 0.9 0.7 0.3
+            Pm 1
+            Pr 0.25",
+            "test.mtl",
+            Path::new(""),
+        )
+        .unwrap();
+
+        assert_eq!(materials.len(), 3);
+        assert_eq!(materials[0].name, "Glass");
+        assert_eq!(materials[0].illumination, 7);
+        assert!((materials[0].dissolve - 0.1).abs() < 1e-6);
+        assert_eq!(materials[0].ior, 1.45);
+        assert_eq!(materials[0].specular, Vector3::new(1.0, 1.0, 1.0));
+        assert!(!materials[0].is_emissive());
+        assert!(materials[1].is_emissive());
+        assert_eq!(materials[1].emission, Vector3::new(10.0, 8.0, 6.0));
+        assert!(!materials[1].is_physically_based());
+        assert!(materials[2].is_physically_based());
+        assert_eq!(materials[2].metallic, Some(1.0));
+        assert_eq!(materials[2].roughness, Some(0.25));
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let error = parse_mtl("newmtl A\nKd 1 1 1\nNs shiny", "test.mtl", Path::new("")).err();
+        assert_eq!(
+            error.unwrap(),
+            "test.mtl:3: Expected a number for Ns, got 'shiny'"
+        );
+        assert!(parse_mtl("Kd 1 1 1", "test.mtl", Path::new(""))
+            .err()
+            .unwrap()
+            .starts_with("test.mtl:1: "));
+        assert!(parse_mtl("newmtl A\nillum 11", "test.mtl", Path::new("")).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::object::object::Hit;
+use crate::utils::vector_utils::orthonormal_basis;
+
+use super::texture::Texture;
+
+// Step in texture coordinates for the finite differences of bump maps.
+const BUMP_DELTA: f32 = 5e-4;
+
+/**
+ * Detail added to the shading normal of a surface, without adding geometry.
+ */
+#[derive(Clone, Debug)]
+pub enum NormalMap {
+    // Normals in the tangent space of the surface (along dp/du, dp/dv and the normal), stored as
+    // colors, where (0.5, 0.5, 1) is the normal itself. The tilt is multiplied by the strength.
+    Normal(Texture, f32),
+    // Heights, the surface is moved along its normal by height * scale.
+    Bump(Texture, f32),
+}
+
+impl NormalMap {
+    // The shading normal at the hit, on the same side as `hit.normal`.
+    pub fn apply(&self, hit: &Hit) -> Vector3<f32> {
+        // Maps are defined on the outside of the surface.
+        let outward = if hit.is_facing_you {
+            hit.normal
+        } else {
+            -hit.normal
+        };
+
+        // Surfaces without texture coordinates still get a frame, for solid textures.
+        let (dpdu, dpdv) = if hit.dpdu.cross(hit.dpdv).magnitude2() > 0.0 {
+            (hit.dpdu, hit.dpdv)
+        } else {
+            orthonormal_basis(outward)
+        };
+        // The shading normal may not be perpendicular to the triangle's derivatives.
+        let dpdu = dpdu - outward * outward.dot(dpdu);
+        let dpdv = dpdv - outward * outward.dot(dpdv);
+
+        let normal = match self {
+            NormalMap::Normal(texture, strength) => {
+                if dpdu.magnitude2() == 0.0 {
+                    return hit.normal;
+                }
+                let tangent = dpdu.normalize();
+                // Texture coordinates may be mirrored, the bitangent follows v.
+                let mut bitangent = outward.cross(tangent);
+                if bitangent.dot(dpdv) < 0.0 {
+                    bitangent = -bitangent;
+                }
+
+                let color = texture.value(hit);
+                let x = (2.0 * color.x - 1.0) * strength;
+                let y = (2.0 * color.y - 1.0) * strength;
+                let z = 2.0 * color.z - 1.0;
+                tangent * x + bitangent * y + outward * z
+            }
+            NormalMap::Bump(texture, scale) => {
+                let height_at = |du: f32, dv: f32| {
+                    let shifted = Hit {
+                        point: hit.point + dpdu * du + dpdv * dv,
+                        uv: hit.uv + Vector2::new(du, dv),
+                        ..*hit
+                    };
+                    return texture.scalar(&shifted);
+                };
+                let height = height_at(0.0, 0.0);
+                let dhdu = (height_at(BUMP_DELTA, 0.0) - height) / BUMP_DELTA * scale;
+                let dhdv = (height_at(0.0, BUMP_DELTA) - height) / BUMP_DELTA * scale;
+
+                let normal = (dpdu + outward * dhdu).cross(dpdv + outward * dhdv);
+                if normal.dot(outward) < 0.0 {
+                    -normal
+                } else {
+                    normal
+                }
+            }
+        };
+
+        if normal.magnitude2() == 0.0 || !normal.x.is_finite() {
+            return hit.normal;
+        }
+        if hit.is_facing_you {
+            return normal.normalize();
+        }
+        return -normal.normalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::material::Material;
+    use crate::materials::procedural::{Pattern, PatternKind};
+    use crate::object::object::PrimitiveId;
+    use std::sync::Arc;
+
+    #[test]
+    fn tilts_along_the_surface_derivatives() {
+        let material = Material::Diffuse(Vector3::new(0.0, 0.0, 0.0));
+        let hit = Hit {
+            point_at_intersection: 1.0,
+            point: Vector3::new(0.0, 0.0, 0.0),
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            geometric_normal: Vector3::new(0.0, 0.0, 1.0),
+            is_facing_you: true,
+            uv: Vector2::new(0.5, 0.5),
+            dpdu: Vector3::new(2.0, 0.0, 0.0),
+            // Mirrored texture coordinates.
+            dpdv: Vector3::new(0.0, -2.0, 0.0),
+            material: &material,
+            primitive: PrimitiveId::default(),
+        };
+
+        // Leaning towards +u and +v.
+        let tilted = NormalMap::Normal(Texture::Constant(Vector3::new(1.0, 1.0, 1.0)), 1.0);
+        let normal = tilted.apply(&hit);
+        assert!((normal.magnitude() - 1.0).abs() < 1e-5);
+        assert!(normal.x > 0.5 && normal.y < -0.5 && normal.z > 0.5);
+
+        // Heights rising along u tip the normal back towards -u.
+        let ramp = Pattern {
+            space: crate::materials::procedural::PatternSpace::Solid,
+            ..Pattern::new(
+                PatternKind::Gradient,
+                Texture::uniform(0.0),
+                Texture::uniform(1.0),
+            )
+        };
+        let mut sloped = hit;
+        sloped.dpdu = Vector3::new(0.0, 2.0, 0.0);
+        sloped.dpdv = Vector3::new(2.0, 0.0, 0.0);
+        sloped.point = Vector3::new(0.0, 0.5, 0.0);
+        let bump = NormalMap::Bump(Texture::Pattern(Arc::new(ramp)), 1.0);
+        let normal = bump.apply(&sloped);
+        assert!(normal.y < -0.5 && normal.z > 0.3);
+
+        // Seen from behind, the normal stays on the ray's side.
+        let mut behind = hit;
+        behind.is_facing_you = false;
+        behind.normal = -hit.normal;
+        assert!(tilted.apply(&behind).z < 0.0);
+    }
+}
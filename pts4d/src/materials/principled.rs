@@ -0,0 +1,419 @@
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector3};
+use rand::Rng;
+
+use crate::object::object::Hit;
+use crate::utils::vector_utils::random_unit_vector;
+
+use super::bsdf::{Bsdf, BsdfFlags, BsdfSample};
+use super::microfacet::{
+    ggx_reflection_eval, ggx_reflection_pdf, sample_ggx_reflection, RoughDielectric, MIN_ALPHA,
+};
+use super::mtl::{luminance, textured, MtlMaterial};
+use super::normal_map::NormalMap;
+use super::texture::Texture;
+
+/**
+ * A principled (Disney style) uber material, layering a diffuse base with sheen, a specular
+ * reflection, rough glass and a clearcoat on top, all driven by a few artist friendly parameters.
+ * It is what physically based materials of MTL and glTF files are turned into.
+ */
+#[derive(Clone, Debug)]
+pub struct Principled {
+    pub base_color: Vector3<f32>,
+    pub metallic: f32,
+    // Perceptual roughness, squared into the GGX alpha.
+    pub roughness: f32,
+    // Reflectance of dielectrics at normal incidence, 0.5 is the usual 4%.
+    pub specular: f32,
+    // Soft, fabric-like reflection at grazing angles, tinted towards the base color by sheen_tint.
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    // A colorless varnish on top of everything else.
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    // How much of the dielectric base is glass rather than diffuse.
+    pub transmission: f32,
+    pub ior: f32,
+    pub emission: Vector3<f32>,
+    // Texture maps, multiplied with the matching parameters.
+    pub base_color_map: Option<Texture>,
+    // Roughness in the green channel and metallic in the blue one, as in glTF.
+    pub metallic_roughness_map: Option<Texture>,
+    pub roughness_map: Option<Texture>,
+    pub emission_map: Option<Texture>,
+    pub normal_map: Option<NormalMap>,
+    // Cut out parts of meshes, see `Material::is_opaque_at`.
+    pub alpha_map: Option<Texture>,
+}
+
+// The parameters of the material at a hit, with the maps applied, and how likely each lobe is sampled.
+struct Lobes {
+    base_color: Vector3<f32>,
+    alpha: f32,
+    // Reflectance at normal incidence of the specular lobe, and of its part over the dielectric base.
+    specular_color: Vector3<f32>,
+    dielectric_specular: f32,
+    // Seen from the side of the hit.
+    glass: RoughDielectric,
+    diffuse_weight: f32,
+    specular_weight: f32,
+    transmission_weight: f32,
+    // Probabilities of sampling the diffuse, specular, glass and clearcoat lobes.
+    probabilities: [f32; 4],
+}
+
+impl Principled {
+    pub fn new(base_color: Vector3<f32>) -> Principled {
+        return Principled {
+            base_color,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            transmission: 0.0,
+            ior: 1.5,
+            emission: Vector3::new(0.0, 0.0, 0.0),
+            base_color_map: None,
+            metallic_roughness_map: None,
+            roughness_map: None,
+            emission_map: None,
+            normal_map: None,
+            alpha_map: None,
+        };
+    }
+
+    // Physically based MTL materials (Pr, Pm, Ps, Pc, Pcr). Kd is the base color, Ks the specular
+    // level unless it is left black, and the transparent part of glass illumination models is
+    // the transmission.
+    pub fn from_mtl(mtl: &MtlMaterial) -> Principled {
+        let mut principled = Principled::new(mtl.diffuse);
+        principled.base_color_map = mtl.diffuse_map.clone();
+        principled.metallic = mtl.metallic.unwrap_or(0.0);
+        // Without a roughness, the Phong exponent is converted into one. A Blinn-Phong lobe of
+        // exponent Ns is about as wide as a microfacet one of alpha = sqrt(2 / (Ns + 2)), which
+        // is the square of the roughness.
+        principled.roughness = mtl
+            .roughness
+            .unwrap_or((2.0 / (mtl.specular_exponent + 2.0)).powf(0.25));
+        if mtl.specular != Vector3::new(0.0, 0.0, 0.0) {
+            principled.specular = luminance(mtl.specular);
+        }
+        principled.sheen = mtl.sheen.unwrap_or(0.0);
+        principled.clearcoat = mtl.clearcoat.unwrap_or(0.0);
+        principled.clearcoat_roughness = mtl.clearcoat_roughness.unwrap_or(0.03);
+        if matches!(mtl.illumination, 4 | 6 | 7 | 9) {
+            principled.transmission = 1.0 - mtl.dissolve;
+        }
+        principled.ior = mtl.ior;
+        principled.emission = mtl.emission;
+        principled.emission_map = mtl.emission_map.clone();
+        principled.normal_map = mtl.normal_map.clone();
+        principled.alpha_map = mtl.alpha_map.clone();
+        return principled;
+    }
+
+    fn lobes(&self, hit: &Hit) -> Lobes {
+        let base_color = textured(self.base_color, &self.base_color_map, hit);
+        let (mut roughness, metallic) = match &self.metallic_roughness_map {
+            Some(map) => {
+                let value = map.value(hit);
+                (self.roughness * value.y, self.metallic * value.z)
+            }
+            None => (self.roughness, self.metallic),
+        };
+        if let Some(map) = &self.roughness_map {
+            roughness *= map.scalar(hit);
+        }
+        let metallic = metallic.clamp(0.0, 1.0);
+        let alpha = roughness.clamp(0.0, 1.0).powi(2).max(MIN_ALPHA);
+
+        // Metals are all specular, the rest of the base splits into diffuse and glass.
+        let dielectric_specular = 0.08 * self.specular;
+        let specular_color = Vector3::new(
+            dielectric_specular,
+            dielectric_specular,
+            dielectric_specular,
+        ) * (1.0 - metallic)
+            + base_color * metallic;
+        let transmission_weight = (1.0 - metallic) * self.transmission.clamp(0.0, 1.0);
+        let diffuse_weight = (1.0 - metallic) - transmission_weight;
+        let specular_weight = 1.0 - transmission_weight;
+
+        let total = diffuse_weight + specular_weight + transmission_weight + self.clearcoat;
+        return Lobes {
+            base_color,
+            alpha,
+            specular_color,
+            dielectric_specular,
+            glass: RoughDielectric {
+                ior: self.ior,
+                alpha,
+            }
+            .seen_from(hit),
+            diffuse_weight,
+            specular_weight,
+            transmission_weight,
+            probabilities: [
+                diffuse_weight / total,
+                specular_weight / total,
+                transmission_weight / total,
+                self.clearcoat / total,
+            ],
+        };
+    }
+
+    #[inline]
+    fn clearcoat_alpha(&self) -> f32 {
+        return self
+            .clearcoat_roughness
+            .clamp(0.0, 1.0)
+            .powi(2)
+            .max(MIN_ALPHA);
+    }
+
+    // The lobes of the material at a hit.
+    pub fn bsdf(&self, hit: &Hit) -> PrincipledBsdf<'_> {
+        return PrincipledBsdf {
+            material: self,
+            lobes: self.lobes(hit),
+        };
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        return self.emission != Vector3::new(0.0, 0.0, 0.0);
+    }
+
+    pub fn emit(&self, hit: &Hit) -> Vector3<f32> {
+        return textured(self.emission, &self.emission_map, hit);
+    }
+}
+
+// The lobes of a principled material at a hit, see `Principled::bsdf`.
+pub struct PrincipledBsdf<'a> {
+    material: &'a Principled,
+    lobes: Lobes,
+}
+
+impl Bsdf for PrincipledBsdf<'_> {
+    fn flags(&self) -> BsdfFlags {
+        return BsdfFlags {
+            non_delta: true,
+            delta: false,
+            transmission: self.lobes.transmission_weight > 0.0,
+            dispersion: false,
+        };
+    }
+
+    fn sample(&self, wo: Vector3<f32>) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let lobes = &self.lobes;
+        let [diffuse, specular, transmission, _] = lobes.probabilities;
+        let choice = rand::thread_rng().gen::<f32>();
+
+        let wi = if choice < diffuse {
+            // A point on the unit sphere tangent to the surface gives a cosine weighted direction.
+            (Vector3::new(0.0, 0.0, 1.0) + random_unit_vector()).normalize()
+        } else if choice < diffuse + specular {
+            sample_ggx_reflection(wo, lobes.alpha)?
+        } else if choice < diffuse + specular + transmission {
+            lobes.glass.sample(wo)?.wi
+        } else {
+            sample_ggx_reflection(wo, self.material.clearcoat_alpha())?
+        };
+
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        return Some(BsdfSample {
+            wi,
+            weight: self.eval(wo, wi) / pdf,
+            pdf,
+            is_delta: false,
+        });
+    }
+
+    fn eval(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> Vector3<f32> {
+        let lobes = &self.lobes;
+        let material = self.material;
+        let mut value = Vector3::new(0.0, 0.0, 0.0);
+        if wo.z <= 0.0 {
+            return value;
+        }
+
+        if wi.z > 0.0 {
+            let wm = (wo + wi).normalize();
+            let cos_d = wi.dot(wm);
+
+            if lobes.diffuse_weight > 0.0 {
+                // Burley's diffuse, darker at grazing angles on smooth surfaces and brighter on rough ones.
+                let roughness = lobes.alpha.sqrt();
+                let fd90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+                let retro_reflection = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z))
+                    * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+
+                let base_luminance = luminance(lobes.base_color);
+                let tint = if base_luminance > 0.0 {
+                    lobes.base_color / base_luminance
+                } else {
+                    Vector3::new(1.0, 1.0, 1.0)
+                };
+                let sheen_color = Vector3::new(1.0, 1.0, 1.0) * (1.0 - material.sheen_tint)
+                    + tint * material.sheen_tint;
+                let sheen = sheen_color * (material.sheen * schlick_weight(cos_d));
+
+                // The diffuse base only gets the light the specular layer on top of it lets through.
+                let transmitted = (1.0 - lobes.dielectric_specular) * (1.0 - schlick_weight(cos_d));
+
+                value += (lobes.base_color * (retro_reflection / PI) + sheen)
+                    * (lobes.diffuse_weight * transmitted * wi.z);
+            }
+
+            let fresnel = lobes.specular_color
+                + (Vector3::new(1.0, 1.0, 1.0) - lobes.specular_color) * schlick_weight(wo.dot(wm));
+            value += fresnel * (lobes.specular_weight * ggx_reflection_eval(wo, wi, lobes.alpha));
+        }
+
+        if lobes.transmission_weight > 0.0 {
+            // Light going through glass is tinted by its color.
+            let glass = lobes.glass.eval_scalar(wo, wi) * lobes.transmission_weight;
+            value += if wi.z < 0.0 {
+                lobes.base_color * glass
+            } else {
+                Vector3::new(glass, glass, glass)
+            };
+        }
+
+        if material.clearcoat > 0.0 {
+            // Whatever the varnish reflects doesn't reach the layers below.
+            value *= 1.0 - material.clearcoat * clearcoat_fresnel(wo.z);
+            if wi.z > 0.0 {
+                let wm = (wo + wi).normalize();
+                let clearcoat = material.clearcoat
+                    * clearcoat_fresnel(wo.dot(wm))
+                    * ggx_reflection_eval(wo, wi, material.clearcoat_alpha());
+                value += Vector3::new(clearcoat, clearcoat, clearcoat);
+            }
+        }
+
+        return value;
+    }
+
+    fn pdf(&self, wo: Vector3<f32>, wi: Vector3<f32>) -> f32 {
+        let lobes = &self.lobes;
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+
+        let [diffuse, specular, transmission, clearcoat] = lobes.probabilities;
+        let mut pdf = 0.0;
+        if wi.z > 0.0 {
+            pdf += diffuse * wi.z / PI;
+            pdf += specular * ggx_reflection_pdf(wo, wi, lobes.alpha);
+            pdf += clearcoat * ggx_reflection_pdf(wo, wi, self.material.clearcoat_alpha());
+        }
+        if transmission > 0.0 {
+            pdf += transmission * lobes.glass.pdf(wo, wi);
+        }
+        return pdf;
+    }
+}
+
+#[inline]
+fn schlick_weight(cos_theta: f32) -> f32 {
+    return (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+}
+
+// The clearcoat is a varnish with an index of refraction of 1.5.
+#[inline]
+fn clearcoat_fresnel(cos_theta: f32) -> f32 {
+    return 0.04 + 0.96 * schlick_weight(cos_theta);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::material::Material;
+    use crate::object::object::PrimitiveId;
+    use cgmath::Vector2;
+
+    #[test]
+    fn converts_phong_exponents_into_roughness() {
+        for (exponent, alpha) in [
+            (0.0, 1.0),
+            (98.0, 0.02f32.sqrt()),
+            (1998.0, 0.001f32.sqrt()),
+        ] {
+            let mut mtl = MtlMaterial::new("phong");
+            mtl.specular_exponent = exponent;
+            let roughness = Principled::from_mtl(&mtl).roughness;
+            assert!((roughness * roughness - alpha).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn white_furnace_conserves_energy() {
+        // Lit by a white environment, no combination of lobes reflects more light than arrives.
+        let materials = [
+            Principled::new(Vector3::new(1.0, 1.0, 1.0)),
+            Principled {
+                metallic: 1.0,
+                roughness: 0.3,
+                ..Principled::new(Vector3::new(1.0, 1.0, 1.0))
+            },
+            Principled {
+                clearcoat: 1.0,
+                roughness: 0.5,
+                ..Principled::new(Vector3::new(1.0, 1.0, 1.0))
+            },
+            Principled {
+                transmission: 1.0,
+                roughness: 0.2,
+                ..Principled::new(Vector3::new(1.0, 1.0, 1.0))
+            },
+        ];
+
+        let material = Material::Diffuse(Vector3::new(0.0, 0.0, 0.0));
+        let hit = Hit {
+            point_at_intersection: 1.0,
+            point: Vector3::new(0.0, 0.0, 0.0),
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            geometric_normal: Vector3::new(0.0, 0.0, 1.0),
+            is_facing_you: true,
+            uv: Vector2::new(0.0, 0.0),
+            dpdu: Vector3::new(0.0, 0.0, 0.0),
+            dpdv: Vector3::new(0.0, 0.0, 0.0),
+            material: &material,
+            primitive: PrimitiveId::default(),
+        };
+        let wo = Vector3::new(0.0, 0.6, 0.8);
+
+        for principled in &materials {
+            let bsdf = principled.bsdf(&hit);
+            let samples = 200000;
+            let mut total = 0.0;
+            for _ in 0..samples {
+                if let Some(sample) = bsdf.sample(wo) {
+                    total += sample.weight.y;
+                }
+            }
+            let albedo = total / samples as f32;
+            assert!(
+                // Up to Monte Carlo noise.
+                albedo > 0.5 && albedo < 1.01,
+                "{:?}: {}",
+                principled,
+                albedo
+            );
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::object::object::Hit;
+
+use super::texture::Texture;
+
+// Detail is added this many times, each time twice as fine and half as strong.
+const DEFAULT_OCTAVES: u32 = 6;
+
+// Where a pattern is evaluated.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum PatternSpace {
+    // On the texture coordinates of the surface, (u, v, 0).
+    #[default]
+    Uv,
+    // At the hit point in world space, as if the object was carved out of a block of it.
+    Solid,
+}
+
+impl PatternSpace {
+    pub fn from_name(name: &str) -> Result<PatternSpace, String> {
+        return match name {
+            "uv" => Ok(PatternSpace::Uv),
+            "solid" => Ok(PatternSpace::Solid),
+            _ => Err(format!(
+                "Unknown pattern space '{}', expected uv or solid",
+                name
+            )),
+        };
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PatternKind {
+    // Alternating unit cells.
+    Checker,
+    // Perlin noise.
+    Noise,
+    // Fractal Brownian motion, noise summed over octaves.
+    Fbm,
+    // Like fbm, but summing the absolute value of the noise, which gives sharp creases.
+    Turbulence,
+    // Stripes along x, warped by turbulence into veins.
+    Marble,
+    // Rings around the y axis, slightly wobbly.
+    Wood,
+    // From `low` at y = 0 to `high` at y = 1.
+    Gradient,
+}
+
+impl PatternKind {
+    pub fn from_name(name: &str) -> Result<PatternKind, String> {
+        return match name {
+            "checker" => Ok(PatternKind::Checker),
+            "noise" => Ok(PatternKind::Noise),
+            "fbm" => Ok(PatternKind::Fbm),
+            "turbulence" => Ok(PatternKind::Turbulence),
+            "marble" => Ok(PatternKind::Marble),
+            "wood" => Ok(PatternKind::Wood),
+            "gradient" => Ok(PatternKind::Gradient),
+            _ => Err(format!(
+                "Unknown pattern '{}', expected checker, noise, fbm, turbulence, marble, wood or gradient",
+                name
+            )),
+        };
+    }
+}
+
+/**
+ * A procedural pattern, blending between two textures (which may be patterns themselves)
+ * by a value between 0 and 1 computed from where the surface was hit.
+ */
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub space: PatternSpace,
+    // Coordinates are multiplied by it, larger scales make smaller features.
+    pub scale: f32,
+    pub octaves: u32,
+    pub low: Texture,
+    pub high: Texture,
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind, low: Texture, high: Texture) -> Pattern {
+        return Pattern {
+            kind,
+            space: PatternSpace::default(),
+            scale: 1.0,
+            octaves: DEFAULT_OCTAVES,
+            low,
+            high,
+        };
+    }
+
+    pub fn value(&self, hit: &Hit) -> Vector3<f32> {
+        let t = self.blend(hit);
+        // Checkers only need one side.
+        if t <= 0.0 {
+            return self.low.value(hit);
+        }
+        if t >= 1.0 {
+            return self.high.value(hit);
+        }
+        return self.low.value(hit) * (1.0 - t) + self.high.value(hit) * t;
+    }
+
+    // How far from `low` towards `high` the pattern is at the hit.
+    fn blend(&self, hit: &Hit) -> f32 {
+        let p = match self.space {
+            PatternSpace::Uv => Vector3::new(hit.uv.x, hit.uv.y, 0.0),
+            PatternSpace::Solid => hit.point,
+        } * self.scale;
+
+        let t = match self.kind {
+            PatternKind::Checker => {
+                let cell = p.x.floor() + p.y.floor() + p.z.floor();
+                cell.rem_euclid(2.0)
+            }
+            PatternKind::Noise => 0.5 * (perlin(p) + 1.0),
+            PatternKind::Fbm => 0.5 * (fbm(p, self.octaves) + 1.0),
+            PatternKind::Turbulence => turbulence(p, self.octaves),
+            PatternKind::Marble => {
+                0.5 * (1.0 + (PI * (p.x + 4.0 * turbulence(p, self.octaves))).sin())
+            }
+            PatternKind::Wood => {
+                let radius = Vector3::new(p.x, 0.0, p.z).magnitude();
+                (radius + 0.4 * fbm(p, self.octaves)).rem_euclid(1.0)
+            }
+            PatternKind::Gradient => p.y,
+        };
+        return t.clamp(0.0, 1.0);
+    }
+}
+
+// Ken Perlin's improved gradient noise, between about -1 and 1. It is 0 at every integer lattice
+// point and varies smoothly in between, the same everywhere for every run.
+pub fn perlin(p: Vector3<f32>) -> f32 {
+    let cell = Vector3::new(p.x.floor(), p.y.floor(), p.z.floor());
+    let d = p - cell;
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let offset = Vector3::new(dx as f32, dy as f32, dz as f32);
+        return gradient(lattice_hash(x + dx, y + dy, z + dz), d - offset);
+    };
+
+    let (u, v, w) = (fade(d.x), fade(d.y), fade(d.z));
+    let lerp = |t: f32, a: f32, b: f32| a + t * (b - a);
+    return lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+            lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+        ),
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+            lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+        ),
+    );
+}
+
+// Noise summed over octaves, normalized back to about -1 to 1.
+pub fn fbm(p: Vector3<f32>, octaves: u32) -> f32 {
+    let (mut sum, mut total, mut amplitude, mut frequency) = (0.0, 0.0, 1.0, 1.0);
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * perlin(p * frequency);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return sum / total;
+}
+
+// Absolute noise summed over octaves, between 0 and about 1.
+pub fn turbulence(p: Vector3<f32>, octaves: u32) -> f32 {
+    let (mut sum, mut total, mut amplitude, mut frequency) = (0.0, 0.0, 1.0, 1.0);
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * perlin(p * frequency).abs();
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return sum / total;
+}
+
+// 6t^5 - 15t^4 + 10t^3, which has no kinks at the cell borders.
+#[inline]
+fn fade(t: f32) -> f32 {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+// Stands in for Perlin's permutation table, so the noise doesn't repeat every 256 cells.
+#[inline]
+fn lattice_hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut hash = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0x5bd1_e995);
+    return hash ^ (hash >> 15);
+}
+
+// Dot product of `d` with one of the 12 directions to the edges of a cube, picked by `hash`.
+#[inline]
+fn gradient(hash: u32, d: Vector3<f32>) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { d.x } else { d.y };
+    let v = if h < 4 {
+        d.y
+    } else if h == 12 || h == 14 {
+        d.x
+    } else {
+        d.z
+    };
+    let u = if h & 1 == 0 { u } else { -u };
+    let v = if h & 2 == 0 { v } else { -v };
+    return u + v;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::material::Material;
+    use crate::object::object::PrimitiveId;
+    use cgmath::Vector2;
+
+    #[test]
+    fn noise_is_smooth_and_bounded() {
+        let mut previous = perlin(Vector3::new(0.0, 0.3, 0.7));
+        for i in 1..2000 {
+            let p = Vector3::new(i as f32 * 0.01, 0.3, 0.7);
+            let value = perlin(p);
+            assert!(value.abs() <= 1.05, "noise of {} at {:?}", value, p);
+            assert!((value - previous).abs() < 0.05);
+            assert!(turbulence(p, 4) >= 0.0);
+            previous = value;
+        }
+        assert_eq!(perlin(Vector3::new(3.0, -2.0, 5.0)), 0.0);
+    }
+
+    #[test]
+    fn checkers_alternate() {
+        let white = Texture::Constant(Vector3::new(1.0, 1.0, 1.0));
+        let black = Texture::Constant(Vector3::new(0.0, 0.0, 0.0));
+        let mut checker = Pattern::new(PatternKind::Checker, black, white);
+        checker.scale = 4.0;
+
+        let material = Material::Diffuse(Vector3::new(0.0, 0.0, 0.0));
+        let at = |u: f32, v: f32| {
+            let hit = Hit {
+                point_at_intersection: 1.0,
+                point: Vector3::new(0.0, 0.0, 0.0),
+                normal: Vector3::new(0.0, 0.0, 1.0),
+                geometric_normal: Vector3::new(0.0, 0.0, 1.0),
+                is_facing_you: true,
+                uv: Vector2::new(u, v),
+                dpdu: Vector3::new(0.0, 0.0, 0.0),
+                dpdv: Vector3::new(0.0, 0.0, 0.0),
+                material: &material,
+                primitive: PrimitiveId::default(),
+            };
+            return checker.value(&hit).x;
+        };
+        assert_eq!(at(0.1, 0.1), 0.0);
+        assert_eq!(at(0.3, 0.1), 1.0);
+        assert_eq!(at(0.3, 0.3), 0.0);
+    }
+}
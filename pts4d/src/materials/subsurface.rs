@@ -0,0 +1,167 @@
+use cgmath::{ElementWise, Vector3};
+use rand::Rng;
+
+use super::bsdf::{Bsdf, BsdfFlags, BsdfSample};
+use super::material::{mirror, Lambertian};
+use super::medium::Medium;
+use super::microfacet::fresnel_dielectric;
+
+/**
+ * Translucent materials like skin, wax or marble: light goes into them, scatters around in their
+ * interior medium (a random walk) and comes out somewhere else. Only meant for closed surfaces.
+ */
+#[derive(Clone, Debug)]
+pub struct Subsurface {
+    pub ior: f32,
+    medium: Medium,
+}
+
+impl Subsurface {
+    // `albedo` is the color of a thick piece of the material, `mean_free_path` the average
+    // distance light goes in between scattering, per color channel.
+    pub fn new(albedo: Vector3<f32>, mean_free_path: Vector3<f32>, ior: f32) -> Subsurface {
+        let extinction =
+            Vector3::new(1.0, 1.0, 1.0).div_element_wise(mean_free_path.map(|d| d.max(1e-6)));
+        let single_scattering = albedo.map(single_scattering_albedo);
+        return Subsurface {
+            ior,
+            medium: Medium {
+                absorption: (Vector3::new(1.0, 1.0, 1.0) - single_scattering)
+                    .mul_element_wise(extinction),
+                scattering: single_scattering.mul_element_wise(extinction),
+                anisotropy: 0.0,
+                density: None,
+            },
+        };
+    }
+
+    // What fills the inside, which the random walk goes through.
+    pub fn medium(&self) -> &Medium {
+        return &self.medium;
+    }
+
+    // Probability of light arriving at the surface from the inside at `cos` to be reflected back.
+    pub fn internal_reflectance(&self, cos: f32) -> f32 {
+        return fresnel_dielectric(cos, 1.0 / self.ior);
+    }
+
+    pub fn bsdf(&self) -> SubsurfaceBoundary {
+        return SubsurfaceBoundary { ior: self.ior };
+    }
+}
+
+// The albedo of a single scattering event for which a thick slab has the given (multiple
+// scattering) albedo, fitted by Chiang et al. ("Practical and Controllable Subsurface
+// Scattering for Production Path Tracing", 2016).
+fn single_scattering_albedo(albedo: f32) -> f32 {
+    let a = albedo.clamp(0.0, 0.999);
+    let fit = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+    return (1.0 - fit * fit).clamp(0.0, 1.0);
+}
+
+/**
+ * The surface of a subsurface material: a smooth specular reflection by Fresnel, and diffuse
+ * transmission into the material for the rest. Neither can be lit by shadow rays.
+ */
+pub struct SubsurfaceBoundary {
+    ior: f32,
+}
+
+impl Bsdf for SubsurfaceBoundary {
+    fn flags(&self) -> BsdfFlags {
+        return BsdfFlags {
+            non_delta: false,
+            delta: true,
+            transmission: true,
+            dispersion: false,
+        };
+    }
+
+    fn sample(&self, wo: Vector3<f32>) -> Option<BsdfSample> {
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        let wi = if rand::thread_rng().gen::<f32>() < fresnel_dielectric(wo.z, self.ior) {
+            mirror(wo)
+        } else {
+            let wi = Lambertian { albedo: white }.sample(wo)?.wi;
+            Vector3::new(wi.x, wi.y, -wi.z)
+        };
+
+        return Some(BsdfSample {
+            wi,
+            weight: white,
+            pdf: 0.0,
+            is_delta: true,
+        });
+    }
+
+    fn eval(&self, _wo: Vector3<f32>, _wi: Vector3<f32>) -> Vector3<f32> {
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+
+    fn pdf(&self, _wo: Vector3<f32>, _wi: Vector3<f32>) -> f32 {
+        return 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::medium::MediumEvent;
+    use cgmath::InnerSpace;
+
+    #[test]
+    fn thick_slabs_reflect_their_albedo() {
+        // Random walks into the half space below z = 0, without the boundary's Fresnel.
+        for albedo in [0.2, 0.5, 0.8] {
+            let subsurface = Subsurface::new(
+                Vector3::new(albedo, albedo, albedo),
+                Vector3::new(1.0, 1.0, 1.0),
+                1.0,
+            );
+            let medium = subsurface.medium();
+
+            let walks = 20000;
+            let mut reflected = 0.0;
+            for _ in 0..walks {
+                let mut point = Vector3::new(0.0, 0.0, 0.0);
+                let entry = Lambertian {
+                    albedo: Vector3::new(1.0, 1.0, 1.0),
+                }
+                .sample(Vector3::new(0.0, 0.0, 1.0))
+                .unwrap()
+                .wi;
+                let mut direction = Vector3::new(entry.x, entry.y, -entry.z);
+                let mut weight = 1.0;
+                for _ in 0..1000 {
+                    let to_surface = if direction.z > 0.0 {
+                        -point.z / direction.z
+                    } else {
+                        f32::INFINITY
+                    };
+                    match medium.sample_distance(point, direction, to_surface) {
+                        MediumEvent::Scattered {
+                            distance,
+                            weight: w,
+                        } => {
+                            weight *= w.x;
+                            point += distance * direction;
+                            direction = medium.sample_phase(direction).normalize();
+                        }
+                        MediumEvent::Passed { weight: w } => {
+                            reflected += weight * w.x;
+                            break;
+                        }
+                    }
+                }
+            }
+
+            let measured = reflected / walks as f32;
+            assert!(
+                (measured - albedo).abs() < 0.05,
+                "an albedo of {} reflected {}",
+                albedo,
+                measured
+            );
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::{Vector2, Vector3};
+
+use crate::object::object::Hit;
+
+use super::procedural::Pattern;
+
+// What happens to texture coordinates outside of [0, 1].
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    pub fn from_name(name: &str) -> Result<WrapMode, String> {
+        return match name {
+            "repeat" => Ok(WrapMode::Repeat),
+            "clamp" => Ok(WrapMode::Clamp),
+            "mirror" => Ok(WrapMode::Mirror),
+            _ => Err(format!(
+                "Unknown wrap mode '{}', expected repeat, clamp or mirror",
+                name
+            )),
+        };
+    }
+
+    // Maps a texel index onto the image, for an image `size` texels wide.
+    #[inline]
+    fn apply(&self, index: i64, size: usize) -> usize {
+        let size = size as i64;
+        let wrapped = match self {
+            WrapMode::Repeat => index.rem_euclid(size),
+            WrapMode::Clamp => index.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let period = index.rem_euclid(2 * size);
+                if period < size {
+                    period
+                } else {
+                    2 * size - 1 - period
+                }
+            }
+        };
+        return wrapped as usize;
+    }
+}
+
+/**
+ * An image loaded into memory, with its colors in linear space.
+ */
+#[derive(Debug)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    // Row by row, starting at the top of the image.
+    pixels: Vec<Vector3<f32>>,
+}
+
+impl ImageTexture {
+    // Loads a PNG or PPM. Color images are stored gamma encoded, so they get decoded,
+    // data (alpha masks, bump maps...) is kept as is.
+    pub fn load(path: &Path, is_color: bool) -> Result<ImageTexture, String> {
+        let image = image::open(path)
+            .map_err(|e| format!("There was an error loading '{}': {}", path.display(), e))?
+            .into_rgb32f();
+
+        let decode = |value: f32| {
+            if is_color {
+                return value.powf(2.2);
+            }
+            return value;
+        };
+
+        let pixels = image
+            .pixels()
+            .map(|pixel| Vector3::new(decode(pixel[0]), decode(pixel[1]), decode(pixel[2])))
+            .collect();
+
+        return Ok(ImageTexture {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels,
+        });
+    }
+
+    // Loads the opacity of a PNG or PPM: its alpha channel if it has one, otherwise its (grey)
+    // red channel, kept as is and copied into all three channels.
+    pub fn load_opacity(path: &Path) -> Result<ImageTexture, String> {
+        let image = image::open(path)
+            .map_err(|e| format!("There was an error loading '{}': {}", path.display(), e))?;
+        let channel = if image.color().has_alpha() { 3 } else { 0 };
+        let image = image.into_rgba32f();
+
+        let pixels = image
+            .pixels()
+            .map(|pixel| {
+                let opacity = pixel[channel];
+                return Vector3::new(opacity, opacity, opacity);
+            })
+            .collect();
+
+        return Ok(ImageTexture {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels,
+        });
+    }
+
+    // An image decoded elsewhere, e.g. embedded in a glTF file. `bytes` are rows from the top,
+    // 8 bit channels, `channels` of them per pixel. Only color images get gamma decoded.
+    pub fn from_bytes(
+        width: usize,
+        height: usize,
+        channels: usize,
+        bytes: &[u8],
+        is_color: bool,
+    ) -> Result<ImageTexture, String> {
+        if channels == 0 || bytes.len() != width * height * channels {
+            return Err(format!(
+                "Expected {}x{} pixels of {} channels, got {} bytes",
+                width,
+                height,
+                channels,
+                bytes.len()
+            ));
+        }
+
+        let decode = |value: u8| {
+            let value = value as f32 / 255.0;
+            if is_color {
+                return value.powf(2.2);
+            }
+            return value;
+        };
+
+        // Grey images have a single channel, which goes into all three.
+        let pixels = bytes
+            .chunks(channels)
+            .map(|pixel| {
+                let channel = |index: usize| decode(pixel[index.min(channels - 1)]);
+                if channels < 3 {
+                    return Vector3::new(channel(0), channel(0), channel(0));
+                }
+                return Vector3::new(channel(0), channel(1), channel(2));
+            })
+            .collect();
+
+        return Ok(ImageTexture {
+            width,
+            height,
+            pixels,
+        });
+    }
+
+    // Tangent space normal maps are mostly (0.5, 0.5, 1), unlike grey height maps.
+    pub fn looks_like_normal_map(&self) -> bool {
+        let total: Vector3<f32> = self.pixels.iter().sum();
+        let average = total / self.pixels.len().max(1) as f32;
+        return average.z > 0.7 && (average.x - 0.5).abs() < 0.15 && (average.y - 0.5).abs() < 0.15;
+    }
+
+    #[inline]
+    fn texel(&self, x: usize, y: usize) -> Vector3<f32> {
+        return self.pixels[y * self.width + x];
+    }
+
+    // Bilinear interpolation between the four texels closest to `uv`.
+    // (0, 0) is the bottom left corner of the image, as in OBJ files.
+    pub fn sample(&self, uv: Vector2<f32>, wrap: WrapMode) -> Vector3<f32> {
+        // Texel centers are at half integer coordinates.
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f32 - 0.5;
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+
+        let left = wrap.apply(x0 as i64, self.width);
+        let right = wrap.apply(x0 as i64 + 1, self.width);
+        let top = wrap.apply(y0 as i64, self.height);
+        let bottom = wrap.apply(y0 as i64 + 1, self.height);
+
+        let upper = self.texel(left, top) * (1.0 - tx) + self.texel(right, top) * tx;
+        let lower = self.texel(left, bottom) * (1.0 - tx) + self.texel(right, bottom) * tx;
+        return upper * (1.0 - ty) + lower * ty;
+    }
+}
+
+/**
+ * A color, or a number, which varies over a surface.
+ */
+#[derive(Clone, Debug)]
+pub enum Texture {
+    Constant(Vector3<f32>),
+    // Images are shared between all the materials which use them.
+    Image(Arc<ImageTexture>, WrapMode),
+    // Procedural, shared like images.
+    Pattern(Arc<Pattern>),
+}
+
+impl Texture {
+    // A texture which is `value` everywhere, for numeric parameters.
+    pub fn uniform(value: f32) -> Texture {
+        return Texture::Constant(Vector3::new(value, value, value));
+    }
+
+    pub fn value(&self, hit: &Hit) -> Vector3<f32> {
+        return match self {
+            Texture::Constant(color) => *color,
+            Texture::Image(image, wrap) => image.sample(hit.uv, *wrap),
+            Texture::Pattern(pattern) => pattern.value(hit),
+        };
+    }
+
+    // For numeric parameters (roughness, fuzz...), the average of the channels.
+    pub fn scalar(&self, hit: &Hit) -> f32 {
+        let value = self.value(hit);
+        return (value.x + value.y + value.z) / 3.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_texel_indices() {
+        assert_eq!(WrapMode::Repeat.apply(-1, 4), 3);
+        assert_eq!(WrapMode::Repeat.apply(5, 4), 1);
+        assert_eq!(WrapMode::Clamp.apply(-3, 4), 0);
+        assert_eq!(WrapMode::Clamp.apply(9, 4), 3);
+        assert_eq!(WrapMode::Mirror.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Mirror.apply(4, 4), 3);
+        assert_eq!(WrapMode::Mirror.apply(9, 4), 1);
+    }
+
+    #[test]
+    fn interpolates_between_texel_centers() {
+        // Black on the left, white on the right.
+        let image = ImageTexture {
+            width: 2,
+            height: 1,
+            pixels: vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0)],
+        };
+
+        let at = |u: f32, wrap: WrapMode| image.sample(Vector2::new(u, 0.5), wrap).x;
+        assert!((at(0.25, WrapMode::Clamp) - 0.0).abs() < 1e-6);
+        assert!((at(0.5, WrapMode::Clamp) - 0.5).abs() < 1e-6);
+        assert!((at(0.75, WrapMode::Clamp) - 1.0).abs() < 1e-6);
+        assert!((at(1.0, WrapMode::Clamp) - 1.0).abs() < 1e-6);
+        // Past the right edge, repeating blends back into the left texel.
+        assert!((at(1.0, WrapMode::Repeat) - 0.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::path::Path;
+
+use cgmath::Vector3;
+
+// Voxel files start with a text header line, `VOXELS <nx> <ny> <nz>`, followed directly by
+// nx * ny * nz little endian f32 densities, x changing fastest, then y, then z.
+const HEADER_MAGIC: &str = "VOXELS";
+
+/**
+ * Densities sampled on a regular 3D grid, spanning the unit cube.
+ * Values in between voxel centers are interpolated trilinearly, outside of the cube it's empty.
+ */
+#[derive(Debug)]
+pub struct VoxelGrid {
+    size: [usize; 3],
+    values: Vec<f32>,
+    // The densest voxel, bounds the density anywhere in the grid.
+    pub max_value: f32,
+}
+
+impl VoxelGrid {
+    pub fn load(path: &Path) -> Result<VoxelGrid, String> {
+        let bytes = fs::read(path)
+            .map_err(|e| format!("There was an error opening '{}': {}", path.display(), e))?;
+        return VoxelGrid::parse(&bytes)
+            .map_err(|e| format!("Invalid voxel file '{}': {}", path.display(), e));
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<VoxelGrid, String> {
+        let header_end = bytes
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .ok_or("Missing the header line")?;
+        let header = std::str::from_utf8(&bytes[..header_end])
+            .map_err(|_| "The header line is not text".to_string())?;
+
+        let mut words = header.split_whitespace();
+        if words.next() != Some(HEADER_MAGIC) {
+            return Err(format!("The header has to start with {}", HEADER_MAGIC));
+        }
+        let mut size = [0; 3];
+        for axis_size in size.iter_mut() {
+            *axis_size = words
+                .next()
+                .and_then(|word| word.parse::<usize>().ok())
+                .filter(|value| *value > 0)
+                .ok_or("The header needs three positive grid sizes")?;
+        }
+
+        let data = &bytes[header_end + 1..];
+        let count = size[0] * size[1] * size[2];
+        if data.len() != count * 4 {
+            return Err(format!(
+                "Expected {} densities, found {} bytes of them",
+                count,
+                data.len()
+            ));
+        }
+
+        let values: Vec<f32> = data
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]).max(0.0))
+            .collect();
+        let max_value = values.iter().cloned().fold(0.0, f32::max);
+
+        return Ok(VoxelGrid {
+            size,
+            values,
+            max_value,
+        });
+    }
+
+    #[inline]
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        return self.values[(z * self.size[1] + y) * self.size[0] + x];
+    }
+
+    // Density at a point of the unit cube.
+    pub fn density(&self, point: Vector3<f32>) -> f32 {
+        if !(0.0..=1.0).contains(&point.x)
+            || !(0.0..=1.0).contains(&point.y)
+            || !(0.0..=1.0).contains(&point.z)
+        {
+            return 0.0;
+        }
+
+        // Voxel centers are at half integer coordinates, the outermost half voxel is clamped.
+        let mut lower = [0; 3];
+        let mut upper = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let coordinate = (point[axis] * self.size[axis] as f32 - 0.5)
+                .clamp(0.0, (self.size[axis] - 1) as f32);
+            lower[axis] = coordinate.floor() as usize;
+            upper[axis] = (lower[axis] + 1).min(self.size[axis] - 1);
+            fraction[axis] = coordinate - lower[axis] as f32;
+        }
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let row = |y: usize, z: usize| {
+            lerp(
+                self.voxel(lower[0], y, z),
+                self.voxel(upper[0], y, z),
+                fraction[0],
+            )
+        };
+        let slice = |z: usize| lerp(row(lower[1], z), row(upper[1], z), fraction[1]);
+        return lerp(slice(lower[2]), slice(upper[2]), fraction[2]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_interpolates_densities() {
+        // Empty on the left half of the grid, full on the right.
+        let mut bytes = b"VOXELS 2 1 1\n".to_vec();
+        bytes.extend_from_slice(&0.0_f32.to_le_bytes());
+        bytes.extend_from_slice(&2.0_f32.to_le_bytes());
+
+        let grid = VoxelGrid::parse(&bytes).unwrap();
+        assert_eq!(grid.max_value, 2.0);
+        assert_eq!(grid.density(Vector3::new(0.1, 0.5, 0.5)), 0.0);
+        assert!((grid.density(Vector3::new(0.5, 0.5, 0.5)) - 1.0).abs() < 1e-6);
+        assert_eq!(grid.density(Vector3::new(0.9, 0.5, 0.5)), 2.0);
+        assert_eq!(grid.density(Vector3::new(1.5, 0.5, 0.5)), 0.0);
+
+        assert!(VoxelGrid::parse(b"VOXELS 2 1 1\n\0\0\0\0").is_err());
+        assert!(VoxelGrid::parse(b"VOXELS 2 1\n").is_err());
+    }
+}
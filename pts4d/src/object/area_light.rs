@@ -0,0 +1,212 @@
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::{
+    accel::aabb::AABB,
+    materials::{material::Material, texture::Texture},
+    utils::vector_utils::{Interval, Ray},
+};
+
+use super::object::{Hit, Hitable, PrimitiveId};
+
+// Flat shapes emitting light.
+#[derive(Copy, Clone, Debug)]
+pub enum AreaLightShape {
+    // The parallelogram spanned by the two edges from `corner`, emitting towards edge_u x edge_v.
+    Quad {
+        corner: Vector3<f32>,
+        edge_u: Vector3<f32>,
+        edge_v: Vector3<f32>,
+    },
+    // Emitting towards the unit `normal`.
+    Disk {
+        center: Vector3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+    },
+}
+
+/**
+ * A one-sided emitter, e.g. a softbox or a ceiling panel.
+ * Its back is black, and it may be hidden from the camera while still lighting the scene.
+ */
+pub struct AreaLight {
+    pub shape: AreaLightShape,
+    pub material: Material,
+    // Whether camera rays see the light, everything else always does.
+    pub visible: bool,
+    back: Material,
+    bbox: AABB,
+}
+
+impl AreaLightShape {
+    // Unit normal of the emitting side.
+    pub fn normal(&self) -> Vector3<f32> {
+        return match self {
+            AreaLightShape::Quad { edge_u, edge_v, .. } => edge_u.cross(*edge_v).normalize(),
+            AreaLightShape::Disk { normal, .. } => *normal,
+        };
+    }
+
+    fn bounding_box(&self) -> AABB {
+        // Flat shapes get a little thickness, so their boxes aren't empty.
+        let padding = Vector3::new(1e-4, 1e-4, 1e-4);
+        let (min, max) = match *self {
+            AreaLightShape::Quad {
+                corner,
+                edge_u,
+                edge_v,
+            } => {
+                let corners = [corner + edge_u, corner + edge_v, corner + edge_u + edge_v];
+                corners.iter().fold((corner, corner), |(min, max), point| {
+                    (
+                        Vector3::new(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z)),
+                        Vector3::new(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z)),
+                    )
+                })
+            }
+            AreaLightShape::Disk {
+                center,
+                normal,
+                radius,
+            } => {
+                // How far the rim reaches along every axis.
+                let extent = Vector3::new(
+                    (1.0 - normal.x * normal.x).max(0.0).sqrt(),
+                    (1.0 - normal.y * normal.y).max(0.0).sqrt(),
+                    (1.0 - normal.z * normal.z).max(0.0).sqrt(),
+                ) * radius;
+                (center - extent, center + extent)
+            }
+        };
+        return AABB::new_from_diagonals(min - padding, max + padding);
+    }
+}
+
+impl AreaLight {
+    pub fn new(shape: AreaLightShape, color: Vector3<f32>, intensity: f32) -> AreaLight {
+        return AreaLight {
+            shape,
+            material: Material::Emissive(Texture::Constant(color), intensity),
+            visible: true,
+            back: Material::Emissive(Texture::uniform(0.0), 0.0),
+            bbox: shape.bounding_box(),
+        };
+    }
+}
+
+impl Hitable for AreaLight {
+    fn intersect(&self, ray: &Ray, bounds: Interval) -> Option<Hit<'_>> {
+        let normal = self.shape.normal();
+        let denominator = normal.dot(ray.direction);
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+
+        let on_plane = match self.shape {
+            AreaLightShape::Quad { corner, .. } => corner,
+            AreaLightShape::Disk { center, .. } => center,
+        };
+        let t = normal.dot(on_plane - ray.origin) / denominator;
+        if t <= bounds.min || t >= bounds.max {
+            return None;
+        }
+        let point = ray.point_at(t);
+
+        let (uv, dpdu, dpdv) = match self.shape {
+            AreaLightShape::Quad {
+                corner,
+                edge_u,
+                edge_v,
+            } => {
+                // Coordinates of the point along both edges, which don't have to be perpendicular.
+                let n = edge_u.cross(edge_v);
+                let w = n / n.dot(n);
+                let planar = point - corner;
+                let u = w.dot(planar.cross(edge_v));
+                let v = w.dot(edge_u.cross(planar));
+                if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+                    return None;
+                }
+                (Vector2::new(u, v), edge_u, edge_v)
+            }
+            AreaLightShape::Disk { center, radius, .. } => {
+                if (point - center).magnitude2() > radius * radius {
+                    return None;
+                }
+                let zero = Vector3::new(0.0, 0.0, 0.0);
+                (Vector2::new(0.0, 0.0), zero, zero)
+            }
+        };
+
+        let is_facing_you = denominator < 0.0;
+        let facing_normal = if is_facing_you { normal } else { -normal };
+        return Some(Hit {
+            point_at_intersection: t,
+            point,
+            normal: facing_normal,
+            geometric_normal: facing_normal,
+            is_facing_you,
+            uv,
+            dpdu,
+            dpdv,
+            material: if is_facing_you {
+                &self.material
+            } else {
+                &self.back
+            },
+            primitive: PrimitiveId::default(),
+        });
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        return &self.bbox;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_emits_from_the_front() {
+        // Facing down, from y = 1.
+        let light = AreaLight::new(
+            AreaLightShape::Quad {
+                corner: Vector3::new(-1.0, 1.0, -1.0),
+                edge_u: Vector3::new(2.0, 0.0, 0.0),
+                edge_v: Vector3::new(0.0, 0.0, 2.0),
+            },
+            Vector3::new(1.0, 1.0, 1.0),
+            1.0,
+        );
+        let bounds = Interval::new(0.001, f32::MAX);
+        let ray = |origin: Vector3<f32>, direction: Vector3<f32>| Ray { origin, direction };
+
+        let below = light
+            .intersect(
+                &ray(Vector3::new(0.5, 0.0, 0.5), Vector3::new(0.0, 1.0, 0.0)),
+                bounds,
+            )
+            .unwrap();
+        assert!(below.is_facing_you && below.material.is_emissive());
+        assert!((below.point_at_intersection - 1.0).abs() < 1e-6);
+
+        let above = light
+            .intersect(
+                &ray(Vector3::new(0.5, 2.0, 0.5), Vector3::new(0.0, -1.0, 0.0)),
+                bounds,
+            )
+            .unwrap();
+        assert!(!above.is_facing_you);
+        assert_eq!(
+            above
+                .material
+                .emit(&ray(above.point, above.normal), &above)
+                .y,
+            0.0
+        );
+
+        let outside = ray(Vector3::new(1.5, 0.0, 0.5), Vector3::new(0.0, 1.0, 0.0));
+        assert!(light.intersect(&outside, bounds).is_none());
+    }
+}
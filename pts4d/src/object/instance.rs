@@ -0,0 +1,242 @@
+use std::sync::Arc;
+
+use cgmath::{
+    Deg, EuclideanSpace, InnerSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Vector3,
+};
+
+use crate::{
+    accel::aabb::AABB,
+    materials::medium::Medium,
+    object::mesh::Mesh,
+    utils::vector_utils::{Interval, Ray},
+};
+
+use super::object::{Hit, Hitable};
+use super::triangle::TriangleIntersector;
+
+/**
+ * Affine object-to-world transform, along with everything derived from it
+ * that is needed to move rays into object space and normals back out.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    pub object_to_world: Matrix4<f32>,
+    pub world_to_object: Matrix4<f32>,
+    // Inverse transpose of the upper 3x3, for transforming normals.
+    normal_matrix: Matrix3<f32>,
+}
+
+#[allow(dead_code)]
+impl Transform {
+    // Fails for singular matrices, e.g. scaled to zero along an axis.
+    pub fn new(object_to_world: Matrix4<f32>) -> Result<Transform, String> {
+        let world_to_object = object_to_world
+            .invert()
+            .ok_or("Instance transforms must be invertible".to_string())?;
+        let linear_inverse = Matrix3::from_cols(
+            world_to_object.x.truncate(),
+            world_to_object.y.truncate(),
+            world_to_object.z.truncate(),
+        );
+
+        return Ok(Transform {
+            object_to_world,
+            world_to_object,
+            normal_matrix: linear_inverse.transpose(),
+        });
+    }
+
+    pub fn identity() -> Transform {
+        return Transform {
+            object_to_world: Matrix4::identity(),
+            world_to_object: Matrix4::identity(),
+            normal_matrix: Matrix3::identity(),
+        };
+    }
+
+    pub fn from_translation_and_scale(
+        translation: Vector3<f32>,
+        scale: f32,
+    ) -> Result<Transform, String> {
+        return Transform::new(Matrix4::from_translation(translation) * Matrix4::from_scale(scale));
+    }
+
+    // Scale first, then rotate around X, Y and Z (in degrees), then translate.
+    pub fn from_components(
+        translation: Vector3<f32>,
+        rotation_degrees: Vector3<f32>,
+        scale: Vector3<f32>,
+    ) -> Result<Transform, String> {
+        let rotation = Matrix4::from_angle_z(Deg(rotation_degrees.z))
+            * Matrix4::from_angle_y(Deg(rotation_degrees.y))
+            * Matrix4::from_angle_x(Deg(rotation_degrees.x));
+
+        return Transform::new(
+            Matrix4::from_translation(translation)
+                * rotation
+                * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z),
+        );
+    }
+
+    pub fn then(&self, other: &Transform) -> Result<Transform, String> {
+        return Transform::new(other.object_to_world * self.object_to_world);
+    }
+
+    #[inline]
+    pub fn point_to_world(&self, point: Vector3<f32>) -> Vector3<f32> {
+        return cgmath::Transform::transform_point(&self.object_to_world, Point3::from_vec(point))
+            .to_vec();
+    }
+
+    #[inline]
+    pub fn vector_to_world(&self, vector: Vector3<f32>) -> Vector3<f32> {
+        return cgmath::Transform::transform_vector(&self.object_to_world, vector);
+    }
+
+    #[inline]
+    pub fn normal_to_world(&self, normal: Vector3<f32>) -> Vector3<f32> {
+        return (self.normal_matrix * normal).normalize();
+    }
+
+    // The direction is deliberately not normalised, so distances along the
+    // object space ray are the same as along the world space one.
+    #[inline]
+    pub fn ray_to_object(&self, ray: &Ray) -> Ray {
+        return Ray {
+            origin: cgmath::Transform::transform_point(
+                &self.world_to_object,
+                Point3::from_vec(ray.origin),
+            )
+            .to_vec(),
+            direction: cgmath::Transform::transform_vector(&self.world_to_object, ray.direction),
+        };
+    }
+
+    pub fn bounding_box_to_world(&self, bbox: &AABB) -> AABB {
+        let mut world_bbox = AABB::new_empty();
+        let (min, max) = (bbox.min(), bbox.max());
+        for corner in 0..8 {
+            let point = Vector3::new(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            );
+            world_bbox = world_bbox.expand_to_point(self.point_to_world(point));
+        }
+        return world_bbox;
+    }
+}
+
+/**
+ * A placement of a shared mesh in the world.
+ * Rays are moved into the mesh's object space, intersected against its
+ * bottom-level BVH and the resulting hit is moved back into world space.
+ */
+pub struct MeshInstance {
+    pub mesh: Arc<Mesh>,
+    pub transform: Transform,
+    // What fills the mesh, if it is closed, for rays passing through its surface.
+    pub interior: Option<Medium>,
+    bbox: AABB,
+}
+
+impl MeshInstance {
+    pub fn new(mesh: Arc<Mesh>, transform: Transform) -> MeshInstance {
+        let bbox = transform.bounding_box_to_world(mesh.bounding_box());
+        return MeshInstance {
+            mesh,
+            transform,
+            interior: None,
+            bbox,
+        };
+    }
+
+    pub fn intersect_with(
+        &self,
+        ray: &Ray,
+        bounds: Interval,
+        intersector: TriangleIntersector,
+    ) -> Option<Hit<'_>> {
+        let object_ray = self.transform.ray_to_object(ray);
+        let mut hit = self.mesh.intersect_with(&object_ray, bounds, intersector)?;
+
+        hit.point = ray.point_at(hit.point_at_intersection);
+        hit.normal = self.transform.normal_to_world(hit.normal);
+        hit.geometric_normal = self.transform.normal_to_world(hit.geometric_normal);
+        hit.dpdu = self.transform.vector_to_world(hit.dpdu);
+        hit.dpdv = self.transform.vector_to_world(hit.dpdv);
+
+        return Some(hit);
+    }
+}
+
+impl Hitable for MeshInstance {
+    fn intersect(&self, ray: &Ray, bounds: Interval) -> Option<Hit<'_>> {
+        return self.intersect_with(ray, bounds, TriangleIntersector::default());
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        return &self.bbox;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A single triangle in the x + y = 1 plane.
+    fn tilted_triangle() -> Arc<Mesh> {
+        let obj = wavefront_obj::obj::parse("v 1 0 0\nv 0 1 0\nv 1 0 1\nf 1 2 3\n").unwrap();
+        return Arc::new(Mesh::new(obj).unwrap());
+    }
+
+    #[test]
+    fn hits_the_transformed_mesh_with_correct_normals() {
+        let transform = Transform::from_components(
+            Vector3::new(0.0, 0.0, -5.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(2.0, 1.0, 1.0),
+        )
+        .unwrap();
+        let instance = MeshInstance::new(tilted_triangle(), transform);
+        let bounds = Interval::new(0.001, f32::MAX);
+
+        // Centroid of the triangle once it is stretched along x and moved back.
+        let target = (Vector3::new(2.0, 0.0, -5.0)
+            + Vector3::new(0.0, 1.0, -5.0)
+            + Vector3::new(2.0, 0.0, -4.0))
+            / 3.0;
+        let ray = Ray {
+            origin: Vector3::new(0.0, 0.0, 0.0),
+            direction: target,
+        };
+        let hit = instance.intersect(&ray, bounds).unwrap();
+        assert!((hit.point_at_intersection - 1.0).abs() < 1e-5);
+        assert!((hit.point - target).magnitude() < 1e-5);
+
+        // The plane became x / 2 + y = 1, scaling the normal by 2 along x would tilt it wrongly.
+        let expected_normal = Vector3::new(0.5, 1.0, 0.0).normalize();
+        for normal in [hit.normal, hit.geometric_normal] {
+            assert!((normal.dot(expected_normal).abs() - 1.0).abs() < 1e-5);
+            assert!(normal.dot(ray.direction) < 0.0);
+        }
+
+        // Where the untransformed triangle would have been hit.
+        let untransformed = Ray {
+            origin: Vector3::new(0.0, 0.0, 0.0),
+            direction: Vector3::new(2.0, 1.0, 1.0) / 3.0,
+        };
+        assert!(instance.intersect(&untransformed, bounds).is_none());
+    }
+
+    #[test]
+    fn rejects_singular_transforms() {
+        let flattened = Transform::from_components(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 45.0, 0.0),
+            Vector3::new(1.0, 0.0, 1.0),
+        );
+        assert!(flattened.is_err());
+        assert!(Transform::from_translation_and_scale(Vector3::new(1.0, 2.0, 3.0), 2.0).is_ok());
+    }
+}
@@ -1,104 +1,165 @@
-use cgmath::{InnerSpace, Matrix3, Point3, Transform, Vector3};
-use wavefront_obj::obj::{ObjSet, Primitive, VTNIndex, Vertex};
+use cgmath::{InnerSpace, Vector2, Vector3};
+use wavefront_obj::obj::{ObjSet, Primitive, TVertex, Vertex};
 
 use crate::{
-    accel::aabb::AABB,
+    accel::{aabb::AABB, bvh::BVH},
     materials::material::{Material, MaterialSet},
     utils::vector_utils::{correct_face_normal, Interval, Ray},
 };
 
-use super::object::{Hit, Hitable};
+use super::object::{Hit, Hitable, PrimitiveId};
+use super::triangle::TriangleIntersector;
 
+const DEFAULT_MATERIAL: &str = "__default__";
+
+/**
+ * A triangle mesh in its own object space, with its own bottom-level BVH.
+ * Meshes are placed in the scene through `MeshInstance`s, so a single mesh
+ * can be shared by any number of instances.
+ */
 pub struct Mesh {
-    pub geometry: ObjSet,
     pub material_set: MaterialSet,
+    // Flattened geometry, all objects of the ObjSet merged together.
+    vertices: Vec<Vector3<f32>>,
+    texture_coordinates: Vec<Vector2<f32>>,
+    // Vertex normals of the file.
+    normals: Vec<Vector3<f32>>,
+    triangles: Vec<Triangle>,
+    // Shading normal at each corner of each triangle, interpolated over the triangle.
+    // Either the file's normals, or generated for triangles which don't have any.
+    corner_normals: Vec<[Vector3<f32>; 3]>,
+    // dp/du and dp/dv of each triangle, zero for triangles without texture coordinates.
+    tangents: Vec<[Vector3<f32>; 2]>,
+    // Material names referenced by the triangles, resolved in `material_set`.
+    material_names: Vec<String>,
+    bvh: BVH,
     bbox: AABB,
 }
 
+struct Triangle {
+    // Indices into `Mesh::vertices`
+    vertices: [usize; 3],
+    // Indices into `Mesh::texture_coordinates`, if the triangle has any.
+    texture_coordinates: Option<[usize; 3]>,
+    // Indices into `Mesh::normals`, if the triangle has any.
+    normals: Option<[usize; 3]>,
+    // Index into `Mesh::material_names`
+    material: usize,
+}
+
 #[inline]
 fn convert_to_cgmath_vec(vertex: Vertex) -> Vector3<f32> {
     return Vector3::new(vertex.x as f32, vertex.y as f32, vertex.z as f32);
 }
 
-fn translate_and_scale_vertex_cgmath_vec(vec1: Vertex, vec2: Vector3<f32>, scale: f32) -> Vertex {
-    let scale_mat: Matrix3<f32> = Matrix3 {
-        x: Vector3::new(scale, 0.0, 0.0),
-        y: Vector3::new(0.0, scale, 0.0),
-        z: Vector3::new(0.0, 0.0, scale),
-    };
-
-    let scaled_object: Vector3<f32> = <Matrix3<f32> as Transform<Point3<f32>>>::transform_vector(
-        &scale_mat,
-        convert_to_cgmath_vec(vec1),
-    );
-    let result = scaled_object + vec2;
-
-    return Vertex {
-        x: result.x as f64,
-        y: result.y as f64,
-        z: result.z as f64,
-    };
-}
-
-fn translate_and_scale_object(
-    vertex_set: &Vec<Vertex>,
-    to: Vector3<f32>,
-    scale: f32,
-) -> Vec<Vertex> {
-    let mut translated_set: Vec<Vertex> = Vec::new();
-    for point in vertex_set {
-        translated_set.push(translate_and_scale_vertex_cgmath_vec(*point, to, scale));
-    }
-
-    return translated_set;
+#[inline]
+fn convert_to_cgmath_uv(vertex: TVertex) -> Vector2<f32> {
+    return Vector2::new(vertex.u as f32, vertex.v as f32);
 }
 
 #[allow(dead_code)]
 impl Mesh {
-    pub fn new(center: Vector3<f32>, scale: f32, geometry: ObjSet) -> Mesh {
+    pub fn new(geometry: ObjSet) -> Result<Mesh, String> {
         return Mesh::new_override_material(
-            center,
-            scale,
             geometry,
             Material::Diffuse(Vector3::new(0.6, 0.6, 0.6)),
         );
     }
 
-    pub fn new_override_material(
-        center: Vector3<f32>,
-        scale: f32,
-        geometry: ObjSet,
-        material: Material,
-    ) -> Mesh {
+    pub fn new_override_material(geometry: ObjSet, material: Material) -> Result<Mesh, String> {
         return Mesh::new_override_material_set(
-            center,
-            scale,
             geometry,
             MaterialSet::new_with_default_material(material),
         );
     }
 
+    // Fails if a face refers to vertices, texture coordinates or normals its object doesn't have.
     pub fn new_override_material_set(
-        center: Vector3<f32>,
-        scale: f32,
-        mut geometry: ObjSet,
-        material_set: MaterialSet,
-    ) -> Mesh {
-        let mut bbox: AABB =
-            AABB::new_from_diagonals(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
-
-        // Building a simple bounding box.
-        for obj in geometry.objects.as_mut_slice() {
-            obj.vertices = translate_and_scale_object(&obj.vertices, center, scale);
+        geometry: ObjSet,
+        mut material_set: MaterialSet,
+    ) -> Result<Mesh, String> {
+        if !material_set.materials.contains_key(DEFAULT_MATERIAL) {
+            material_set.add(
+                DEFAULT_MATERIAL.to_string(),
+                Material::Diffuse(Vector3::new(0.6, 0.6, 0.6)),
+            );
+        }
+
+        let mut vertices: Vec<Vector3<f32>> = Vec::new();
+        let mut texture_coordinates: Vec<Vector2<f32>> = Vec::new();
+        let mut normals: Vec<Vector3<f32>> = Vec::new();
+        let mut triangles: Vec<Triangle> = Vec::new();
+        let mut material_names: Vec<String> = Vec::new();
+
+        for obj in &geometry.objects {
+            // Indices in an ObjSet are local to each object.
+            let vertex_offset = vertices.len();
+            for vertex in &obj.vertices {
+                vertices.push(convert_to_cgmath_vec(*vertex));
+            }
+            let uv_offset = texture_coordinates.len();
+            for uv in &obj.tex_vertices {
+                texture_coordinates.push(convert_to_cgmath_uv(*uv));
+            }
+            let normal_offset = normals.len();
+            for normal in &obj.normals {
+                normals.push(convert_to_cgmath_vec(*normal));
+            }
+
             for geom in &obj.geometry {
+                // Geometry without a (known) material falls back onto the default one.
+                let material_name = match &geom.material_name {
+                    Some(name) if material_set.materials.contains_key(name) => name.clone(),
+                    _ => DEFAULT_MATERIAL.to_string(),
+                };
+                let material = match material_names.iter().position(|n| *n == material_name) {
+                    Some(index) => index,
+                    None => {
+                        material_names.push(material_name);
+                        material_names.len() - 1
+                    }
+                };
+
                 for shape in &geom.shapes {
                     match shape.primitive {
                         // Each vertex is made out of VertexIndex, Option<TextureIndex>, Option<NormalIndex>
-                        Primitive::Triangle(a, b, c) => {
-                            bbox = AABB::new_from_aabbs(
-                                bbox,
-                                triangle_bounding_box(&(a, b, c), &obj.vertices),
-                            );
+                        Primitive::Triangle((a, a_uv, a_n), (b, b_uv, b_n), (c, c_uv, c_n)) => {
+                            check_indices(&obj.name, "vertex", [a, b, c], obj.vertices.len())?;
+                            // Only used when all three corners have texture coordinates.
+                            let uvs = match (a_uv, b_uv, c_uv) {
+                                (Some(a_uv), Some(b_uv), Some(c_uv)) => {
+                                    check_indices(
+                                        &obj.name,
+                                        "texture coordinate",
+                                        [a_uv, b_uv, c_uv],
+                                        obj.tex_vertices.len(),
+                                    )?;
+                                    Some([a_uv + uv_offset, b_uv + uv_offset, c_uv + uv_offset])
+                                }
+                                _ => None,
+                            };
+                            let triangle_normals = match (a_n, b_n, c_n) {
+                                (Some(a_n), Some(b_n), Some(c_n)) => {
+                                    check_indices(
+                                        &obj.name,
+                                        "normal",
+                                        [a_n, b_n, c_n],
+                                        obj.normals.len(),
+                                    )?;
+                                    Some([
+                                        a_n + normal_offset,
+                                        b_n + normal_offset,
+                                        c_n + normal_offset,
+                                    ])
+                                }
+                                _ => None,
+                            };
+                            triangles.push(Triangle {
+                                vertices: [a + vertex_offset, b + vertex_offset, c + vertex_offset],
+                                texture_coordinates: uvs,
+                                normals: triangle_normals,
+                                material,
+                            });
                         }
                         _ => continue,
                     }
@@ -106,116 +167,249 @@ impl Mesh {
             }
         }
 
-        return Mesh {
-            geometry,
+        let mut bbox = AABB::new_empty();
+        for vertex in &vertices {
+            bbox = bbox.expand_to_point(*vertex);
+        }
+
+        let mut mesh = Mesh {
             material_set,
+            vertices,
+            texture_coordinates,
+            normals,
+            triangles,
+            corner_normals: Vec::new(),
+            tangents: Vec::new(),
+            material_names,
+            bvh: BVH::build(&[]),
             bbox,
         };
+
+        let triangle_boxes: Vec<AABB> = (0..mesh.triangles.len())
+            .map(|triangle_index| mesh.triangle_bounding_box(triangle_index))
+            .collect();
+        mesh.bvh = BVH::build(&triangle_boxes);
+        // Faces without normals in the file stay flat, unless smoothed with a crease angle.
+        mesh.generate_normals(0.0);
+        mesh.tangents = (0..mesh.triangles.len())
+            .map(|triangle_index| mesh.triangle_tangents(triangle_index))
+            .collect();
+
+        return Ok(mesh);
     }
 
-    fn intersect_triangle(
-        &self,
-        ray: &Ray,
-        triangle: &(VTNIndex, VTNIndex, VTNIndex),
-        material_name: &String,
-        vertices_cache: &Vec<Vertex>,
-        bounds: Interval,
-    ) -> Option<Hit> {
-        let (p1, p2, p3) = triangle;
+    // Solves for how the triangle's points move with its texture coordinates,
+    // e1 = du1 * dp/du + dv1 * dp/dv and e2 = du2 * dp/du + dv2 * dp/dv.
+    fn triangle_tangents(&self, triangle_index: usize) -> [Vector3<f32>; 2] {
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        let Some([a_uv, b_uv, c_uv]) = self.triangles[triangle_index].texture_coordinates else {
+            return [zero, zero];
+        };
+        let [a, b, c] = self.triangle_vertices(triangle_index);
+        let (e1, e2) = (b - a, c - a);
+        let duv1 = self.texture_coordinates[b_uv] - self.texture_coordinates[a_uv];
+        let duv2 = self.texture_coordinates[c_uv] - self.texture_coordinates[a_uv];
+
+        let determinant = duv1.x * duv2.y - duv1.y * duv2.x;
+        if determinant.abs() < 1e-12 {
+            return [zero, zero];
+        }
+        return [
+            (e1 * duv2.y - e2 * duv1.y) / determinant,
+            (e2 * duv1.x - e1 * duv2.x) / determinant,
+        ];
+    }
 
-        let (vertex_index_1, _, _) = p1;
-        let (vertex_index_2, _, _) = p2;
-        let (vertex_index_3, _, _) = p3;
+    // (Re)computes the shading normals of the triangles which have none in the file, by averaging
+    // the normals of the faces around each vertex, weighted by their angle at the vertex.
+    // Faces which are more than `crease_angle` degrees apart aren't smoothed together.
+    pub fn generate_normals(&mut self, crease_angle: f32) {
+        let cos_crease_angle = crease_angle.to_radians().cos();
+
+        let face_normals: Vec<Vector3<f32>> = (0..self.triangles.len())
+            .map(|triangle_index| {
+                let [a, b, c] = self.triangle_vertices(triangle_index);
+                let normal = (b - a).cross(c - a);
+                if normal.magnitude2() > 0.0 {
+                    return normal.normalize();
+                }
+                return normal;
+            })
+            .collect();
+
+        // Every triangle touching a vertex, along with the angle of its corner there.
+        let mut around_vertex: Vec<Vec<(usize, f32)>> = vec![Vec::new(); self.vertices.len()];
+        for triangle_index in 0..self.triangles.len() {
+            let corners = self.triangle_vertices(triangle_index);
+            for corner in 0..3 {
+                let vertex = self.triangles[triangle_index].vertices[corner];
+                let angle = corner_angle(corners, corner);
+                around_vertex[vertex].push((triangle_index, angle));
+            }
+        }
 
-        let maybe_v1 = vertices_cache.get(*vertex_index_1);
-        let maybe_v2 = vertices_cache.get(*vertex_index_2);
-        let maybe_v3 = vertices_cache.get(*vertex_index_3);
+        self.corner_normals = (0..self.triangles.len())
+            .map(|triangle_index| {
+                let triangle = &self.triangles[triangle_index];
+                let face_normal = face_normals[triangle_index];
+
+                // Normals from the file win, unless they are degenerate.
+                if let Some(indices) = triangle.normals {
+                    return indices.map(|index| {
+                        let normal = self.normals[index];
+                        if normal.magnitude2() > 0.0 {
+                            return normal.normalize();
+                        }
+                        return face_normal;
+                    });
+                }
 
-        if maybe_v1.is_none() || maybe_v2.is_none() || maybe_v3.is_none() {
-            panic!("Some vertices weren't assembled together into a triangle");
-        }
+                return triangle.vertices.map(|vertex| {
+                    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+                    for (other, angle) in &around_vertex[vertex] {
+                        let other_normal = face_normals[*other];
+                        if other_normal.dot(face_normal) >= cos_crease_angle {
+                            normal += other_normal * *angle;
+                        }
+                    }
+                    if normal.magnitude2() > 0.0 {
+                        return normal.normalize();
+                    }
+                    return face_normal;
+                });
+            })
+            .collect();
+    }
 
-        let a = convert_to_cgmath_vec(*maybe_v1.unwrap());
-        let b = convert_to_cgmath_vec(*maybe_v2.unwrap());
-        let c = convert_to_cgmath_vec(*maybe_v3.unwrap());
+    pub fn triangle_count(&self) -> usize {
+        return self.triangles.len();
+    }
 
-        let e1 = b - a;
-        let e2 = c - a;
+    pub fn triangle_material(&self, triangle_index: usize) -> &Material {
+        let material_name = &self.material_names[self.triangles[triangle_index].material];
+        return self.material_set.get(material_name);
+    }
 
-        let ray_cross_e2 = ray.direction.cross(e2);
-        let det = e1.dot(ray_cross_e2);
+    fn triangle_bounding_box(&self, triangle_index: usize) -> AABB {
+        let [a, b, c] = self.triangle_vertices(triangle_index);
+        return AABB::new_from_diagonals(a, b).expand_to_point(c);
+    }
 
-        if det > -f32::EPSILON && det < f32::EPSILON {
-            return None; // This ray is parallel to this triangle.
-        }
+    pub fn triangle_vertices(&self, triangle_index: usize) -> [Vector3<f32>; 3] {
+        let triangle = &self.triangles[triangle_index];
+        return [
+            self.vertices[triangle.vertices[0]],
+            self.vertices[triangle.vertices[1]],
+            self.vertices[triangle.vertices[2]],
+        ];
+    }
 
-        let inv_det = 1.0 / det;
-        let s = ray.origin - a;
-        let u = inv_det * s.dot(ray_cross_e2);
-        if u < 0.0 || u > 1.0 {
-            return None;
-        }
+    // Intersects the mesh's BVH, testing triangles with the given intersector.
+    pub fn intersect_with(
+        &self,
+        ray: &Ray,
+        bounds: Interval,
+        intersector: TriangleIntersector,
+    ) -> Option<Hit<'_>> {
+        return self
+            .bvh
+            .intersect(ray, bounds, |triangle_index, triangle_bounds| {
+                self.intersect_triangle(ray, triangle_index, triangle_bounds, intersector)
+            });
+    }
 
-        let s_cross_e1 = s.cross(e1);
-        let v = inv_det * ray.direction.dot(s_cross_e1);
-        if v < 0.0 || u + v > 1.0 {
-            return None;
+    fn intersect_triangle(
+        &self,
+        ray: &Ray,
+        triangle_index: usize,
+        bounds: Interval,
+        intersector: TriangleIntersector,
+    ) -> Option<Hit<'_>> {
+        let [a, b, c] = self.triangle_vertices(triangle_index);
+        let triangle_hit = intersector.intersect(ray, a, b, c, bounds)?;
+
+        let t = triangle_hit.t;
+        let (u, v) = (triangle_hit.u, triangle_hit.v);
+        let normal = (b - a).cross(c - a).normalize();
+        let geometric_normal = correct_face_normal(ray, normal);
+
+        // Shading normals are kept on the same side of the surface as the geometric one.
+        let [a_n, b_n, c_n] = self.corner_normals[triangle_index];
+        let mut shading_normal = (a_n * (1.0 - u - v) + b_n * u + c_n * v).normalize();
+        if shading_normal.dot(geometric_normal) < 0.0 {
+            shading_normal = -shading_normal;
         }
 
-        // At this stage we can compute t to find out where the intersection point is on the line.
-        let t = inv_det * e2.dot(s_cross_e1);
-        if t > bounds.max || t < bounds.min {
+        let uv = match self.triangles[triangle_index].texture_coordinates {
+            Some([a_uv, b_uv, c_uv]) => {
+                self.texture_coordinates[a_uv] * (1.0 - u - v)
+                    + self.texture_coordinates[b_uv] * u
+                    + self.texture_coordinates[c_uv] * v
+            }
+            None => Vector2::new(0.0, 0.0),
+        };
+        let [dpdu, dpdv] = self.tangents[triangle_index];
+        let hit = Hit {
+            point: ray.point_at(t),
+            material: self.triangle_material(triangle_index),
+            normal: shading_normal,
+            geometric_normal,
+            is_facing_you: ray.direction.dot(normal) < 0.0,
+            uv,
+            dpdu,
+            dpdv,
+            point_at_intersection: t,
+            primitive: PrimitiveId {
+                object: 0,
+                triangle: triangle_index,
+            },
+        };
+
+        // Leaves and fences are often quads with their shape cut out by an alpha map. Rays, shadow
+        // rays too, go through the cut out parts to whatever is behind them.
+        if !hit.material.is_opaque_at(&hit) {
             return None;
         }
+        return Some(hit);
+    }
+}
 
-        if t > f32::EPSILON {
-            let intersection_point = ray.point_at(t);
-            let normal = (e1).cross(e2).normalize();
-            return Some(Hit {
-                point: intersection_point,
-                material: self.material_set.get(material_name),
-                normal: correct_face_normal(ray, normal),
-                is_facing_you: ray.direction.dot(normal) < 0.0,
-                point_at_intersection: t,
-            });
-        }
+// Indices of a face have to point into the lists of its own object.
+fn check_indices(
+    object: &str,
+    what: &str,
+    indices: [usize; 3],
+    count: usize,
+) -> Result<(), String> {
+    return match indices.iter().find(|index| **index >= count) {
+        // Counted from 1, like in obj files.
+        Some(index) => Err(format!(
+            "A face of '{}' refers to {} {}, but there are only {}",
+            object,
+            what,
+            index + 1,
+            count
+        )),
+        None => Ok(()),
+    };
+}
 
-        return None;
+// Angle of the triangle at one of its corners, in radians.
+#[inline]
+fn corner_angle(corners: [Vector3<f32>; 3], corner: usize) -> f32 {
+    let vertex = corners[corner];
+    let to_next = corners[(corner + 1) % 3] - vertex;
+    let to_previous = corners[(corner + 2) % 3] - vertex;
+    if to_next.magnitude2() == 0.0 || to_previous.magnitude2() == 0.0 {
+        return 0.0;
     }
+    let cos_angle = to_next.normalize().dot(to_previous.normalize());
+    return cos_angle.clamp(-1.0, 1.0).acos();
 }
 
 impl Hitable for Mesh {
-    fn intersect(&self, ray: &Ray, bounds: Interval) -> Option<Hit> {
-        let mut hit: Option<Hit> = None;
-        let mut closest_so_far: f32 = bounds.max;
-
-        for obj in &self.geometry.objects {
-            for geom in &obj.geometry {
-                let material_name = geom.material_name.as_ref().unwrap();
-                for shape in &geom.shapes {
-                    match shape.primitive {
-                        // Each vertex is made out of VertexIndex, Option<TextureIndex>, Option<NormalIndex>
-                        Primitive::Triangle(a, b, c) => {
-                            let maybe_hit = self.intersect_triangle(
-                                ray,
-                                &(a, b, c),
-                                material_name,
-                                &obj.vertices,
-                                Interval::new(bounds.min, closest_so_far),
-                            );
-                            if let Some(hit_point) = maybe_hit {
-                                if closest_so_far > hit_point.point_at_intersection {
-                                    closest_so_far = hit_point.point_at_intersection;
-                                    hit = Some(hit_point);
-                                }
-                            }
-                        }
-                        _ => continue,
-                    }
-                }
-            }
-        }
-        return hit;
+    fn intersect(&self, ray: &Ray, bounds: Interval) -> Option<Hit<'_>> {
+        return self.intersect_with(ray, bounds, TriangleIntersector::default());
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -223,31 +417,176 @@ impl Hitable for Mesh {
     }
 }
 
-fn triangle_bounding_box(
-    triangle: &(VTNIndex, VTNIndex, VTNIndex),
-    vertices_cache: &Vec<Vertex>,
-) -> AABB {
-    let (p1, p2, p3) = triangle;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::principled::Principled;
+    use crate::materials::procedural::{Pattern, PatternKind};
+    use crate::materials::texture::Texture;
+    use std::sync::Arc;
+
+    #[test]
+    fn rays_go_through_cut_out_parts() {
+        let quad = wavefront_obj::obj::parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n",
+        )
+        .unwrap();
+        // Opaque on the left half, cut out on the right one.
+        let mut halves = Pattern::new(
+            PatternKind::Checker,
+            Texture::uniform(1.0),
+            Texture::uniform(0.0),
+        );
+        halves.scale = 2.0;
+        let mut leaf = Principled::new(Vector3::new(0.2, 0.6, 0.1));
+        leaf.alpha_map = Some(Texture::Pattern(Arc::new(halves)));
+        let mesh = Mesh::new_override_material(quad, Material::Principled(leaf)).unwrap();
+
+        let bounds = Interval::new(0.001, f32::MAX);
+        let towards = |x: f32| Ray {
+            origin: Vector3::new(x, 0.25, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        for _ in 0..100 {
+            assert!(mesh.intersect(&towards(0.25), bounds).is_some());
+            assert!(mesh.intersect(&towards(0.75), bounds).is_none());
+        }
+    }
 
-    let (vertex_index_1, _, _) = p1;
-    let (vertex_index_2, _, _) = p2;
-    let (vertex_index_3, _, _) = p3;
+    #[test]
+    fn rejects_faces_with_out_of_range_indices() {
+        let triangle = || {
+            return wavefront_obj::obj::parse(
+                "o tri\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nf 1/1/1 2/1/1 3/1/1\n",
+            )
+            .unwrap();
+        };
+        assert!(Mesh::new(triangle()).is_ok());
+
+        // The obj parser checks indices, but glTF files are converted into ObjSets as they are.
+        let corrupt = |corner: (usize, Option<usize>, Option<usize>)| {
+            let mut geometry = triangle();
+            let shape = &mut geometry.objects[0].geometry[0].shapes[0];
+            let Primitive::Triangle(a, b, _) = shape.primitive else {
+                unreachable!();
+            };
+            shape.primitive = Primitive::Triangle(a, b, corner);
+            return Mesh::new(geometry).err().unwrap();
+        };
+        assert_eq!(
+            corrupt((3, Some(0), Some(0))),
+            "A face of 'tri' refers to vertex 4, but there are only 3"
+        );
+        assert_eq!(
+            corrupt((2, Some(1), Some(0))),
+            "A face of 'tri' refers to texture coordinate 2, but there are only 1"
+        );
+        assert_eq!(
+            corrupt((2, Some(0), Some(5))),
+            "A face of 'tri' refers to normal 6, but there are only 1"
+        );
+    }
 
-    let maybe_v1 = vertices_cache.get(*vertex_index_1);
-    let maybe_v2 = vertices_cache.get(*vertex_index_2);
-    let maybe_v3 = vertices_cache.get(*vertex_index_3);
+    fn face_normal(mesh: &Mesh, triangle_index: usize) -> Vector3<f32> {
+        let [a, b, c] = mesh.triangle_vertices(triangle_index);
+        return (b - a).cross(c - a).normalize();
+    }
 
-    if maybe_v1.is_none() || maybe_v2.is_none() || maybe_v3.is_none() {
-        panic!("Some vertices weren't assembled together into a triangle");
+    #[test]
+    fn keeps_the_edges_of_a_cube_sharp() {
+        let cube = wavefront_obj::obj::parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n\
+             f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 4 8 7 3\nf 1 5 8 4\nf 2 3 7 6\n",
+        )
+        .unwrap();
+        let mut mesh = Mesh::new(cube).unwrap();
+        // Flat by default, and still flat when its faces are 90 degrees apart.
+        for crease_angle in [None, Some(60.0)] {
+            if let Some(crease_angle) = crease_angle {
+                mesh.generate_normals(crease_angle);
+            }
+            for triangle_index in 0..mesh.triangle_count() {
+                let face_normal = face_normal(&mesh, triangle_index);
+                for normal in mesh.corner_normals[triangle_index] {
+                    assert!((normal - face_normal).magnitude() < 1e-5);
+                }
+            }
+        }
     }
 
-    let a = convert_to_cgmath_vec(*maybe_v1.unwrap());
-    let b = convert_to_cgmath_vec(*maybe_v2.unwrap());
-    let c = convert_to_cgmath_vec(*maybe_v3.unwrap());
+    #[test]
+    fn smooths_a_sphere_into_radial_normals() {
+        // A UV sphere of radius 1, with its poles as the first and last vertices.
+        let (columns, rows) = (32, 16);
+        let mut source = String::from("v 0 1 0\n");
+        for row in 1..rows {
+            let theta = std::f32::consts::PI * row as f32 / rows as f32;
+            for column in 0..columns {
+                let phi = 2.0 * std::f32::consts::PI * column as f32 / columns as f32;
+                let (x, y, z) = (
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    -theta.sin() * phi.sin(),
+                );
+                source += &format!("v {} {} {}\n", x, y, z);
+            }
+        }
+        source += "v 0 -1 0\n";
+        let ring_vertex = |row: usize, column: usize| 2 + (row - 1) * columns + column % columns;
+        let bottom = 2 + (rows - 1) * columns;
+        for column in 0..columns {
+            source += &format!(
+                "f 1 {} {}\n",
+                ring_vertex(1, column),
+                ring_vertex(1, column + 1)
+            );
+            for row in 1..rows - 1 {
+                source += &format!(
+                    "f {} {} {} {}\n",
+                    ring_vertex(row, column),
+                    ring_vertex(row + 1, column),
+                    ring_vertex(row + 1, column + 1),
+                    ring_vertex(row, column + 1)
+                );
+            }
+            source += &format!(
+                "f {} {} {}\n",
+                bottom,
+                ring_vertex(rows - 1, column + 1),
+                ring_vertex(rows - 1, column)
+            );
+        }
 
-    let aabb1 = AABB::new_from_diagonals(a, b);
-    let aabb2 = AABB::new_from_diagonals(a, c);
-    let final_aabb = AABB::new_from_aabbs(aabb1, aabb2);
+        let mut mesh = Mesh::new(wavefront_obj::obj::parse(source).unwrap()).unwrap();
+        mesh.generate_normals(60.0);
+        for triangle_index in 0..mesh.triangle_count() {
+            let [a, b, c] = mesh.triangle_vertices(triangle_index);
+            assert!(face_normal(&mesh, triangle_index).dot(a + b + c) > 0.0);
+            for (corner, normal) in [a, b, c].iter().zip(mesh.corner_normals[triangle_index]) {
+                assert!(normal.dot(corner.normalize()) > 0.999);
+            }
+        }
+    }
 
-    return final_aabb;
+    #[test]
+    fn prefers_the_normals_of_the_file() {
+        // The normal of the last corner is degenerate, so it falls back to the face normal.
+        let triangle = wavefront_obj::obj::parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 1 0 1\nvn 0 0 0\nf 1//1 2//1 3//2\n",
+        )
+        .unwrap();
+        let mut mesh = Mesh::new(triangle).unwrap();
+        mesh.generate_normals(60.0);
+
+        let file_normal = Vector3::new(1.0, 0.0, 1.0).normalize();
+        let indices = mesh.triangles[0].normals.unwrap();
+        for (index, normal) in indices.iter().zip(mesh.corner_normals[0]) {
+            let expected = if *index == 0 {
+                file_normal
+            } else {
+                face_normal(&mesh, 0)
+            };
+            assert!((normal - expected).magnitude() < 1e-5);
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use cgmath::Vector3;
+use cgmath::{Vector2, Vector3};
 
 use crate::accel::aabb::AABB;
 use crate::materials::material::Material;
@@ -10,11 +10,11 @@ pub trait Hitable {
     //
     // Returns an Intersect object, which contains all necessary information to bounce / render.
     // Should return None if there is no intersection
-    fn intersect(&self, ray: &Ray, bounds: Interval) -> Option<Hit>;
+    fn intersect(&self, ray: &Ray, bounds: Interval) -> Option<Hit<'_>>;
     fn bounding_box(&self) -> &AABB;
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct Hit<'a> {
     // Given a vector
     // a --- (p) ------> b
@@ -26,10 +26,34 @@ pub struct Hit<'a> {
 
     // Normal vector denoting whether the hit came from the inside or outside
     // since by just a single point you have no idea.
+    // This is the shading normal, which may be interpolated across the surface.
     pub normal: Vector3<f32>,
 
+    // The actual normal of the surface, on the same side as `normal`.
+    // Decides which side of the surface a direction is on.
+    pub geometric_normal: Vector3<f32>,
+
     pub is_facing_you: bool,
 
+    // Texture coordinates of the hit point, (0, 0) if the surface has none.
+    pub uv: Vector2<f32>,
+
+    // How the hit point moves along the surface with the texture coordinates (dp/du and dp/dv),
+    // zero if the surface has none. Normal and bump maps are oriented by them.
+    pub dpdu: Vector3<f32>,
+    pub dpdv: Vector3<f32>,
+
     // Material, expressing what has been hit
     pub material: &'a Material,
+
+    // Which primitive has been hit, filled in as the hit travels up through the scene.
+    pub primitive: PrimitiveId,
+}
+
+// Uniquely identifies a primitive in the scene: the top-level object (sphere or mesh instance)
+// and, for meshes, the triangle within it. Lets hits on emitters be matched with the scene's lights.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PrimitiveId {
+    pub object: usize,
+    pub triangle: usize,
 }
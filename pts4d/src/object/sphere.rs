@@ -1,17 +1,21 @@
-use cgmath::{dot, InnerSpace, Vector3};
+use std::f32::consts::PI;
+
+use cgmath::{dot, InnerSpace, Vector2, Vector3};
 
 use crate::{
     accel::aabb::AABB,
-    materials::material::Material,
+    materials::{material::Material, medium::Medium},
     utils::vector_utils::{correct_face_normal, Interval, Ray},
 };
 
-use super::object::{Hit, Hitable};
+use super::object::{Hit, Hitable, PrimitiveId};
 
 pub struct Sphere {
     pub center: Vector3<f32>,
     pub radius: f32,
     pub material: Material,
+    // What fills the sphere, for rays passing through its surface.
+    pub interior: Option<Medium>,
     bbox: AABB,
 }
 
@@ -26,13 +30,36 @@ impl Sphere {
             center,
             radius,
             material,
+            interior: None,
             bbox: Sphere::compute_bounding_box(center, radius),
         }
     }
 }
 
+// Latitude-longitude coordinates of a point on the unit sphere, v goes from -Y up to +Y.
+#[inline]
+fn sphere_uv(normal: Vector3<f32>) -> Vector2<f32> {
+    let phi = (-normal.z).atan2(normal.x) + PI;
+    let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+    return Vector2::new(phi / (2.0 * PI), theta / PI);
+}
+
+// How a point on the sphere, `offset` from its center, moves with its coordinates (dp/du, dp/dv).
+// Zero at the poles, where u is undefined.
+#[inline]
+fn sphere_derivatives(offset: Vector3<f32>, radius: f32) -> (Vector3<f32>, Vector3<f32>) {
+    let normal = offset / radius;
+    let sin_theta = (1.0 - normal.y * normal.y).max(0.0).sqrt();
+    if sin_theta < 1e-4 {
+        return (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+    }
+    let dpdu = 2.0 * PI * Vector3::new(offset.z, 0.0, -offset.x);
+    let dpdv = (PI * radius / sin_theta) * (Vector3::new(0.0, 1.0, 0.0) - normal.y * normal);
+    return (dpdu, dpdv);
+}
+
 impl Hitable for Sphere {
-    fn intersect(&self, ray: &Ray, bounds: Interval) -> Option<Hit> {
+    fn intersect(&self, ray: &Ray, bounds: Interval) -> Option<Hit<'_>> {
         let oc = ray.origin - self.center;
         let a = dot(ray.direction, ray.direction); // || ray.direction ||^2
         let b = 2.0 * dot(oc, ray.direction);
@@ -44,12 +71,18 @@ impl Hitable for Sphere {
             if x1 < bounds.max && x1 > bounds.min {
                 let ray_t = ray.point_at(x1);
                 let normal = ((ray_t - self.center) / self.radius).normalize();
+                let (dpdu, dpdv) = sphere_derivatives(ray_t - self.center, self.radius);
                 return Some(Hit {
                     point_at_intersection: x1,
                     point: ray_t,
                     normal: correct_face_normal(ray, normal),
+                    geometric_normal: correct_face_normal(ray, normal),
                     is_facing_you: ray.direction.dot(normal) < 0.0,
+                    uv: sphere_uv(normal),
+                    dpdu,
+                    dpdv,
                     material: &self.material,
+                    primitive: PrimitiveId::default(),
                 });
             }
 
@@ -57,12 +90,18 @@ impl Hitable for Sphere {
             if x2 < bounds.max && x2 > bounds.min {
                 let ray_t = ray.point_at(x2);
                 let normal = ((ray_t - self.center) / self.radius).normalize();
+                let (dpdu, dpdv) = sphere_derivatives(ray_t - self.center, self.radius);
                 return Some(Hit {
                     point_at_intersection: x2,
                     point: ray_t,
                     normal: correct_face_normal(ray, normal),
+                    geometric_normal: correct_face_normal(ray, normal),
                     is_facing_you: ray.direction.dot(normal) < 0.0,
+                    uv: sphere_uv(normal),
+                    dpdu,
+                    dpdv,
                     material: &self.material,
+                    primitive: PrimitiveId::default(),
                 });
             }
         }
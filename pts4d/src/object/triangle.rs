@@ -0,0 +1,310 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::utils::vector_utils::{Interval, Ray};
+
+// Which ray-triangle test meshes are intersected with.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[allow(dead_code)]
+pub enum TriangleIntersector {
+    // Fast, but rays may slip through edges shared between triangles.
+    #[default]
+    MollerTrumbore,
+    // Woop, Benthin and Wald 2013, never misses a shared edge or vertex.
+    Watertight,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TriangleHit {
+    pub t: f32,
+    // Barycentric coordinates, the weights of the second and third vertex.
+    pub u: f32,
+    pub v: f32,
+}
+
+impl TriangleIntersector {
+    #[inline]
+    pub fn intersect(
+        &self,
+        ray: &Ray,
+        a: Vector3<f32>,
+        b: Vector3<f32>,
+        c: Vector3<f32>,
+        bounds: Interval,
+    ) -> Option<TriangleHit> {
+        match self {
+            TriangleIntersector::MollerTrumbore => intersect_moller_trumbore(ray, a, b, c, bounds),
+            TriangleIntersector::Watertight => intersect_watertight(ray, a, b, c, bounds),
+        }
+    }
+}
+
+pub fn intersect_moller_trumbore(
+    ray: &Ray,
+    a: Vector3<f32>,
+    b: Vector3<f32>,
+    c: Vector3<f32>,
+    bounds: Interval,
+) -> Option<TriangleHit> {
+    let e1 = b - a;
+    let e2 = c - a;
+
+    let ray_cross_e2 = ray.direction.cross(e2);
+    let det = e1.dot(ray_cross_e2);
+
+    if det > -f32::EPSILON && det < f32::EPSILON {
+        return None; // This ray is parallel to this triangle.
+    }
+
+    let inv_det = 1.0 / det;
+    let s = ray.origin - a;
+    let u = inv_det * s.dot(ray_cross_e2);
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let s_cross_e1 = s.cross(e1);
+    let v = inv_det * ray.direction.dot(s_cross_e1);
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    // At this stage we can compute t to find out where the intersection point is on the line.
+    let t = inv_det * e2.dot(s_cross_e1);
+    if t > bounds.max || t < bounds.min || t <= f32::EPSILON {
+        return None;
+    }
+
+    return Some(TriangleHit { t, u, v });
+}
+
+// Watertight ray-triangle intersection (https://jcgt.org/published/0002/01/05/paper.pdf).
+// The triangle is moved into a ray-aligned space where the ray starts at the origin and goes
+// along +Z, which turns the test into a 2D one done with edge functions. Edges shared between
+// triangles are evaluated identically from both sides, so a ray can never fall in between.
+pub fn intersect_watertight(
+    ray: &Ray,
+    a: Vector3<f32>,
+    b: Vector3<f32>,
+    c: Vector3<f32>,
+    bounds: Interval,
+) -> Option<TriangleHit> {
+    let direction = ray.direction;
+
+    // Pick the dominant axis of the ray as Z, and keep the winding of the remaining two.
+    let kz = max_dimension(direction);
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if direction[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    // Shear constants.
+    let sx = direction[kx] / direction[kz];
+    let sy = direction[ky] / direction[kz];
+    let sz = 1.0 / direction[kz];
+
+    let a_rel = a - ray.origin;
+    let b_rel = b - ray.origin;
+    let c_rel = c - ray.origin;
+
+    let ax = a_rel[kx] - sx * a_rel[kz];
+    let ay = a_rel[ky] - sy * a_rel[kz];
+    let bx = b_rel[kx] - sx * b_rel[kz];
+    let by = b_rel[ky] - sy * b_rel[kz];
+    let cx = c_rel[kx] - sx * c_rel[kz];
+    let cy = c_rel[ky] - sy * c_rel[kz];
+
+    // Scaled barycentric coordinates, as 2D edge functions.
+    let mut u = cx * by - cy * bx;
+    let mut v = ax * cy - ay * cx;
+    let mut w = bx * ay - by * ax;
+
+    // Exactly on an edge, redo the edge functions in double precision to break the tie consistently.
+    if u == 0.0 || v == 0.0 || w == 0.0 {
+        u = (cx as f64 * by as f64 - cy as f64 * bx as f64) as f32;
+        v = (ax as f64 * cy as f64 - ay as f64 * cx as f64) as f32;
+        w = (bx as f64 * ay as f64 - by as f64 * ax as f64) as f32;
+    }
+
+    // Both front and back faces are accepted, so only mixed signs mean a miss.
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let az = sz * a_rel[kz];
+    let bz = sz * b_rel[kz];
+    let cz = sz * c_rel[kz];
+    let t_scaled = u * az + v * bz + w * cz;
+
+    let inv_det = 1.0 / det;
+    let t = t_scaled * inv_det;
+    if t > bounds.max || t < bounds.min || t <= 0.0 {
+        return None;
+    }
+
+    return Some(TriangleHit {
+        t,
+        u: v * inv_det,
+        v: w * inv_det,
+    });
+}
+
+#[inline]
+fn max_dimension(vector: Vector3<f32>) -> usize {
+    let abs = Vector3::new(vector.x.abs(), vector.y.abs(), vector.z.abs());
+    if abs.x > abs.y && abs.x > abs.z {
+        return 0;
+    }
+    if abs.y > abs.z {
+        return 1;
+    }
+    return 2;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    fn bounds() -> Interval {
+        return Interval::new(0.001, f32::MAX);
+    }
+
+    // Counts how many of the triangles the ray hits.
+    fn count_hits(ray: &Ray, triangles: &[[Vector3<f32>; 3]]) -> usize {
+        return triangles
+            .iter()
+            .filter(|[a, b, c]| intersect_watertight(ray, *a, *b, *c, bounds()).is_some())
+            .count();
+    }
+
+    // A fan of `n` triangles around `center`, all sharing it as a vertex.
+    fn fan(center: Vector3<f32>, radius: f32, n: usize) -> Vec<[Vector3<f32>; 3]> {
+        let point = |i: usize| {
+            let angle = 2.0 * PI * (i % n) as f32 / n as f32;
+            center + radius * Vector3::new(angle.cos(), angle.sin(), 0.0)
+        };
+        return (0..n).map(|i| [center, point(i), point(i + 1)]).collect();
+    }
+
+    #[test]
+    fn hits_the_inside_and_misses_the_outside() {
+        let (a, b, c) = (
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+        let towards = |x: f32, y: f32| Ray {
+            origin: Vector3::new(x, y, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+
+        let hit = intersect_watertight(&towards(0.25, 0.25), a, b, c, bounds()).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-6);
+        assert!((hit.u - 0.25).abs() < 1e-6);
+        assert!((hit.v - 0.25).abs() < 1e-6);
+
+        assert!(intersect_watertight(&towards(0.75, 0.75), a, b, c, bounds()).is_none());
+        assert!(intersect_watertight(&towards(-0.1, 0.5), a, b, c, bounds()).is_none());
+
+        // Triangles behind the ray, or past the bounds, are not hit.
+        let away = Ray {
+            origin: Vector3::new(0.25, 0.25, 1.0),
+            direction: Vector3::new(0.0, 0.0, 1.0),
+        };
+        assert!(intersect_watertight(&away, a, b, c, bounds()).is_none());
+        assert!(
+            intersect_watertight(&towards(0.25, 0.25), a, b, c, Interval::new(0.001, 0.5))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn never_misses_a_shared_edge() {
+        // Two triangles of a skewed and tilted (but planar) quad, sharing the diagonal from `a` to `c`.
+        let a = Vector3::new(-1.3, -0.7, -2.1);
+        let b = Vector3::new(1.1, -0.9, -2.7);
+        let c = Vector3::new(0.9, 1.2, -3.3);
+        let d = a + (c - b);
+        let quad = [[a, b, c], [a, c, d]];
+
+        let origins = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.3, -0.2, 1.7),
+            Vector3::new(-2.5, 1.5, 3.0),
+            Vector3::new(4.0, 0.1, -0.5),
+        ];
+
+        for origin in origins {
+            for i in 0..=1000 {
+                // Exactly on the shared edge, as far as f32 goes.
+                let target = a + (c - a) * (i as f32 / 1000.0);
+                let ray = Ray {
+                    origin,
+                    direction: target - origin,
+                };
+                assert!(
+                    count_hits(&ray, &quad) >= 1,
+                    "Ray from {:?} towards {:?} slipped through the shared edge",
+                    origin,
+                    target
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn never_misses_a_shared_vertex() {
+        let center = Vector3::new(0.37, -0.41, -1.9);
+        let triangles = fan(center, 0.8, 7);
+
+        for i in 0..500 {
+            let angle = i as f32 * 0.731;
+            let origin = Vector3::new(
+                2.0 * angle.cos(),
+                2.0 * angle.sin(),
+                0.5 + (i % 13) as f32 * 0.3,
+            );
+            let ray = Ray {
+                origin,
+                direction: center - origin,
+            };
+            assert!(
+                count_hits(&ray, &triangles) >= 1,
+                "Ray from {:?} slipped through the shared vertex",
+                origin
+            );
+        }
+    }
+
+    #[test]
+    fn agrees_with_moller_trumbore_away_from_edges() {
+        let (a, b, c) = (
+            Vector3::new(-1.0, -1.0, -3.0),
+            Vector3::new(2.0, -0.5, -4.0),
+            Vector3::new(0.0, 1.5, -2.5),
+        );
+
+        for i in 0..100 {
+            let u = 0.1 + 0.6 * (i % 10) as f32 / 10.0;
+            let v = 0.1 + 0.2 * (i / 10) as f32 / 10.0;
+            let target = a + u * (b - a) + v * (c - a);
+            let ray = Ray {
+                origin: Vector3::new(0.2, 0.1, 1.0),
+                direction: target - Vector3::new(0.2, 0.1, 1.0),
+            };
+
+            let watertight = intersect_watertight(&ray, a, b, c, bounds()).unwrap();
+            let moller = intersect_moller_trumbore(&ray, a, b, c, bounds()).unwrap();
+            assert!((watertight.t - moller.t).abs() < 1e-4);
+            assert!((watertight.u - moller.u).abs() < 1e-4);
+            assert!((watertight.v - moller.v).abs() < 1e-4);
+        }
+    }
+}
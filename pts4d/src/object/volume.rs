@@ -0,0 +1,92 @@
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::{
+    accel::aabb::AABB,
+    materials::{material::Material, medium::Medium},
+    utils::vector_utils::{Interval, Ray},
+};
+
+use super::object::{Hit, Hitable, PrimitiveId};
+
+/**
+ * A box of the scene filled with a medium, e.g. a cloud of smoke from a voxel grid.
+ * Its sides are invisible, hitting them only moves rays in or out of the medium.
+ */
+pub struct Volume {
+    pub medium: Medium,
+    material: Material,
+    bbox: AABB,
+}
+
+impl Volume {
+    pub fn new(bounds: AABB, medium: Medium) -> Volume {
+        return Volume {
+            medium,
+            material: Material::Interface,
+            bbox: bounds,
+        };
+    }
+}
+
+impl Hitable for Volume {
+    fn intersect(&self, ray: &Ray, bounds: Interval) -> Option<Hit<'_>> {
+        // Slab test, remembering through which axis the ray enters and leaves.
+        let mut near = f32::NEG_INFINITY;
+        let mut far = f32::INFINITY;
+        let mut near_axis = 0;
+        let mut far_axis = 0;
+
+        for axis in 0..3 {
+            let interval = self.bbox.get_axis(axis);
+            let inv_direction = 1.0 / ray.direction[axis];
+            let mut t0 = (interval.min - ray.origin[axis]) * inv_direction;
+            let mut t1 = (interval.max - ray.origin[axis]) * inv_direction;
+            if inv_direction < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            if t0 > near {
+                near = t0;
+                near_axis = axis;
+            }
+            if t1 < far {
+                far = t1;
+                far_axis = axis;
+            }
+        }
+
+        if far < near {
+            return None;
+        }
+
+        let (t, axis) = if near > bounds.min && near < bounds.max {
+            (near, near_axis)
+        } else if far > bounds.min && far < bounds.max {
+            (far, far_axis)
+        } else {
+            return None;
+        };
+
+        // The side of the box which was hit, facing back towards the ray.
+        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+        normal[axis] = -ray.direction[axis].signum();
+        let normal = normal.normalize();
+
+        return Some(Hit {
+            point_at_intersection: t,
+            point: ray.point_at(t),
+            normal,
+            geometric_normal: normal,
+            is_facing_you: t == near,
+            uv: Vector2::new(0.0, 0.0),
+            dpdu: Vector3::new(0.0, 0.0, 0.0),
+            dpdv: Vector3::new(0.0, 0.0, 0.0),
+            material: &self.material,
+            primitive: PrimitiveId::default(),
+        });
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        return &self.bbox;
+    }
+}
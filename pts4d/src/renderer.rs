@@ -1,113 +1,612 @@
-use crate::accel::aabb::HitableAccelStructure;
-use crate::object::object::{Hit, Hitable};
+use crate::lights::light::{Light, LightSample};
+use crate::materials::bsdf::SurfaceBsdf;
+use crate::materials::material::{Lambertian, Material};
+use crate::materials::medium::{Medium, MediumEvent};
+use crate::materials::subsurface::Subsurface;
+use crate::object::object::Hit;
 use crate::scene::scene::Scene;
 use crate::scene::screen::Screen;
-use crate::scene::screen::{HEIGHT, WIDTH};
+use crate::scene::settings::RenderSettings;
+use crate::utils::spectrum::Wavelengths;
 use crate::utils::vector_utils::{Interval, Ray};
-use cgmath::{ElementWise, Vector3};
+use cgmath::{ElementWise, InnerSpace, Vector3};
+use rand::Rng;
 use rayon::prelude::*;
-use std::f32::MAX;
 
-const MAX_DEPTH: i32 = 8;
-pub const SAMPLES_PER_PIXEL: i32 = 35;
 const MIN_T: f32 = 0.001;
-const DEBUG_AABB: bool = false;
+// Scattering events after which light is given up on inside of subsurface materials.
+const MAX_WALK_STEPS: usize = 256;
 
-pub fn ray_trace(scene: &Scene, ray: &Ray) -> Vector3<f32> {
-    return ray_trace_rec(scene, ray, 0);
+// How the ray currently being traced was sampled at the previous bounce.
+// Needed to weigh emitters found by chance against sampling them directly.
+struct Bounce {
+    origin: Vector3<f32>,
+    pdf: f32,
+    is_delta: bool,
 }
 
-// Recursively ray-trace until the number of bounces has reached MAX_DEPTH
-pub fn ray_trace_rec(scene: &Scene, ray: &Ray, bounces: i32) -> Vector3<f32> {
-    if bounces >= MAX_DEPTH {
+// The radiance arriving along the ray, in RGB, or at the given wavelengths in spectral mode.
+pub fn ray_trace(
+    scene: &Scene,
+    settings: &RenderSettings,
+    ray: &Ray,
+    wavelengths: Option<&Wavelengths>,
+) -> Vector3<f32> {
+    return ray_trace_rec(
+        scene,
+        settings,
+        ray,
+        0,
+        None,
+        scene.fog.as_ref(),
+        wavelengths,
+    );
+}
+
+// Recursively ray-trace until the number of bounces has reached the maximum depth.
+// `medium` is whatever the ray is travelling through.
+fn ray_trace_rec(
+    scene: &Scene,
+    settings: &RenderSettings,
+    ray: &Ray,
+    bounces: i32,
+    previous: Option<&Bounce>,
+    medium: Option<&Medium>,
+    wavelengths: Option<&Wavelengths>,
+) -> Vector3<f32> {
+    if bounces >= settings.max_depth {
         // Stare into the void!
         return Vector3::new(0.0, 0.0, 0.0);
     }
 
-    let mut closest_hit: Option<Hit> = None;
-    let mut closest_t = MAX;
+    if settings.debug_aabb {
+        // Heatmap of how many bounding boxes the ray had to go through.
+        let node_hits = scene.count_bvh_node_hits(ray, Interval::new(MIN_T, f32::MAX));
+        return Vector3::new(node_hits as f32 / 64.0, 0.0, 0.0);
+    }
 
-    for obj in &scene.spheres {
-        if obj
-            .bounding_box()
-            .intersect(ray, Interval::new(MIN_T, closest_t))
-        {
-            if DEBUG_AABB {
-                return Vector3::new(1.0, 0.0, 0.0);
-            }
-            if let Some(hit) = cast_ray(obj, ray, closest_t) {
-                closest_t = hit.point_at_intersection;
-                closest_hit = Some(hit);
+    let closest_hit = scene.intersect(ray, Interval::new(MIN_T, f32::MAX));
+
+    // The ray may scatter in the medium before it gets to the surface.
+    let mut medium_weight = Vector3::new(1.0, 1.0, 1.0);
+    if let Some(medium) = medium {
+        let speed = ray.direction.magnitude();
+        let max_distance = closest_hit
+            .as_ref()
+            .map_or(f32::INFINITY, |hit| hit.point_at_intersection * speed);
+
+        let direction = ray.direction / speed;
+        match medium.sample_distance(ray.origin, direction, max_distance) {
+            MediumEvent::Scattered { distance, weight } => {
+                let point = ray.origin + distance * direction;
+                let direct_color =
+                    sample_direct_light_in_medium(scene, medium, point, direction, wavelengths);
+
+                let scattered_direction = medium.sample_phase(direction);
+                let bounce = Bounce {
+                    origin: point,
+                    pdf: medium.phase(direction, scattered_direction),
+                    is_delta: false,
+                };
+                let scattered_ray = Ray {
+                    origin: point,
+                    direction: scattered_direction,
+                };
+                // The phase function is sampled exactly, it cancels out with its pdf.
+                let scattered_color = ray_trace_rec(
+                    scene,
+                    settings,
+                    &scattered_ray,
+                    bounces + 1,
+                    Some(&bounce),
+                    Some(medium),
+                    wavelengths,
+                );
+                return spectral(weight, wavelengths)
+                    .mul_element_wise(direct_color + scattered_color);
             }
+            MediumEvent::Passed { weight } => medium_weight = spectral(weight, wavelengths),
         }
     }
 
-    for obj in &scene.meshes {
-        if obj
-            .bounding_box()
-            .intersect(ray, Interval::new(MIN_T, closest_t))
-        {
-            if DEBUG_AABB {
-                return Vector3::new(0.0, 1.0, 0.0);
-            }
-            if let Some(hit) = cast_ray(obj, ray, closest_t) {
-                closest_t = hit.point_at_intersection;
-                closest_hit = Some(hit);
-            }
+    if let Some(hit) = closest_hit {
+        // Boundaries of media aren't really there, the ray just carries on in the new medium.
+        // Neither are lights hidden from the camera, for camera rays.
+        let is_hidden = previous.is_none() && !scene.is_visible_to_camera(hit.primitive);
+        if matches!(hit.material, Material::Interface) || is_hidden {
+            let continued_ray = Ray {
+                origin: hit.point,
+                direction: ray.direction,
+            };
+            let next_medium = scene.medium_after_crossing(&hit, medium);
+            return medium_weight.mul_element_wise(ray_trace_rec(
+                scene,
+                settings,
+                &continued_ray,
+                bounces,
+                previous,
+                next_medium,
+                wavelengths,
+            ));
         }
+
+        // Light found by a BSDF sampled ray, weighted against having sampled it directly.
+        let emitted_color = emission_weight(scene, &hit, previous)
+            * spectral(hit.material.emit(ray, &hit), wavelengths);
+
+        let hero_wavelength = wavelengths.map(|wavelengths| wavelengths.hero());
+        let Some(bsdf) = hit.material.bsdf(ray, &hit, hero_wavelength) else {
+            return medium_weight.mul_element_wise(emitted_color);
+        };
+
+        let scattered_color =
+            scatter_light(scene, settings, &bsdf, &hit, bounces, medium, wavelengths);
+        return medium_weight.mul_element_wise(emitted_color + scattered_color);
     }
 
-    if let Some(hit) = closest_hit {
-        let emitted_color = hit.material.emit(ray);
+    // Stare into the void once again! Unless there's an environment all around.
+    let Some(environment) = scene.lights.environment() else {
+        return Vector3::new(0.0, 0.0, 0.0);
+    };
+    let direction = ray.direction.normalize();
+    let weight = match previous {
+        None => 1.0,
+        Some(bounce) if bounce.is_delta => 1.0,
+        Some(bounce) => power_heuristic(bounce.pdf, scene.lights.environment_pdf(direction)),
+    };
+    let radiance = spectral(environment.radiance(direction), wavelengths);
+    return medium_weight.mul_element_wise(radiance) * weight;
+}
+
+// Light the BSDF at the hit scatters along the ray: from shadow rays, and from tracing a sampled
+// direction further.
+fn scatter_light(
+    scene: &Scene,
+    settings: &RenderSettings,
+    bsdf: &SurfaceBsdf,
+    hit: &Hit,
+    bounces: i32,
+    medium: Option<&Medium>,
+    wavelengths: Option<&Wavelengths>,
+) -> Vector3<f32> {
+    // Only the parts of the BSDF which can be evaluated are lit by shadow rays, the delta
+    // ones get their light by sampling.
+    let direct_color = if bsdf.flags().non_delta {
+        sample_direct_light(scene, bsdf, hit, medium, wavelengths)
+    } else {
+        Vector3::new(0.0, 0.0, 0.0)
+    };
+
+    let Some(scatter) = bsdf.sample() else {
+        return direct_color;
+    };
+
+    // Going through the surface puts the ray into whatever is on the other side.
+    let goes_through = scatter.ray.direction.dot(hit.geometric_normal) < 0.0;
+    if goes_through && matches!(hit.material, Material::Subsurface(_)) {
+        let attenuation = spectral(scatter.attenuation, wavelengths);
+        let subsurface_color = subsurface_light(
+            scene,
+            settings,
+            hit,
+            &scatter.ray,
+            bounces + 1,
+            medium,
+            wavelengths,
+        );
+        return direct_color + attenuation.mul_element_wise(subsurface_color);
+    }
+    let next_medium = if goes_through {
+        scene.medium_after_crossing(hit, medium)
+    } else {
+        medium
+    };
 
-        if let Some((scattered, attenuation)) = hit.material.scatter(ray, &hit) {
-            let scattered_color =
-                attenuation.mul_element_wise(ray_trace_rec(scene, &scattered, bounces + 1));
-            return emitted_color + scattered_color;
+    let mut attenuation = spectral(scatter.attenuation, wavelengths);
+    let mut next_wavelengths = wavelengths.copied();
+    if let Some(wavelengths) = wavelengths.filter(|_| bsdf.flags().dispersion) {
+        let (hero_only, weight) = wavelengths.only_hero();
+        attenuation = attenuation.mul_element_wise(weight);
+        next_wavelengths = Some(hero_only);
+    }
+
+    let bounce = Bounce {
+        origin: hit.point,
+        pdf: scatter.pdf,
+        is_delta: scatter.is_delta,
+    };
+    let scattered_color = attenuation.mul_element_wise(ray_trace_rec(
+        scene,
+        settings,
+        &scatter.ray,
+        bounces + 1,
+        Some(&bounce),
+        next_medium,
+        next_wavelengths.as_ref(),
+    ));
+    return direct_color + scattered_color;
+}
+
+// Light coming out of a subsurface material after `ray` went into it at `entry`. The light random
+// walks through the material's medium until it gets back to the surface, and leaves diffusely from
+// there, where it is lit like any matte surface.
+fn subsurface_light(
+    scene: &Scene,
+    settings: &RenderSettings,
+    entry: &Hit,
+    ray: &Ray,
+    bounces: i32,
+    medium: Option<&Medium>,
+    wavelengths: Option<&Wavelengths>,
+) -> Vector3<f32> {
+    if bounces >= settings.max_depth {
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+    let Material::Subsurface(subsurface) = entry.material else {
+        return Vector3::new(0.0, 0.0, 0.0);
+    };
+    let Some((exit, weight)) = random_walk(scene, subsurface, entry.primitive.object, ray) else {
+        return Vector3::new(0.0, 0.0, 0.0);
+    };
+
+    // The exit seen from the outside, as if a ray came straight down onto it.
+    let outside = Hit {
+        normal: -exit.normal,
+        geometric_normal: -exit.geometric_normal,
+        is_facing_you: !exit.is_facing_you,
+        ..exit
+    };
+    let ray_in = Ray {
+        origin: outside.point + outside.normal,
+        direction: -outside.normal,
+    };
+    let white = Vector3::new(1.0, 1.0, 1.0);
+    let bsdf = SurfaceBsdf::new(Box::new(Lambertian { albedo: white }), &ray_in, &outside);
+
+    let exit_color = scatter_light(
+        scene,
+        settings,
+        &bsdf,
+        &outside,
+        bounces,
+        medium,
+        wavelengths,
+    );
+    return spectral(weight, wavelengths).mul_element_wise(exit_color);
+}
+
+// Follows light through the inside of a subsurface material, from scattering event to scattering
+// event, until it leaves through the surface of `object`, the one it went in by. Returns where it
+// left (seen from the inside) and how much of it did. None if it didn't in time, the surface wasn't
+// closed, or it ran into another object inside of the material.
+fn random_walk<'a>(
+    scene: &'a Scene,
+    subsurface: &Subsurface,
+    object: usize,
+    ray: &Ray,
+) -> Option<(Hit<'a>, Vector3<f32>)> {
+    let medium = subsurface.medium();
+    let extinction = medium.extinction();
+    let mut rng = rand::thread_rng();
+    let mut ray = Ray {
+        origin: ray.origin,
+        direction: ray.direction.normalize(),
+    };
+
+    // Distances are sampled for one color channel all along the walk, and weighted against the
+    // walk having been sampled for any of them (by the balance heuristic). Weighing every step
+    // on its own instead leaves colored fireflies after a few hundred of them.
+    let channel = rng.gen_range(0..3);
+    let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+    let mut pdfs = Vector3::new(1.0, 1.0, 1.0);
+
+    for _ in 0..MAX_WALK_STEPS {
+        let hit = scene.intersect(&ray, Interval::new(MIN_T, f32::MAX))?;
+        let distance = -(1.0 - rng.gen::<f32>()).ln() / extinction[channel];
+        let scatters = distance < hit.point_at_intersection;
+
+        let travelled = distance.min(hit.point_at_intersection);
+        let transmittance = (-extinction * travelled).map(f32::exp);
+        if scatters {
+            throughput =
+                throughput.mul_element_wise(transmittance.mul_element_wise(medium.scattering));
+            pdfs = pdfs.mul_element_wise(transmittance.mul_element_wise(extinction));
+        } else {
+            throughput = throughput.mul_element_wise(transmittance);
+            pdfs = pdfs.mul_element_wise(transmittance);
+        }
+        // Only the ratios matter, long walks would underflow otherwise.
+        let scale = pdfs[channel];
+        if scale <= 0.0 {
+            return None;
+        }
+        throughput /= scale;
+        pdfs /= scale;
+
+        if scatters {
+            ray = Ray {
+                origin: ray.point_at(distance),
+                direction: medium.sample_phase(ray.direction).normalize(),
+            };
+            continue;
+        }
+        if hit.primitive.object != object {
+            return None;
         }
 
-        return emitted_color;
+        // Some of the light is reflected back in by the surface.
+        let cos = -ray.direction.dot(hit.normal);
+        if rng.gen::<f32>() >= subsurface.internal_reflectance(cos) {
+            let pdf = (pdfs.x + pdfs.y + pdfs.z) / 3.0;
+            return Some((hit, throughput / pdf));
+        }
+        ray = Ray {
+            origin: hit.point,
+            direction: ray.direction + 2.0 * cos * hit.normal,
+        };
     }
+    return None;
+}
 
-    // Stare into the void once again!
-    return Vector3::new(0.0, 0.0, 0.0);
+// RGB colors of the scene are turned into their spectra at the traced wavelengths.
+#[inline]
+fn spectral(color: Vector3<f32>, wavelengths: Option<&Wavelengths>) -> Vector3<f32> {
+    return match wavelengths {
+        Some(wavelengths) => wavelengths.uplift(color),
+        None => color,
+    };
 }
 
-fn cast_ray<'a>(obj: &'a impl Hitable, ray: &'a Ray, closest_t: f32) -> Option<Hit<'a>> {
-    // Cast a single ray and get the closest hit.
-    let curr_obj = obj;
-    let mut hit: Option<Hit> = None;
+// Next event estimation: samples a point on one of the lights and casts a shadow ray towards it.
+fn sample_direct_light(
+    scene: &Scene,
+    bsdf: &SurfaceBsdf,
+    hit: &Hit,
+    medium: Option<&Medium>,
+    wavelengths: Option<&Wavelengths>,
+) -> Vector3<f32> {
+    let black = Vector3::new(0.0, 0.0, 0.0);
+
+    let Some((light, light_sample)) = scene.lights.sample(hit.point) else {
+        return black;
+    };
+
+    // Most surfaces only reflect light from their own side, and a shadow ray going
+    // through the surface would just hit the surface itself. Transmitted light comes
+    // through whatever fills the other side.
+    let medium = if light_sample.direction.dot(hit.geometric_normal) > 0.0 {
+        medium
+    } else if bsdf.flags().transmission {
+        scene.medium_after_crossing(hit, medium)
+    } else {
+        return black;
+    };
+
+    let value = spectral(bsdf.eval(light_sample.direction), wavelengths);
+    if value == black {
+        return black;
+    }
+
+    let Some(light_color) = light_arriving(scene, hit.point, light, &light_sample, medium) else {
+        return black;
+    };
+    let light_color = spectral(light_color, wavelengths);
+
+    let weight = if light_sample.is_delta {
+        1.0
+    } else {
+        power_heuristic(light_sample.pdf, bsdf.pdf(light_sample.direction))
+    };
+
+    return light_color.mul_element_wise(value) * (weight / light_sample.pdf);
+}
+
+// Next event estimation from a point where the ray scattered inside of a medium.
+fn sample_direct_light_in_medium(
+    scene: &Scene,
+    medium: &Medium,
+    point: Vector3<f32>,
+    direction: Vector3<f32>,
+    wavelengths: Option<&Wavelengths>,
+) -> Vector3<f32> {
+    let black = Vector3::new(0.0, 0.0, 0.0);
+
+    let Some((light, light_sample)) = scene.lights.sample(point) else {
+        return black;
+    };
+    let Some(light_color) = light_arriving(scene, point, light, &light_sample, Some(medium)) else {
+        return black;
+    };
+    let light_color = spectral(light_color, wavelengths);
+
+    let phase = medium.phase(direction, light_sample.direction);
+    let weight = if light_sample.is_delta {
+        1.0
+    } else {
+        power_heuristic(light_sample.pdf, phase)
+    };
+
+    return light_color * (phase * weight / light_sample.pdf);
+}
+
+// Light emitted by the sampled point of the light towards `origin`, attenuated by the media in
+// between. None if anything else is in the way, boundaries of media are looked through.
+fn light_arriving(
+    scene: &Scene,
+    origin: Vector3<f32>,
+    light: &Light,
+    light_sample: &LightSample,
+    medium: Option<&Medium>,
+) -> Option<Vector3<f32>> {
+    let mut shadow_ray = Ray {
+        origin,
+        direction: light_sample.direction,
+    };
+    let mut medium = medium;
+    let mut transmittance = Vector3::new(1.0, 1.0, 1.0);
+    let mut remaining = light_sample.distance * 1.001 + MIN_T;
+
+    loop {
+        // Whatever the shadow ray hits first has to be the light itself, otherwise it is occluded.
+        let Some(hit) = scene.intersect(&shadow_ray, Interval::new(MIN_T, remaining)) else {
+            // Nothing is in the way of lights which aren't geometry, only whatever fills the space.
+            let emitted = light.emitted(-light_sample.direction, light_sample.distance)?;
+            if let Some(medium) = medium {
+                transmittance = transmittance.mul_element_wise(medium.transmittance(
+                    shadow_ray.origin,
+                    shadow_ray.direction,
+                    remaining,
+                ));
+            }
+            return Some(emitted.mul_element_wise(transmittance));
+        };
+        if let Some(medium) = medium {
+            transmittance = transmittance.mul_element_wise(medium.transmittance(
+                shadow_ray.origin,
+                shadow_ray.direction,
+                hit.point_at_intersection,
+            ));
+        }
+
+        if Some(hit.primitive) == light.primitive() {
+            return Some(
+                hit.material
+                    .emit(&shadow_ray, &hit)
+                    .mul_element_wise(transmittance),
+            );
+        }
+        if !matches!(hit.material, Material::Interface) {
+            return None;
+        }
 
-    let temp_closest_hit = curr_obj.intersect(ray, Interval::new(MIN_T, closest_t));
-    if let Some(closest_hit) = temp_closest_hit {
-        hit = Some(closest_hit);
+        medium = scene.medium_after_crossing(&hit, medium);
+        remaining -= hit.point_at_intersection;
+        shadow_ray.origin = hit.point;
     }
+}
+
+// Multiple importance sampling weight for emission found by following the BSDF.
+fn emission_weight(scene: &Scene, hit: &Hit, previous: Option<&Bounce>) -> f32 {
+    return match previous {
+        // Camera rays and specular bounces can't be sampled by shadow rays.
+        None => 1.0,
+        Some(bounce) if bounce.is_delta => 1.0,
+        Some(bounce) => power_heuristic(bounce.pdf, scene.lights.pdf(bounce.origin, hit)),
+    };
+}
 
-    return hit;
+#[inline]
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let pdf_2 = pdf * pdf;
+    let other_pdf_2 = other_pdf * other_pdf;
+    if pdf_2 + other_pdf_2 == 0.0 {
+        return 0.0;
+    }
+    return pdf_2 / (pdf_2 + other_pdf_2);
 }
 
-pub fn render_pass(scene: &Scene) -> Screen {
-    let mut new_screen = vec![vec![Vector3::new(0.0, 0.0, 0.0); WIDTH]; HEIGHT];
+pub fn render_pass(scene: &Scene, settings: &RenderSettings) -> Screen {
+    let mut new_screen = vec![vec![Vector3::new(0.0, 0.0, 0.0); settings.width]; settings.height];
 
-    for y in 0..HEIGHT {
+    for (y, row) in new_screen.iter_mut().enumerate() {
         // Create an array of all x coordinates for a specific row
         // Execute a single_pixel_pass on all of them and collect()
         // collect() preserves order so we can write it directly onto the screen
-        new_screen[y] = (0..WIDTH)
+        *row = (0..settings.width)
             .collect::<Vec<_>>()
             .par_iter()
-            .map(|x| single_pixel_pass(*x, y, scene))
+            .map(|x| single_pixel_pass(*x, y, scene, settings))
             .collect();
     }
     return new_screen;
 }
 
-fn single_pixel_pass(x: usize, y: usize, scene: &Scene) -> Vector3<f32> {
+fn single_pixel_pass(x: usize, y: usize, scene: &Scene, settings: &RenderSettings) -> Vector3<f32> {
     let mut color: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0);
-    for _ in 0..SAMPLES_PER_PIXEL {
-        let ray = scene.shoot_ray(x as f32 / WIDTH as f32, y as f32 / HEIGHT as f32);
-        color += ray_trace(scene, &ray);
+    for _ in 0..settings.samples_per_pass {
+        let Some(ray) = scene.shoot_ray(
+            x as f32 / settings.width as f32,
+            y as f32 / settings.height as f32,
+        ) else {
+            continue;
+        };
+        // The heatmap of bounding boxes is no spectrum.
+        if settings.spectral && !settings.debug_aabb {
+            let wavelengths = Wavelengths::sample();
+            color += wavelengths.to_rgb(ray_trace(scene, settings, &ray, Some(&wavelengths)));
+        } else {
+            color += ray_trace(scene, settings, &ray, None);
+        }
     }
 
-    return color / SAMPLES_PER_PIXEL as f32;
+    return color / settings.samples_per_pass as f32;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::sphere::Sphere;
+    use crate::scene::camera::Camera;
+
+    #[test]
+    fn power_heuristic_weights_sum_to_one() {
+        let pdfs = [0.0, 1e-6, 0.1, 0.5, 1.0, 3.0, 250.0, 1e6];
+        for a in pdfs {
+            for b in pdfs {
+                let sum = power_heuristic(a, b) + power_heuristic(b, a);
+                if a == 0.0 && b == 0.0 {
+                    assert_eq!(sum, 0.0);
+                } else {
+                    assert!((sum - 1.0).abs() < 1e-6, "{} and {} sum to {}", a, b, sum);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_walks_only_leave_through_the_object_they_entered() {
+        // A marble with a stone inside of it.
+        let marble = Subsurface::new(
+            Vector3::new(0.9, 0.9, 0.9),
+            Vector3::new(0.3, 0.3, 0.3),
+            1.4,
+        );
+        let spheres = vec![
+            Sphere::new(
+                Vector3::new(0.0, 0.0, 0.0),
+                1.0,
+                Material::Subsurface(marble.clone()),
+            ),
+            Sphere::new(
+                Vector3::new(0.0, 0.0, 0.0),
+                0.5,
+                Material::Diffuse(Vector3::new(0.5, 0.5, 0.5)),
+            ),
+        ];
+        let camera = Camera::new(
+            &RenderSettings::default(),
+            60.0,
+            Vector3::new(0.0, 0.0, 5.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+        let scene = Scene::build_complex_scene(Vec::new(), spheres, camera);
+        let ray = Ray {
+            origin: Vector3::new(0.0, 0.0, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+
+        let (mut exits, mut lost) = (0, 0);
+        for _ in 0..2000 {
+            match random_walk(&scene, &marble, 0, &ray) {
+                Some((exit, _)) => {
+                    assert_eq!(exit.primitive.object, 0);
+                    assert!((exit.point.magnitude() - 1.0).abs() < 1e-3);
+                    exits += 1;
+                }
+                None => lost += 1,
+            }
+        }
+        assert!(exits > 100 && lost > 100, "{} exits, {} lost", exits, lost);
+    }
 }
@@ -0,0 +1,146 @@
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::Vector2;
+use rand::Rng;
+
+/**
+ * The shape of the lens opening, which is also the shape out of focus highlights (bokeh) take.
+ */
+#[derive(Clone, Debug, Default)]
+pub enum ApertureShape {
+    #[default]
+    Circle,
+    // A regular polygon, as formed by the blades of a real diaphragm.
+    Polygon {
+        blades: u32,
+        rotation: f32, // degrees
+    },
+    // Any shape, painted in a grayscale image.
+    Mask(Arc<ApertureMask>),
+}
+
+impl ApertureShape {
+    // Uniformly samples a point of the aperture, within [-1, 1] on both axes.
+    pub fn sample(&self) -> Vector2<f32> {
+        let mut rng = rand::thread_rng();
+        match self {
+            ApertureShape::Circle => {
+                let radius = rng.gen::<f32>().sqrt();
+                let angle = 2.0 * PI * rng.gen::<f32>();
+                return Vector2::new(radius * angle.cos(), radius * angle.sin());
+            }
+            ApertureShape::Polygon { blades, rotation } => {
+                // The polygon is a fan of equally large triangles around the center, pick one
+                // of them and a point inside of it.
+                let blade = rng.gen_range(0..*blades) as f32;
+                let step = 2.0 * PI / *blades as f32;
+                let start = rotation.to_radians() + blade * step;
+                let a = Vector2::new(start.cos(), start.sin());
+                let b = Vector2::new((start + step).cos(), (start + step).sin());
+
+                let (mut s, mut t) = (rng.gen::<f32>(), rng.gen::<f32>());
+                if s + t > 1.0 {
+                    s = 1.0 - s;
+                    t = 1.0 - t;
+                }
+                return a * s + b * t;
+            }
+            ApertureShape::Mask(mask) => return mask.sample(),
+        }
+    }
+}
+
+/**
+ * A grayscale image of the aperture, brighter pixels let more light through.
+ * Points are sampled proportionally to the brightness of the pixels.
+ */
+#[derive(Debug)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    // Running sum of the pixel weights, row by row from the top, normalized to end at 1.
+    cdf: Vec<f32>,
+}
+
+impl ApertureMask {
+    pub fn load(path: &Path) -> Result<ApertureMask, String> {
+        let image = image::open(path)
+            .map_err(|e| format!("There was an error loading '{}': {}", path.display(), e))?
+            .into_rgb32f();
+
+        let mut cdf = Vec::with_capacity(image.pixels().len());
+        let mut total = 0.0;
+        for pixel in image.pixels() {
+            total += (pixel[0] + pixel[1] + pixel[2]).max(0.0) / 3.0;
+            cdf.push(total);
+        }
+        if total <= 0.0 {
+            return Err(format!(
+                "The aperture mask '{}' is completely black",
+                path.display()
+            ));
+        }
+        for value in cdf.iter_mut() {
+            *value /= total;
+        }
+
+        return Ok(ApertureMask {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            cdf,
+        });
+    }
+
+    fn sample(&self) -> Vector2<f32> {
+        let mut rng = rand::thread_rng();
+        let choice = rng.gen::<f32>();
+        let pixel = self
+            .cdf
+            .partition_point(|value| *value < choice)
+            .min(self.cdf.len() - 1);
+
+        // A random point inside the picked pixel, the image covers [-1, 1] on its longest side.
+        let x = (pixel % self.width) as f32 + rng.gen::<f32>();
+        let y = (pixel / self.width) as f32 + rng.gen::<f32>();
+        let size = self.width.max(self.height) as f32;
+        return Vector2::new(
+            (2.0 * x - self.width as f32) / size,
+            (self.height as f32 - 2.0 * y) / size,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_stay_within_the_unit_square() {
+        let mask = ApertureMask {
+            width: 4,
+            height: 2,
+            cdf: vec![0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 1.0],
+        };
+        let shapes = [
+            ApertureShape::Circle,
+            ApertureShape::Polygon {
+                blades: 5,
+                rotation: 18.0,
+            },
+            ApertureShape::Mask(Arc::new(mask)),
+        ];
+        for shape in shapes {
+            for _ in 0..10000 {
+                let point = shape.sample();
+                assert!(
+                    point.x.abs() <= 1.0 && point.y.abs() <= 1.0,
+                    "{:?} sampled {:?}",
+                    shape,
+                    point
+                );
+            }
+        }
+    }
+}
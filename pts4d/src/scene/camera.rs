@@ -3,8 +3,43 @@ use std::f32::consts::PI;
 use cgmath::{InnerSpace, Vector3};
 use rand::Rng;
 
+use crate::scene::aperture::ApertureShape;
+use crate::scene::settings::RenderSettings;
 use crate::utils::vector_utils::Ray;
 
+/**
+ * How the directions around the camera are laid out on the image.
+ */
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Projection {
+    // A pinhole camera, `fov` is the vertical field of view.
+    #[default]
+    Perspective,
+    // Parallel rays, `height` is the size of the view in world units.
+    Orthographic { height: f32 },
+    // Equidistant fisheye, the angle from the view direction grows linearly with the distance
+    // from the center. `fov` spans the circle inscribed in the image height.
+    Fisheye,
+    // A full 360° x 180° panorama, longitude goes along x and latitude along y.
+    Equirectangular,
+}
+
+impl Projection {
+    pub fn from_name(name: &str) -> Result<Projection, String> {
+        return match name {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => Ok(Projection::Orthographic { height: 10.0 }),
+            "fisheye" => Ok(Projection::Fisheye),
+            "equirectangular" => Ok(Projection::Equirectangular),
+            _ => Err(format!(
+                "Unknown projection '{}', expected perspective, orthographic, fisheye or equirectangular",
+                name
+            )),
+        };
+    }
+}
+
+#[derive(Clone)]
 pub struct CameraConfig {
     pub image_width: f32,
     pub image_height: f32,
@@ -12,6 +47,10 @@ pub struct CameraConfig {
     pub look_at: Vector3<f32>,   // Where is the camera looking at?
     pub up: Vector3<f32>,        // which way is up
     pub fov: f32,                // Vertical FoV
+    pub projection: Projection,
+    pub aperture: f32,           // Radius of the lens, 0 for a pinhole
+    pub focus_distance: f32,     // Distance of the plane in focus
+    pub aperture_shape: ApertureShape,
 }
 
 pub struct Camera {
@@ -20,72 +59,270 @@ pub struct Camera {
 
     // Private
     origin: Vector3<f32>,
+    // Perspective: the corner of the viewport on the focus plane, orthographic: on the camera plane.
     first_pixel_location: Vector3<f32>,
     horizontal: Vector3<f32>,
     vertical: Vector3<f32>,
-    pixel_delta_u: Vector3<f32>,
-    pixel_delta_v: Vector3<f32>,
+    // Camera axes: right and down along the image, and the direction the camera looks towards.
+    right: Vector3<f32>,
+    down: Vector3<f32>,
+    forward: Vector3<f32>,
 }
 
 impl Camera {
     pub fn new(
-        image_height: f32,
-        image_width: f32,
+        settings: &RenderSettings,
         fov: f32,
         look_from: Vector3<f32>,
         look_at: Vector3<f32>,
         up: Vector3<f32>,
     ) -> Camera {
+        return Camera::from_config(CameraConfig {
+            image_width: settings.width as f32,
+            image_height: settings.height as f32,
+            look_from,
+            look_at,
+            up,
+            fov,
+            projection: Projection::default(),
+            aperture: 0.0,
+            focus_distance: (look_at - look_from).magnitude(),
+            aperture_shape: ApertureShape::default(),
+        });
+    }
+
+    pub fn from_config(camera_config: CameraConfig) -> Camera {
+        let CameraConfig {
+            image_width,
+            image_height,
+            fov,
+            look_from,
+            look_at,
+            up,
+            projection,
+            focus_distance,
+            ..
+        } = camera_config;
+
         let theta = fov * PI / 180.0;
         let aspect_ratio = image_width / image_height;
 
-        let viewport_height = 2.0 * (theta / 2.0).tan();
-        let viewport_width = aspect_ratio * viewport_height;
-
         let w = (look_from - look_at).normalize();
         let u = up.cross(w).normalize();
         let v = w.cross(u);
         let origin: Vector3<f32> = look_from;
-  
-        let pixel_delta_u = (viewport_width * u) / image_width;
-        let pixel_delta_v = (viewport_height * -v) / image_height;
 
-        let first_pixel_location =
-            origin - (viewport_width / 2.0) * u - (viewport_height / 2.0) * v - w;
-        
+        // The fisheye and the panorama compute their directions from angles instead.
+        let (viewport_height, viewport_distance) = match projection {
+            Projection::Orthographic { height } => (height, 0.0),
+            // The viewport lies on the focus plane, everything on it is sharp.
+            _ => (2.0 * (theta / 2.0).tan() * focus_distance, focus_distance),
+        };
+        let viewport_width = aspect_ratio * viewport_height;
+
+        let first_pixel_location = origin
+            - (viewport_width / 2.0) * u
+            - (viewport_height / 2.0) * v
+            - viewport_distance * w;
+
         return Camera {
-            camera_config: CameraConfig {
-                image_height,
-                image_width,
-                fov,
-                look_from,
-                look_at,
-                up,
-            },
+            camera_config,
             origin,
             first_pixel_location,
             horizontal: viewport_width * u,
             vertical: viewport_height * v,
-            pixel_delta_u,
-            pixel_delta_v,
+            right: u,
+            down: v,
+            forward: -w,
         };
     }
 
-    pub fn shoot_ray(&self, x: f32, y: f32) -> Ray {
-        let pixel_center = self.first_pixel_location + (x * self.horizontal) + (y * self.vertical);
-        let sample_pixel = pixel_center + self.sample_from_pixel_square();
+    // Shoots a ray through a random point of the pixel at (x, y), both in [0, 1].
+    // Pixels outside of the fisheye circle don't see anything.
+    pub fn shoot_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        let (jitter_x, jitter_y) = self.sample_from_pixel_square();
+        let ray = self.center_ray(x + jitter_x, y + jitter_y)?;
 
-        return Ray {
-            origin: self.origin,
-            direction: sample_pixel - self.origin,
-        };
+        let aperture = self.camera_config.aperture;
+        let has_lens = matches!(
+            self.camera_config.projection,
+            Projection::Perspective | Projection::Orthographic { .. }
+        );
+        if aperture <= 0.0 || !has_lens {
+            return Some(ray);
+        }
+
+        // Thin lens: rays start anywhere on the lens and converge on the focus plane.
+        let focus_point = ray.origin
+            + ray.direction * (self.camera_config.focus_distance / ray.direction.dot(self.forward));
+        let lens_point = self.camera_config.aperture_shape.sample();
+        let origin =
+            ray.origin + aperture * (lens_point.x * self.right + lens_point.y * self.down);
+
+        return Some(Ray {
+            origin,
+            direction: focus_point - origin,
+        });
     }
 
-    pub fn sample_from_pixel_square(&self) -> Vector3<f32> {
+    // Ray through the very center of the lens, as a pinhole camera would shoot it.
+    pub fn center_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        let viewport_point =
+            self.first_pixel_location + (x * self.horizontal) + (y * self.vertical);
+
+        match self.camera_config.projection {
+            Projection::Perspective => {
+                return Some(Ray {
+                    origin: self.origin,
+                    direction: viewport_point - self.origin,
+                });
+            }
+            Projection::Orthographic { .. } => {
+                return Some(Ray {
+                    origin: viewport_point,
+                    direction: self.forward,
+                });
+            }
+            Projection::Fisheye => {
+                let aspect_ratio = self.camera_config.image_width / self.camera_config.image_height;
+                let dx = (2.0 * x - 1.0) * aspect_ratio;
+                let dy = 2.0 * y - 1.0;
+                let radius = (dx * dx + dy * dy).sqrt();
+                if radius > 1.0 {
+                    return None;
+                }
+
+                let angle = radius * (self.camera_config.fov * PI / 180.0) / 2.0;
+                let sideways = if radius > 0.0 {
+                    (dx * self.right + dy * self.down) / radius
+                } else {
+                    Vector3::new(0.0, 0.0, 0.0)
+                };
+                return Some(Ray {
+                    origin: self.origin,
+                    direction: angle.sin() * sideways + angle.cos() * self.forward,
+                });
+            }
+            Projection::Equirectangular => {
+                let longitude = (x - 0.5) * 2.0 * PI;
+                let latitude = (0.5 - y) * PI;
+                let horizontal = longitude.sin() * self.right + longitude.cos() * self.forward;
+                return Some(Ray {
+                    origin: self.origin,
+                    direction: latitude.cos() * horizontal - latitude.sin() * self.down,
+                });
+            }
+        }
+    }
+
+    // Distance of a point from the camera, along the viewing direction.
+    pub fn depth_of(&self, point: Vector3<f32>) -> f32 {
+        return (point - self.origin).dot(self.forward);
+    }
+
+    // Random offset within a pixel, in the same [0, 1] units as the image coordinates.
+    pub fn sample_from_pixel_square(&self) -> (f32, f32) {
         let mut rng = rand::thread_rng();
         let px = -0.5 + rng.gen::<f32>();
         let py: f32 = -0.5 + rng.gen::<f32>();
 
-        return (px * self.pixel_delta_u) + (py * self.pixel_delta_v);
+        return (
+            px / self.camera_config.image_width,
+            py / self.camera_config.image_height,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(projection: Projection, aperture: f32, aperture_shape: ApertureShape) -> Camera {
+        return Camera::from_config(CameraConfig {
+            image_width: 800.0,
+            image_height: 400.0,
+            look_from: Vector3::new(0.0, 0.0, 0.0),
+            look_at: Vector3::new(0.0, 0.0, -1.0),
+            up: Vector3::new(0.0, 1.0, 0.0),
+            fov: 90.0,
+            projection,
+            aperture,
+            focus_distance: 10.0,
+            aperture_shape,
+        });
+    }
+
+    #[test]
+    fn lens_rays_converge_on_the_focus_plane() {
+        let shapes = [
+            ApertureShape::Circle,
+            ApertureShape::Polygon {
+                blades: 6,
+                rotation: 0.0,
+            },
+        ];
+        for shape in shapes {
+            let camera = camera(Projection::Perspective, 0.5, shape);
+            // Perspective rays go from the eye to the viewport on the focus plane.
+            let focus_ray = camera.center_ray(0.3, 0.8).unwrap();
+            let focus_point = focus_ray.origin + focus_ray.direction;
+
+            for _ in 0..1000 {
+                let ray = camera.shoot_ray(0.3, 0.8).unwrap();
+                let hit = ray.origin + ray.direction * (10.0 / ray.direction.dot(camera.forward));
+                // Rays leave from all over the lens, but only miss the point by the pixel jitter,
+                // a pixel being 20 / 400 world units wide there.
+                assert!((hit - focus_point).magnitude() < 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn fisheye_only_sees_inside_its_circle() {
+        // The image is twice as wide as it is high, the circle spans its height.
+        let camera = camera(Projection::Fisheye, 0.0, ApertureShape::Circle);
+        let center = camera.center_ray(0.5, 0.5).unwrap();
+        assert!((center.direction - camera.forward).magnitude() < 1e-5);
+        assert!(camera.center_ray(0.5, 0.01).is_some());
+        assert!(camera.center_ray(0.74, 0.5).is_some());
+        assert!(camera.center_ray(0.76, 0.5).is_none());
+        assert!(camera.center_ray(0.05, 0.05).is_none());
+
+        // The edge of the circle is fov / 2 away from the view direction.
+        let edge = camera.center_ray(0.5, 0.0).unwrap();
+        let angle = edge.direction.normalize().dot(camera.forward).acos();
+        assert!((angle - PI / 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn equirectangular_covers_the_whole_sphere() {
+        let camera = camera(Projection::Equirectangular, 0.0, ApertureShape::Circle);
+        let up = -camera.down;
+        let direction = |x: f32, y: f32| camera.center_ray(x, y).unwrap().direction.normalize();
+
+        for x in [0.0, 0.3, 0.9] {
+            assert!((direction(x, 0.0) - up).magnitude() < 1e-5);
+            assert!((direction(x, 1.0) + up).magnitude() < 1e-5);
+        }
+        assert!((direction(0.5, 0.5) - camera.forward).magnitude() < 1e-5);
+        assert!((direction(0.75, 0.5) - camera.right).magnitude() < 1e-5);
+        assert!((direction(0.0, 0.5) + camera.forward).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let camera = camera(
+            Projection::Orthographic { height: 4.0 },
+            0.0,
+            ApertureShape::Circle,
+        );
+        let top_left = camera.center_ray(0.0, 0.0).unwrap();
+        let bottom_right = camera.center_ray(1.0, 1.0).unwrap();
+        assert_eq!(top_left.direction, camera.forward);
+        assert_eq!(bottom_right.direction, camera.forward);
+
+        // The view is 4 units high and, like the image, twice as wide.
+        let diagonal = bottom_right.origin - top_left.origin;
+        assert!((diagonal - 8.0 * camera.right - 4.0 * camera.down).magnitude() < 1e-5);
     }
 }
@@ -1,24 +1,233 @@
-use crate::object::mesh::Mesh;
+use crate::accel::aabb::AABB;
+use crate::accel::bvh::BVH;
+use crate::lights::light::{Light, LightList};
+use crate::materials::medium::Medium;
+use crate::object::area_light::AreaLight;
+use crate::object::instance::MeshInstance;
+use crate::object::object::{Hit, Hitable, PrimitiveId};
 use crate::object::sphere::Sphere;
+use crate::object::triangle::TriangleIntersector;
+use crate::object::volume::Volume;
 use crate::scene::camera::Camera;
-use crate::utils::vector_utils::Ray;
+use crate::utils::vector_utils::{Interval, Ray};
+
+// Everything the top-level BVH is built over.
+pub enum ScenePrimitive {
+    Sphere(usize),    // sphere index
+    Instance(usize),  // mesh instance index
+    Volume(usize),    // volume index
+    AreaLight(usize), // area light index
+}
 
 pub struct Scene {
-    pub meshes: Vec<Mesh>,
+    pub instances: Vec<MeshInstance>,
     pub spheres: Vec<Sphere>,
+    pub volumes: Vec<Volume>,
+    pub area_lights: Vec<AreaLight>,
     pub camera: Camera,
+    // How triangles of all the meshes in the scene are intersected.
+    pub triangle_intersector: TriangleIntersector,
+    // Emitters, for sampling them directly.
+    pub lights: LightList,
+    // Medium filling all the space outside of objects, e.g. fog.
+    pub fog: Option<Medium>,
+
+    primitives: Vec<ScenePrimitive>,
+    // Top-level acceleration structure, every mesh carries its own bottom-level one.
+    bvh: BVH,
 }
 
 impl Scene {
-    pub fn build_complex_scene(meshes: Vec<Mesh>, spheres: Vec<Sphere>, camera: Camera) -> Scene {
+    pub fn build_complex_scene(
+        instances: Vec<MeshInstance>,
+        spheres: Vec<Sphere>,
+        camera: Camera,
+    ) -> Scene {
+        return Scene::build_scene(instances, spheres, Vec::new(), Vec::new(), camera);
+    }
+
+    pub fn build_scene(
+        instances: Vec<MeshInstance>,
+        spheres: Vec<Sphere>,
+        volumes: Vec<Volume>,
+        area_lights: Vec<AreaLight>,
+        camera: Camera,
+    ) -> Scene {
+        let mut primitives: Vec<ScenePrimitive> = Vec::new();
+        let mut bounding_boxes: Vec<AABB> = Vec::new();
+
+        for (sphere_index, sphere) in spheres.iter().enumerate() {
+            primitives.push(ScenePrimitive::Sphere(sphere_index));
+            bounding_boxes.push(*sphere.bounding_box());
+        }
+
+        for (instance_index, instance) in instances.iter().enumerate() {
+            primitives.push(ScenePrimitive::Instance(instance_index));
+            bounding_boxes.push(*instance.bounding_box());
+        }
+
+        for (volume_index, volume) in volumes.iter().enumerate() {
+            primitives.push(ScenePrimitive::Volume(volume_index));
+            bounding_boxes.push(*volume.bounding_box());
+        }
+
+        for (area_light_index, area_light) in area_lights.iter().enumerate() {
+            primitives.push(ScenePrimitive::AreaLight(area_light_index));
+            bounding_boxes.push(*area_light.bounding_box());
+        }
+
+        let bvh = BVH::build(&bounding_boxes);
+        let lights = collect_lights(&primitives, &instances, &spheres, &area_lights);
+
         return Scene {
-            meshes,
+            instances,
             spheres,
+            volumes,
+            area_lights,
             camera,
+            triangle_intersector: TriangleIntersector::default(),
+            lights,
+            fog: None,
+            primitives,
+            bvh,
         };
     }
 
-    pub fn shoot_ray(&self, x: f32, y: f32) -> Ray {
+    pub fn shoot_ray(&self, x: f32, y: f32) -> Option<Ray> {
         return self.camera.shoot_ray(x, y);
     }
+
+    // Finds the closest hit along the ray within the given bounds.
+    pub fn intersect(&self, ray: &Ray, bounds: Interval) -> Option<Hit<'_>> {
+        return self
+            .bvh
+            .intersect(ray, bounds, |primitive_index, primitive_bounds| {
+                let hit = match self.primitives[primitive_index] {
+                    ScenePrimitive::Sphere(sphere_index) => {
+                        self.spheres[sphere_index].intersect(ray, primitive_bounds)
+                    }
+                    ScenePrimitive::Instance(instance_index) => self.instances[instance_index]
+                        .intersect_with(ray, primitive_bounds, self.triangle_intersector),
+                    ScenePrimitive::Volume(volume_index) => {
+                        self.volumes[volume_index].intersect(ray, primitive_bounds)
+                    }
+                    ScenePrimitive::AreaLight(area_light_index) => {
+                        self.area_lights[area_light_index].intersect(ray, primitive_bounds)
+                    }
+                };
+
+                // Objects only know which of their own triangles was hit, tag which object it was.
+                return hit.map(|mut hit| {
+                    hit.primitive.object = primitive_index;
+                    hit
+                });
+            });
+    }
+
+    // Medium inside of the object the primitive belongs to.
+    pub fn interior_of(&self, primitive: PrimitiveId) -> Option<&Medium> {
+        return match self.primitives[primitive.object] {
+            ScenePrimitive::Sphere(sphere_index) => self.spheres[sphere_index].interior.as_ref(),
+            ScenePrimitive::Instance(instance_index) => {
+                self.instances[instance_index].interior.as_ref()
+            }
+            ScenePrimitive::Volume(volume_index) => Some(&self.volumes[volume_index].medium),
+            ScenePrimitive::AreaLight(_) => None,
+        };
+    }
+
+    // Whether camera rays stop at the primitive, or go on as if it wasn't there.
+    pub fn is_visible_to_camera(&self, primitive: PrimitiveId) -> bool {
+        return match self.primitives[primitive.object] {
+            ScenePrimitive::AreaLight(area_light_index) => {
+                self.area_lights[area_light_index].visible
+            }
+            _ => true,
+        };
+    }
+
+    // Medium a ray ends up in after going through the surface at `hit`.
+    // Objects without an interior medium are thin, crossing them doesn't change the medium.
+    pub fn medium_after_crossing<'a>(
+        &'a self,
+        hit: &Hit,
+        current: Option<&'a Medium>,
+    ) -> Option<&'a Medium> {
+        if self.interior_of(hit.primitive).is_none() {
+            return current;
+        }
+        if hit.is_facing_you {
+            return self.interior_of(hit.primitive);
+        }
+        return self.fog.as_ref();
+    }
+
+    pub fn count_bvh_node_hits(&self, ray: &Ray, bounds: Interval) -> usize {
+        return self.bvh.count_node_hits(ray, bounds);
+    }
+}
+
+// Every emissive sphere and triangle in the scene becomes a light, as does every area light.
+fn collect_lights(
+    primitives: &[ScenePrimitive],
+    instances: &[MeshInstance],
+    spheres: &[Sphere],
+    area_lights: &[AreaLight],
+) -> LightList {
+    let mut lights: Vec<Light> = Vec::new();
+
+    for (primitive_index, primitive) in primitives.iter().enumerate() {
+        match primitive {
+            ScenePrimitive::Sphere(sphere_index) => {
+                let sphere = &spheres[*sphere_index];
+                if sphere.material.is_emissive() {
+                    lights.push(Light::Sphere {
+                        center: sphere.center,
+                        radius: sphere.radius,
+                        primitive: PrimitiveId {
+                            object: primitive_index,
+                            triangle: 0,
+                        },
+                    });
+                }
+            }
+            ScenePrimitive::Instance(instance_index) => {
+                let instance = &instances[*instance_index];
+                for triangle_index in 0..instance.mesh.triangle_count() {
+                    if !instance
+                        .mesh
+                        .triangle_material(triangle_index)
+                        .is_emissive()
+                    {
+                        continue;
+                    }
+
+                    let vertices = instance
+                        .mesh
+                        .triangle_vertices(triangle_index)
+                        .map(|vertex| instance.transform.point_to_world(vertex));
+                    lights.push(Light::Triangle {
+                        vertices,
+                        primitive: PrimitiveId {
+                            object: primitive_index,
+                            triangle: triangle_index,
+                        },
+                    });
+                }
+            }
+            // Media don't glow.
+            ScenePrimitive::Volume(_) => {}
+            ScenePrimitive::AreaLight(area_light_index) => {
+                lights.push(Light::Area {
+                    shape: area_lights[*area_light_index].shape,
+                    primitive: PrimitiveId {
+                        object: primitive_index,
+                        triangle: 0,
+                    },
+                });
+            }
+        }
+    }
+
+    return LightList::new(lights);
 }
@@ -0,0 +1,1301 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::{FromStr, SplitWhitespace};
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::accel::aabb::AABB;
+use crate::lights::environment::{Background, Environment, EnvironmentImage};
+use crate::lights::light::Light;
+use crate::lights::sky::Sky;
+use crate::materials::ior::Ior;
+use crate::materials::material::Material;
+use crate::materials::medium::{DensityField, Medium};
+use crate::materials::microfacet::{metal_preset, Conductor, RoughDielectric};
+use crate::materials::normal_map::NormalMap;
+use crate::materials::principled::Principled;
+use crate::materials::procedural::{Pattern, PatternKind, PatternSpace};
+use crate::materials::subsurface::Subsurface;
+use crate::materials::texture::{ImageTexture, Texture, WrapMode};
+use crate::materials::voxel_grid::VoxelGrid;
+use crate::object::area_light::{AreaLight, AreaLightShape};
+use crate::object::instance::{MeshInstance, Transform};
+use crate::object::mesh::Mesh;
+use crate::object::sphere::Sphere;
+use crate::object::triangle::TriangleIntersector;
+use crate::object::volume::Volume;
+use crate::scene::aperture::{ApertureMask, ApertureShape};
+use crate::scene::camera::{Camera, CameraConfig, Projection};
+use crate::scene::scene::Scene;
+use crate::scene::settings::RenderSettings;
+use crate::utils::scene_builders::load_model;
+
+// Scene files are plain text, one statement per line, `#` starts a comment. Everything after the
+// statement name is `key value...` pairs, in any order, see the `parse_*` functions for the keys.
+// Textures, materials and media have to be declared before they are used. Paths are relative to
+// the scene file.
+
+/**
+ * A parsed scene file. Meshes are only loaded from disk once the final
+ * render settings (which the camera depends on) are known, in `build`.
+ */
+pub struct SceneDescription {
+    // Render settings of the scene file, on top of the defaults.
+    pub settings: RenderSettings,
+
+    file_name: String,
+    camera: CameraDescription,
+    triangle_intersector: TriangleIntersector,
+    fog: Option<Medium>,
+    environment: Option<Environment>,
+    spheres: Vec<Sphere>,
+    volumes: Vec<Volume>,
+    area_lights: Vec<AreaLight>,
+    // Lights which aren't geometry, added to the scene's lights once it's built.
+    lights: Vec<Light>,
+    meshes: Vec<MeshDescription>,
+}
+
+struct CameraDescription {
+    look_from: Vector3<f32>,
+    look_at: Vector3<f32>,
+    up: Vector3<f32>,
+    fov: f32,
+    projection: Projection,
+    aperture: f32,
+    focus_distance: Option<f32>,
+    aperture_shape: ApertureShape,
+}
+
+struct MeshDescription {
+    path: PathBuf,
+    // Overrides the materials of the mtl library, by name.
+    material: Option<(String, Material)>,
+    // Smoothing threshold for generating normals, in degrees.
+    crease_angle: Option<f32>,
+    transform: Transform,
+    interior: Option<Medium>,
+    // Where the mesh was declared, for reporting loading errors.
+    line: usize,
+}
+
+pub fn is_scene_file(path: &str) -> bool {
+    return path.ends_with(".scene");
+}
+
+pub fn load_scene_file(path: &str) -> Result<SceneDescription, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("There was an error opening and reading '{}': {}", path, e))?;
+    let base_directory = Path::new(path).parent().unwrap_or(Path::new(""));
+
+    return parse_scene(&source, path, base_directory);
+}
+
+// Parses the contents of a scene file, errors are reported as `file:line: message`.
+pub fn parse_scene(
+    source: &str,
+    file_name: &str,
+    base_directory: &Path,
+) -> Result<SceneDescription, String> {
+    let mut parser = SceneParser {
+        base_directory,
+        settings: RenderSettings::default(),
+        camera: None,
+        triangle_intersector: TriangleIntersector::default(),
+        fog: None,
+        environment: None,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        media: HashMap::new(),
+        spheres: Vec::new(),
+        volumes: Vec::new(),
+        area_lights: Vec::new(),
+        lights: Vec::new(),
+        meshes: Vec::new(),
+    };
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let statement = line.split('#').next().unwrap_or("");
+        parser
+            .parse_statement(statement, line_number)
+            .map_err(|e| format!("{}:{}: {}", file_name, line_number, e))?;
+    }
+
+    let camera = parser
+        .camera
+        .ok_or(format!("{}: The scene has no camera", file_name))?;
+
+    return Ok(SceneDescription {
+        settings: parser.settings,
+        file_name: file_name.to_string(),
+        camera,
+        triangle_intersector: parser.triangle_intersector,
+        fog: parser.fog,
+        environment: parser.environment,
+        spheres: parser.spheres,
+        volumes: parser.volumes,
+        area_lights: parser.area_lights,
+        lights: parser.lights,
+        meshes: parser.meshes,
+    });
+}
+
+impl SceneDescription {
+    pub fn build(self, settings: &RenderSettings) -> Result<Scene, String> {
+        let CameraDescription {
+            look_from,
+            look_at,
+            up,
+            fov,
+            projection,
+            aperture,
+            focus_distance,
+            aperture_shape,
+        } = self.camera;
+        // Rows are rendered top to bottom, so the camera expects up to point down the image.
+        let camera = Camera::from_config(CameraConfig {
+            image_width: settings.width as f32,
+            image_height: settings.height as f32,
+            look_from,
+            look_at,
+            up: -up,
+            fov,
+            projection,
+            aperture,
+            focus_distance: focus_distance.unwrap_or((look_at - look_from).magnitude()),
+            aperture_shape,
+        });
+
+        // Every model is only loaded once, instances of it share the geometry.
+        let mut loaded_meshes: HashMap<(PathBuf, Option<String>, Option<u32>), Arc<Mesh>> =
+            HashMap::new();
+        let mut instances: Vec<MeshInstance> = Vec::new();
+
+        for description in self.meshes {
+            let material_name = description.material.as_ref().map(|(name, _)| name.clone());
+            let key = (
+                description.path.clone(),
+                material_name,
+                description.crease_angle.map(f32::to_bits),
+            );
+
+            let mesh = match loaded_meshes.get(&key) {
+                Some(mesh) => mesh.clone(),
+                None => {
+                    let path = description.path.to_string_lossy();
+                    let (geometry, materials) = load_model(&path)
+                        .map_err(|e| format!("{}:{}: {}", self.file_name, description.line, e))?;
+
+                    let mut mesh = match description.material {
+                        Some((_, material)) => Mesh::new_override_material(geometry, material),
+                        None => Mesh::new_override_material_set(geometry, materials),
+                    }
+                    .map_err(|e| {
+                        format!(
+                            "{}:{}: There was an error loading '{}': {}",
+                            self.file_name, description.line, path, e
+                        )
+                    })?;
+                    if let Some(crease_angle) = description.crease_angle {
+                        mesh.generate_normals(crease_angle);
+                    }
+
+                    let mesh = Arc::new(mesh);
+                    loaded_meshes.insert(key, mesh.clone());
+                    mesh
+                }
+            };
+
+            let mut instance = MeshInstance::new(mesh, description.transform);
+            instance.interior = description.interior;
+            instances.push(instance);
+        }
+
+        let mut scene = Scene::build_scene(
+            instances,
+            self.spheres,
+            self.volumes,
+            self.area_lights,
+            camera,
+        );
+        scene.triangle_intersector = self.triangle_intersector;
+        scene.fog = self.fog;
+        for light in self.lights {
+            scene.lights.add(light);
+        }
+        if let Some(environment) = self.environment {
+            scene.lights.set_environment(environment);
+        }
+
+        return Ok(scene);
+    }
+}
+
+struct SceneParser<'a> {
+    base_directory: &'a Path,
+    settings: RenderSettings,
+    camera: Option<CameraDescription>,
+    triangle_intersector: TriangleIntersector,
+    fog: Option<Medium>,
+    environment: Option<Environment>,
+    textures: HashMap<String, Texture>,
+    materials: HashMap<String, Material>,
+    media: HashMap<String, Medium>,
+    spheres: Vec<Sphere>,
+    volumes: Vec<Volume>,
+    area_lights: Vec<AreaLight>,
+    // Lights which aren't geometry, added to the scene's lights once it's built.
+    lights: Vec<Light>,
+    meshes: Vec<MeshDescription>,
+}
+
+impl SceneParser<'_> {
+    fn parse_statement(&mut self, statement: &str, line: usize) -> Result<(), String> {
+        let mut tokens = Tokens {
+            tokens: statement.split_whitespace(),
+        };
+
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return Ok(()), // Empty line or comment.
+        };
+
+        match keyword {
+            "settings" => self.parse_settings(&mut tokens),
+            "camera" => self.parse_camera(&mut tokens),
+            "intersector" => self.parse_intersector(&mut tokens),
+            "texture" => self.parse_texture(&mut tokens),
+            "material" => self.parse_material(&mut tokens),
+            "medium" => self.parse_medium(&mut tokens),
+            "fog" => self.parse_fog(&mut tokens),
+            "environment" => self.parse_environment(&mut tokens),
+            "sphere" => self.parse_sphere(&mut tokens),
+            "volume" => self.parse_volume(&mut tokens),
+            "mesh" => self.parse_mesh(&mut tokens, line),
+            "light" => self.parse_light(&mut tokens),
+            _ => Err(format!("Unknown statement '{}'", keyword)),
+        }
+    }
+
+    // settings width 640 height 480 max-depth 8 samples-per-pass 16 spectral false
+    fn parse_settings(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        while let Some(key) = tokens.next() {
+            match key {
+                "width" => self.settings.width = tokens.positive_integer(key)?,
+                "height" => self.settings.height = tokens.positive_integer(key)?,
+                "max-depth" => self.settings.max_depth = tokens.positive_integer(key)?,
+                "samples-per-pass" => {
+                    self.settings.samples_per_pass = tokens.positive_integer(key)?
+                }
+                "spectral" => self.settings.spectral = tokens.boolean(key)?,
+                _ => return Err(unknown_key("settings", key)),
+            }
+        }
+        return Ok(());
+    }
+
+    // camera from 0 5 30 at 0 5 0 up 0 1 0 fov 40 aperture 0.5 focus-distance 25 blades 6
+    // The `projection` is perspective (`fov` degrees across the image height, less than 180),
+    // orthographic (`view-height` world units tall, e.g. `projection orthographic view-height 10`),
+    // fisheye (`fov` across the image height) or equirectangular. It focuses on `at` unless
+    // `focus-distance` is given, its aperture is round unless it has `blades` (rotated by
+    // `blade-rotation` degrees) or an `aperture-mask` image.
+    fn parse_camera(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        if self.camera.is_some() {
+            return Err("The camera has already been defined".to_string());
+        }
+
+        let mut look_from = None;
+        let mut look_at = None;
+        let mut up = Vector3::new(0.0, 1.0, 0.0);
+        let mut fov = 60.0;
+        let mut projection = Projection::Perspective;
+        let mut view_height = None;
+        let mut aperture = 0.0;
+        let mut focus_distance = None;
+        let mut blades = None;
+        let mut blade_rotation = 0.0;
+        let mut aperture_mask = None;
+
+        while let Some(key) = tokens.next() {
+            match key {
+                "from" => look_from = Some(tokens.vector(key)?),
+                "at" => look_at = Some(tokens.vector(key)?),
+                "up" => up = tokens.vector(key)?,
+                "fov" => fov = tokens.positive_number(key)?,
+                "projection" => projection = Projection::from_name(tokens.word(key)?)?,
+                "view-height" => view_height = Some(tokens.positive_number(key)?),
+                "aperture" => aperture = tokens.non_negative_number(key)?,
+                "focus-distance" => focus_distance = Some(tokens.positive_number(key)?),
+                "blades" => {
+                    let count: u32 = tokens.positive_integer(key)?;
+                    if count < 3 {
+                        return Err("An aperture needs at least 3 blades".to_string());
+                    }
+                    blades = Some(count);
+                }
+                "blade-rotation" => blade_rotation = tokens.number(key)?,
+                "aperture-mask" => {
+                    let path = self.base_directory.join(tokens.word(key)?);
+                    aperture_mask = Some(Arc::new(ApertureMask::load(&path)?));
+                }
+                _ => return Err(unknown_key("camera", key)),
+            }
+        }
+
+        if projection == Projection::Perspective && fov >= 180.0 {
+            return Err(format!(
+                "The fov of a perspective camera has to be less than 180 degrees, got {}",
+                fov
+            ));
+        }
+        if let (Projection::Orthographic { height }, Some(view_height)) =
+            (&mut projection, view_height)
+        {
+            *height = view_height;
+        }
+
+        let aperture_shape = match (aperture_mask, blades) {
+            (Some(mask), _) => ApertureShape::Mask(mask),
+            (None, Some(blades)) => ApertureShape::Polygon {
+                blades,
+                rotation: blade_rotation,
+            },
+            (None, None) => ApertureShape::Circle,
+        };
+
+        self.camera = Some(CameraDescription {
+            look_from: look_from.ok_or(missing_key("camera", "from"))?,
+            look_at: look_at.ok_or(missing_key("camera", "at"))?,
+            up,
+            fov,
+            projection,
+            aperture,
+            focus_distance,
+            aperture_shape,
+        });
+        return Ok(());
+    }
+
+    // intersector watertight
+    fn parse_intersector(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        self.triangle_intersector = match tokens.word("intersector")? {
+            "moller-trumbore" => TriangleIntersector::MollerTrumbore,
+            "watertight" => TriangleIntersector::Watertight,
+            other => {
+                return Err(format!(
+                    "Unknown intersector '{}', expected moller-trumbore or watertight",
+                    other
+                ))
+            }
+        };
+        return tokens.end();
+    }
+
+    // texture tiles checker scale 8 low 0.1 high 0.9 0.9 0.8
+    // texture bumps image bumps.png wrap repeat data true
+    // Images, or patterns (checker, noise, fbm, turbulence, marble, wood, gradient) blending from
+    // `low` to `high` on the texture coordinates or, with `space solid`, in world space. Images
+    // holding numbers rather than colors are `data`, they aren't gamma decoded.
+    fn parse_texture(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let name = tokens.word("texture name")?;
+        if self.textures.contains_key(name) {
+            return Err(already_defined(name));
+        }
+        let kind = tokens.word("texture type")?;
+
+        let texture = if kind == "image" {
+            let path = self.base_directory.join(tokens.word("image")?);
+            let mut wrap = WrapMode::default();
+            let mut is_data = false;
+            while let Some(key) = tokens.next() {
+                match key {
+                    "wrap" => wrap = WrapMode::from_name(tokens.word(key)?)?,
+                    "data" => is_data = tokens.boolean(key)?,
+                    _ => return Err(unknown_key("image texture", key)),
+                }
+            }
+            let image = ImageTexture::load(&path, !is_data)?;
+            Texture::Image(Arc::new(image), wrap)
+        } else {
+            let mut pattern = Pattern::new(
+                PatternKind::from_name(kind)?,
+                Texture::uniform(0.0),
+                Texture::uniform(1.0),
+            );
+            while let Some(key) = tokens.next() {
+                match key {
+                    "space" => pattern.space = PatternSpace::from_name(tokens.word(key)?)?,
+                    "scale" => pattern.scale = tokens.number(key)?,
+                    "octaves" => pattern.octaves = tokens.positive_integer(key)?,
+                    "low" => pattern.low = self.color_texture(tokens, key)?,
+                    "high" => pattern.high = self.color_texture(tokens, key)?,
+                    _ => return Err(unknown_key(&format!("{} texture", kind), key)),
+                }
+            }
+            Texture::Pattern(Arc::new(pattern))
+        };
+
+        self.textures.insert(name.to_string(), texture);
+        return Ok(());
+    }
+
+    // material red diffuse albedo 0.9 0.1 0.1
+    // material paint principled base-color 0.6 0.05 0.05 roughness 0.4 clearcoat 1 metallic 0.2
+    // Colors and numbers (albedo, fuzz, roughness, base-color, emission...) can name a texture
+    // instead. Conductors take the complex index of refraction of a `metal` preset, or their own
+    // `eta` and `k`. Conductors and dielectrics with a `roughness` are GGX microfacet surfaces.
+    // Smooth dielectrics take a `cauchy` (A, B) or `sellmeier` (B1 B2 B3 C1 C2 C3, micrometers)
+    // index of refraction or a `glass` preset, which disperse light with `spectral true`.
+    // Principled materials layer a diffuse or glass (`transmission`) base, a `metallic` and
+    // `specular` reflection, `sheen` and a `clearcoat`, and take a tangent space `normal-map` or a
+    // `bump` texture. Subsurface materials scatter light inside closed spheres and meshes about
+    // every `mean-free-path`. `interface` materials are invisible and only hold a medium.
+    fn parse_material(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let name = tokens.word("material name")?;
+        if self.materials.contains_key(name) {
+            return Err(already_defined(name));
+        }
+        let kind = tokens.word("material type")?;
+
+        let material = match kind {
+            "diffuse" => {
+                let mut albedo = Texture::uniform(0.8);
+                while let Some(key) = tokens.next() {
+                    match key {
+                        "albedo" => albedo = self.color_texture(tokens, key)?,
+                        _ => return Err(unknown_key("diffuse material", key)),
+                    }
+                }
+                match albedo {
+                    Texture::Constant(albedo) => Material::Diffuse(albedo),
+                    _ => Material::Texture(albedo),
+                }
+            }
+            "metal" => {
+                let mut albedo = Texture::uniform(1.0);
+                let mut fuzz = Texture::uniform(0.0);
+                while let Some(key) = tokens.next() {
+                    match key {
+                        "albedo" => albedo = self.color_texture(tokens, key)?,
+                        "fuzz" => fuzz = self.number_texture(tokens, key)?,
+                        _ => return Err(unknown_key("metal material", key)),
+                    }
+                }
+                Material::Metallic(albedo, fuzz)
+            }
+            "conductor" => {
+                let (mut eta, mut k) = metal_preset("aluminium")?;
+                let mut roughness = Texture::uniform(0.0);
+                while let Some(key) = tokens.next() {
+                    match key {
+                        "metal" => (eta, k) = metal_preset(tokens.word(key)?)?,
+                        "eta" => eta = tokens.vector(key)?,
+                        "k" => k = tokens.vector(key)?,
+                        "roughness" => roughness = self.number_texture(tokens, key)?,
+                        _ => return Err(unknown_key("conductor material", key)),
+                    }
+                }
+                match roughness {
+                    Texture::Constant(roughness) => {
+                        Material::Conductor(Conductor::new(eta, k, roughness.x), None)
+                    }
+                    _ => Material::Conductor(Conductor::new(eta, k, 1.0), Some(roughness)),
+                }
+            }
+            "dielectric" => {
+                let mut ior = Ior::Constant(1.5);
+                let mut roughness = 0.0;
+                while let Some(key) = tokens.next() {
+                    match key {
+                        "ior" => ior = Ior::Constant(tokens.number(key)?),
+                        "cauchy" => ior = Ior::Cauchy(tokens.number(key)?, tokens.number(key)?),
+                        "sellmeier" => {
+                            let b = tokens.vector(key)?;
+                            let c = tokens.vector(key)?;
+                            ior = Ior::Sellmeier(b.into(), c.into());
+                        }
+                        "glass" => ior = Ior::preset(tokens.word(key)?)?,
+                        "roughness" => roughness = tokens.number(key)?,
+                        _ => return Err(unknown_key("dielectric material", key)),
+                    }
+                }
+                // Only smooth glass disperses light.
+                if roughness > 0.0 {
+                    Material::RoughDielectric(RoughDielectric::new(ior.nominal(), roughness))
+                } else {
+                    Material::Dielectric(ior)
+                }
+            }
+            "principled" => {
+                let mut principled = Principled::new(Vector3::new(0.8, 0.8, 0.8));
+                let mut bump_scale = 1.0;
+                while let Some(key) = tokens.next() {
+                    match key {
+                        "base-color" => match self.color_texture(tokens, key)? {
+                            Texture::Constant(color) => principled.base_color = color,
+                            texture => {
+                                principled.base_color = Vector3::new(1.0, 1.0, 1.0);
+                                principled.base_color_map = Some(texture);
+                            }
+                        },
+                        "image" => {
+                            let path = self.base_directory.join(tokens.word(key)?);
+                            let image = ImageTexture::load(&path, true)?;
+                            principled.base_color_map =
+                                Some(Texture::Image(Arc::new(image), WrapMode::default()));
+                        }
+                        "metallic" => principled.metallic = tokens.number(key)?,
+                        "roughness" => match self.number_texture(tokens, key)? {
+                            Texture::Constant(roughness) => principled.roughness = roughness.x,
+                            texture => {
+                                principled.roughness = 1.0;
+                                principled.roughness_map = Some(texture);
+                            }
+                        },
+                        "specular" => principled.specular = tokens.number(key)?,
+                        "sheen" => principled.sheen = tokens.number(key)?,
+                        "sheen-tint" => principled.sheen_tint = tokens.number(key)?,
+                        "clearcoat" => principled.clearcoat = tokens.number(key)?,
+                        "clearcoat-roughness" => {
+                            principled.clearcoat_roughness = tokens.number(key)?
+                        }
+                        "transmission" => principled.transmission = tokens.number(key)?,
+                        "ior" => principled.ior = tokens.number(key)?,
+                        "emission" => match self.color_texture(tokens, key)? {
+                            Texture::Constant(color) => principled.emission = color,
+                            texture => {
+                                principled.emission = Vector3::new(1.0, 1.0, 1.0);
+                                principled.emission_map = Some(texture);
+                            }
+                        },
+                        "normal-map" => {
+                            let texture = self.texture(tokens.word(key)?)?;
+                            principled.normal_map = Some(NormalMap::Normal(texture, 1.0));
+                        }
+                        "bump" => {
+                            let texture = self.texture(tokens.word(key)?)?;
+                            principled.normal_map = Some(NormalMap::Bump(texture, 1.0));
+                        }
+                        "bump-scale" => bump_scale = tokens.number(key)?,
+                        _ => return Err(unknown_key("principled material", key)),
+                    }
+                }
+                principled.normal_map = match principled.normal_map {
+                    Some(NormalMap::Normal(texture, _)) => {
+                        Some(NormalMap::Normal(texture, bump_scale))
+                    }
+                    Some(NormalMap::Bump(texture, _)) => Some(NormalMap::Bump(texture, bump_scale)),
+                    None => None,
+                };
+                Material::Principled(principled)
+            }
+            "subsurface" => {
+                let mut albedo = Vector3::new(0.8, 0.8, 0.8);
+                let mut mean_free_path = Vector3::new(0.1, 0.1, 0.1);
+                let mut ior = 1.4;
+                while let Some(key) = tokens.next() {
+                    match key {
+                        "albedo" => albedo = tokens.vector(key)?,
+                        "mean-free-path" => mean_free_path = tokens.vector(key)?,
+                        "ior" => ior = tokens.number(key)?,
+                        _ => return Err(unknown_key("subsurface material", key)),
+                    }
+                }
+                Material::Subsurface(Subsurface::new(albedo, mean_free_path, ior))
+            }
+            "texture" => {
+                let mut texture = Texture::Constant(Vector3::new(0.8, 0.8, 0.8));
+                let mut wrap = WrapMode::default();
+                while let Some(key) = tokens.next() {
+                    match key {
+                        "color" => texture = self.color_texture(tokens, key)?,
+                        "image" => {
+                            let path = self.base_directory.join(tokens.word(key)?);
+                            let image = ImageTexture::load(&path, true)?;
+                            texture = Texture::Image(Arc::new(image), wrap);
+                        }
+                        "wrap" => wrap = WrapMode::from_name(tokens.word(key)?)?,
+                        _ => return Err(unknown_key("texture material", key)),
+                    }
+                }
+                // The wrap mode may come after the image.
+                if let Texture::Image(image, _) = texture {
+                    texture = Texture::Image(image, wrap);
+                }
+                Material::Texture(texture)
+            }
+            "emissive" => {
+                let mut color = Texture::uniform(1.0);
+                let mut intensity = 1.0;
+                while let Some(key) = tokens.next() {
+                    match key {
+                        "color" => color = self.color_texture(tokens, key)?,
+                        "intensity" => intensity = tokens.number(key)?,
+                        _ => return Err(unknown_key("emissive material", key)),
+                    }
+                }
+                Material::Emissive(color, intensity)
+            }
+            "interface" => {
+                tokens.end()?;
+                Material::Interface
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown material type '{}', expected diffuse, metal, conductor, dielectric, principled, subsurface, texture, emissive or interface",
+                    kind
+                ))
+            }
+        };
+
+        self.materials.insert(name.to_string(), material);
+        return Ok(());
+    }
+
+    // medium smoke absorption 0.01 0.01 0.01 scattering 0.3 0.3 0.3 anisotropy 0.5
+    // medium green-glass color 0.2 0.8 0.3 distance 4
+    // Either coefficients per unit of distance, or the `color` light is tinted to after going
+    // `distance` through the medium.
+    fn parse_medium(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let name = tokens.word("medium name")?;
+        if self.media.contains_key(name) {
+            return Err(already_defined(name));
+        }
+        let medium = parse_medium_keys("medium", tokens)?;
+        self.media.insert(name.to_string(), medium);
+        return Ok(());
+    }
+
+    // environment image sky.hdr rotation 90 intensity 1.5
+    // environment sky elevation 30 azimuth 120 turbidity 3 sun-size 2
+    // A constant `color`, a `gradient` from `bottom` to `top`, a lat-long `image` (.hdr or .pfm) or
+    // a daylight `sky`, which lights everything rays escape to.
+    fn parse_environment(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        if self.environment.is_some() {
+            return Err("The environment has already been defined".to_string());
+        }
+
+        let mut background = None;
+        let mut bottom = Vector3::new(1.0, 1.0, 1.0);
+        let mut top = Vector3::new(0.5, 0.7, 1.0);
+        let mut elevation = 45.0;
+        let mut azimuth = 0.0;
+        let mut turbidity = 3.0;
+        let mut sun_size = 1.0;
+        // `gradient` or `sky`, which are made from their keys once all of them have been read.
+        let mut procedural = None;
+        let mut intensity = 1.0;
+        let mut rotation = 0.0;
+
+        while let Some(key) = tokens.next() {
+            match key {
+                "color" => background = Some(Background::Constant(tokens.vector(key)?)),
+                "gradient" | "sky" => procedural = Some(key),
+                "bottom" => bottom = tokens.vector(key)?,
+                "top" => top = tokens.vector(key)?,
+                "image" => {
+                    let path = self.base_directory.join(tokens.word(key)?);
+                    let image = EnvironmentImage::load(&path)?;
+                    background = Some(Background::Image(Arc::new(image)));
+                }
+                "elevation" => elevation = tokens.number(key)?,
+                "azimuth" => azimuth = tokens.number(key)?,
+                "turbidity" => turbidity = tokens.number(key)?,
+                "sun-size" => sun_size = tokens.number(key)?,
+                "intensity" => intensity = tokens.number(key)?.max(0.0),
+                "rotation" => rotation = tokens.number(key)?,
+                _ => return Err(unknown_key("environment", key)),
+            }
+        }
+
+        let background = match (background, procedural) {
+            (Some(background), None) => background,
+            (None, Some("gradient")) => Background::Gradient { bottom, top },
+            (None, Some(_)) => {
+                Background::Sky(Arc::new(Sky::new(elevation, azimuth, turbidity, sun_size)))
+            }
+            (Some(_), Some(kind)) => {
+                return Err(format!(
+                    "An environment can't be a {} and something else",
+                    kind
+                ))
+            }
+            (None, None) => {
+                return Err(missing_key("environment", "color, gradient, image or sky"))
+            }
+        };
+
+        self.environment = Some(Environment::new(background, intensity, rotation));
+        return Ok(());
+    }
+
+    // fog scattering 0.01 0.01 0.01 anisotropy 0.3
+    // Either `medium <name>`, or the keys of a medium.
+    fn parse_fog(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        if tokens.peek() == Some("medium") {
+            tokens.next();
+            self.fog = Some(self.medium(tokens.word("medium")?)?);
+            return tokens.end();
+        }
+        self.fog = Some(parse_medium_keys("fog", tokens)?);
+        return Ok(());
+    }
+
+    // sphere center 8 5 0 radius 3 material bounds interior smoke
+    fn parse_sphere(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let mut center = None;
+        let mut radius = None;
+        let mut material = None;
+        let mut interior = None;
+
+        while let Some(key) = tokens.next() {
+            match key {
+                "center" => center = Some(tokens.vector(key)?),
+                "radius" => radius = Some(tokens.positive_number(key)?),
+                "material" => material = Some(self.material(tokens.word(key)?)?),
+                "interior" => interior = Some(self.medium(tokens.word(key)?)?),
+                _ => return Err(unknown_key("sphere", key)),
+            }
+        }
+
+        let mut sphere = Sphere::new(
+            center.ok_or(missing_key("sphere", "center"))?,
+            radius.ok_or(missing_key("sphere", "radius"))?,
+            material.ok_or(missing_key("sphere", "material"))?,
+        );
+        sphere.interior = interior;
+        self.spheres.push(sphere);
+        return Ok(());
+    }
+
+    // volume min -5 0 -5 max 5 10 5 medium smoke grid cloud.vol density 2
+    // A box filled with the medium, its coefficients scaled by the densities of the voxel grid.
+    fn parse_volume(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let mut min = None;
+        let mut max = None;
+        let mut medium = None;
+        let mut grid = None;
+        let mut density = 1.0;
+
+        while let Some(key) = tokens.next() {
+            match key {
+                "min" => min = Some(tokens.vector(key)?),
+                "max" => max = Some(tokens.vector(key)?),
+                "medium" => medium = Some(self.medium(tokens.word(key)?)?),
+                "grid" => {
+                    let path = self.base_directory.join(tokens.word(key)?);
+                    grid = Some(Arc::new(VoxelGrid::load(&path)?));
+                }
+                "density" => density = tokens.number(key)?.max(0.0),
+                _ => return Err(unknown_key("volume", key)),
+            }
+        }
+
+        let min: Vector3<f32> = min.ok_or(missing_key("volume", "min"))?;
+        let max: Vector3<f32> = max.ok_or(missing_key("volume", "max"))?;
+        if min.x >= max.x || min.y >= max.y || min.z >= max.z {
+            return Err("The min corner of a volume has to be below its max corner".to_string());
+        }
+        let bounds = AABB::new_from_diagonals(min, max);
+
+        let mut medium = medium.ok_or(missing_key("volume", "medium"))?;
+        match grid {
+            Some(grid) => {
+                medium.density = Some(DensityField {
+                    grid,
+                    bounds,
+                    scale: density,
+                })
+            }
+            None => {
+                medium.absorption *= density;
+                medium.scattering *= density;
+            }
+        }
+
+        self.volumes.push(Volume::new(bounds, medium));
+        return Ok(());
+    }
+
+    // mesh teapot.obj translate 0 0 0 rotate 0 90 0 scale 2 material mirror crease-angle 45
+    // Obj or glTF (.gltf, .glb) files, with their own materials unless given one. Faces without
+    // normals in the file are flat, unless smoothed up to a `crease-angle` in degrees.
+    fn parse_mesh(&mut self, tokens: &mut Tokens, line: usize) -> Result<(), String> {
+        let path = self.base_directory.join(tokens.word("mesh path")?);
+        let mut material = None;
+        let mut crease_angle = None;
+        let mut translation = Vector3::new(0.0, 0.0, 0.0);
+        let mut rotation = Vector3::new(0.0, 0.0, 0.0);
+        let mut scale = Vector3::new(1.0, 1.0, 1.0);
+        let mut interior = None;
+
+        while let Some(key) = tokens.next() {
+            match key {
+                "material" => {
+                    let name = tokens.word(key)?;
+                    material = Some((name.to_string(), self.material(name)?));
+                }
+                "crease-angle" => crease_angle = Some(tokens.number(key)?),
+                "interior" => interior = Some(self.medium(tokens.word(key)?)?),
+                "translate" => translation = tokens.vector(key)?,
+                "rotate" => rotation = tokens.vector(key)?,
+                // Either a single uniform factor, or one per axis.
+                "scale" => {
+                    let x = tokens.number(key)?;
+                    scale = match tokens.peek_number() {
+                        Some(_) => Vector3::new(x, tokens.number(key)?, tokens.number(key)?),
+                        None => Vector3::new(x, x, x),
+                    };
+                    // A flattened mesh has no inverse transform to intersect rays with.
+                    if [scale.x, scale.y, scale.z].iter().any(|s| s.abs() < 1e-6) {
+                        return Err(format!(
+                            "Expected non-zero factors for scale, got {} {} {}",
+                            scale.x, scale.y, scale.z
+                        ));
+                    }
+                }
+                _ => return Err(unknown_key("mesh", key)),
+            }
+        }
+
+        self.meshes.push(MeshDescription {
+            path,
+            material,
+            crease_angle,
+            transform: Transform::from_components(translation, rotation, scale)?,
+            interior,
+            line,
+        });
+        return Ok(());
+    }
+
+    // light sphere center 0 20 0 radius 2 color 1 1 1 intensity 15
+    // light quad corner -1 9.9 -1 edge-u 2 0 0 edge-v 0 0 2 intensity 20 visible false
+    // light spot position 0 8 4 at 0 0 0 angle 25 falloff 5 color 1 0.9 0.7 intensity 200
+    // Quads emit on the side edge-u x edge-v points to, disks towards their `normal`, and both are
+    // hidden from the camera with `visible false`. Spots light a cone of `angle` degrees around
+    // `at`, fading over the last `falloff` degrees, `point` lights shine from a `position` and
+    // `directional` ones along a `direction`.
+    // Lights are sampled directly by the renderer. Spheres, quads and disks are emissive geometry,
+    // point, spot and directional lights can only be reached by shadow rays.
+    fn parse_light(&mut self, tokens: &mut Tokens) -> Result<(), String> {
+        let light_type = tokens.word("light type")?;
+        if !matches!(
+            light_type,
+            "sphere" | "quad" | "disk" | "point" | "spot" | "directional"
+        ) {
+            return Err(format!(
+                "Unknown light type '{}', expected sphere, quad, disk, point, spot or directional",
+                light_type
+            ));
+        }
+        let statement = format!("{} light", light_type);
+
+        let mut color = Vector3::new(1.0, 1.0, 1.0);
+        let mut intensity = 1.0;
+        let mut visible = true;
+        let mut center = None;
+        let mut radius = None;
+        let mut corner = None;
+        let mut edge_u = None;
+        let mut edge_v = None;
+        let mut normal = None;
+        let mut position = None;
+        let mut at = None;
+        let mut direction = None;
+        let mut angle = 30.0;
+        let mut falloff = 5.0;
+
+        while let Some(key) = tokens.next() {
+            match (light_type, key) {
+                (_, "color") => color = tokens.vector(key)?,
+                (_, "intensity") => intensity = tokens.number(key)?,
+                ("quad" | "disk", "visible") => visible = tokens.boolean(key)?,
+                ("sphere" | "disk", "center") => center = Some(tokens.vector(key)?),
+                ("sphere" | "disk", "radius") => radius = Some(tokens.positive_number(key)?),
+                ("quad", "corner") => corner = Some(tokens.vector(key)?),
+                ("quad", "edge-u") => edge_u = Some(tokens.vector(key)?),
+                ("quad", "edge-v") => edge_v = Some(tokens.vector(key)?),
+                ("disk", "normal") => normal = Some(tokens.vector(key)?),
+                ("point" | "spot", "position") => position = Some(tokens.vector(key)?),
+                ("spot", "at") => at = Some(tokens.vector(key)?),
+                ("spot", "angle") => angle = tokens.number(key)?,
+                ("spot", "falloff") => falloff = tokens.number(key)?,
+                ("directional", "direction") => direction = Some(tokens.vector(key)?),
+                _ => return Err(unknown_key(&statement, key)),
+            }
+        }
+
+        match light_type {
+            "sphere" => self.spheres.push(Sphere::new(
+                center.ok_or(missing_key(&statement, "center"))?,
+                radius.ok_or(missing_key(&statement, "radius"))?,
+                Material::Emissive(Texture::Constant(color), intensity),
+            )),
+            "quad" | "disk" => {
+                let shape = if light_type == "quad" {
+                    let edge_u = edge_u.ok_or(missing_key(&statement, "edge-u"))?;
+                    let edge_v = edge_v.ok_or(missing_key(&statement, "edge-v"))?;
+                    if edge_u.cross(edge_v).magnitude2() == 0.0 {
+                        return Err("The edges of a quad light can't be parallel".to_string());
+                    }
+                    AreaLightShape::Quad {
+                        corner: corner.ok_or(missing_key(&statement, "corner"))?,
+                        edge_u,
+                        edge_v,
+                    }
+                } else {
+                    AreaLightShape::Disk {
+                        center: center.ok_or(missing_key(&statement, "center"))?,
+                        normal: unit(normal.ok_or(missing_key(&statement, "normal"))?, "normal")?,
+                        radius: radius.ok_or(missing_key(&statement, "radius"))?,
+                    }
+                };
+                let mut area_light = AreaLight::new(shape, color, intensity);
+                area_light.visible = visible;
+                self.area_lights.push(area_light);
+            }
+            "point" => self.lights.push(Light::Point {
+                position: position.ok_or(missing_key(&statement, "position"))?,
+                intensity: intensity * color,
+            }),
+            "spot" => {
+                let position = position.ok_or(missing_key(&statement, "position"))?;
+                let at = at.ok_or(missing_key(&statement, "at"))?;
+                let angle = f32::clamp(angle, 0.0, 180.0);
+                self.lights.push(Light::Spot {
+                    position,
+                    direction: unit(at - position, "at")?,
+                    intensity: intensity * color,
+                    cos_falloff_start: (angle - falloff.clamp(0.0, angle)).to_radians().cos(),
+                    cos_cutoff: angle.to_radians().cos(),
+                });
+            }
+            _ => self.lights.push(Light::Directional {
+                direction: unit(
+                    direction.ok_or(missing_key(&statement, "direction"))?,
+                    "direction",
+                )?,
+                irradiance: intensity * color,
+            }),
+        }
+        return Ok(());
+    }
+
+    // A color (or a single number for grey), or the name of a texture.
+    fn color_texture(&self, tokens: &mut Tokens, what: &str) -> Result<Texture, String> {
+        if tokens.peek_number().is_none() {
+            return self.texture(tokens.word(what)?);
+        }
+        let value = tokens.number(what)?;
+        if tokens.peek_number().is_none() {
+            return Ok(Texture::uniform(value));
+        }
+        return Ok(Texture::Constant(Vector3::new(
+            value,
+            tokens.number(what)?,
+            tokens.number(what)?,
+        )));
+    }
+
+    // A number, or the name of a texture.
+    fn number_texture(&self, tokens: &mut Tokens, what: &str) -> Result<Texture, String> {
+        if tokens.peek_number().is_none() {
+            return self.texture(tokens.word(what)?);
+        }
+        return Ok(Texture::uniform(tokens.number(what)?));
+    }
+
+    fn texture(&self, name: &str) -> Result<Texture, String> {
+        return self
+            .textures
+            .get(name)
+            .cloned()
+            .ok_or(format!("Unknown texture '{}'", name));
+    }
+
+    fn material(&self, name: &str) -> Result<Material, String> {
+        return self
+            .materials
+            .get(name)
+            .cloned()
+            .ok_or(format!("Unknown material '{}'", name));
+    }
+
+    fn medium(&self, name: &str) -> Result<Medium, String> {
+        return self
+            .media
+            .get(name)
+            .cloned()
+            .ok_or(format!("Unknown medium '{}'", name));
+    }
+}
+
+fn parse_medium_keys(statement: &str, tokens: &mut Tokens) -> Result<Medium, String> {
+    let mut medium = Medium {
+        absorption: Vector3::new(0.0, 0.0, 0.0),
+        scattering: Vector3::new(0.0, 0.0, 0.0),
+        anisotropy: 0.0,
+        density: None,
+    };
+    let mut color = None;
+    let mut distance = 1.0;
+
+    while let Some(key) = tokens.next() {
+        match key {
+            "absorption" => medium.absorption = tokens.vector(key)?,
+            "scattering" => medium.scattering = tokens.vector(key)?,
+            "anisotropy" => medium.anisotropy = tokens.number(key)?.clamp(-0.99, 0.99),
+            "color" => color = Some(tokens.vector(key)?),
+            "distance" => distance = tokens.number(key)?,
+            _ => return Err(unknown_key(statement, key)),
+        }
+    }
+
+    if let Some(color) = color {
+        if distance <= 0.0 {
+            return Err(format!(
+                "The distance of a {} has to be positive",
+                statement
+            ));
+        }
+        let tinted = Medium::from_color_at_distance(color, distance);
+        medium.absorption = tinted.absorption;
+    }
+    return Ok(medium);
+}
+
+fn unknown_key(statement: &str, key: &str) -> String {
+    return format!("Unknown key '{}' for {}", key, statement);
+}
+
+fn missing_key(statement: &str, key: &str) -> String {
+    return format!("Missing '{}' for {}", key, statement);
+}
+
+fn already_defined(name: &str) -> String {
+    return format!("'{}' has already been defined", name);
+}
+
+fn unit(vector: Vector3<f32>, what: &str) -> Result<Vector3<f32>, String> {
+    if vector.magnitude2() == 0.0 {
+        return Err(format!("The {} can't be a zero vector", what));
+    }
+    return Ok(vector.normalize());
+}
+
+// The whitespace separated words of a statement.
+struct Tokens<'a> {
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        return self.tokens.next();
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        return self.tokens.clone().next();
+    }
+
+    fn peek_number(&self) -> Option<f32> {
+        return self.tokens.clone().next()?.parse::<f32>().ok();
+    }
+
+    fn word(&mut self, what: &str) -> Result<&'a str, String> {
+        return self.next().ok_or(format!("Expected a value for {}", what));
+    }
+
+    fn number(&mut self, what: &str) -> Result<f32, String> {
+        let word = self.word(what)?;
+        return word
+            .parse::<f32>()
+            .ok()
+            .filter(|number| number.is_finite())
+            .ok_or(format!("Expected a number for {}, got '{}'", what, word));
+    }
+
+    fn positive_number(&mut self, what: &str) -> Result<f32, String> {
+        let word = self.word(what)?;
+        return word
+            .parse::<f32>()
+            .ok()
+            .filter(|number| number.is_finite() && *number > 0.0)
+            .ok_or(format!(
+                "Expected a positive number for {}, got '{}'",
+                what, word
+            ));
+    }
+
+    fn non_negative_number(&mut self, what: &str) -> Result<f32, String> {
+        let word = self.word(what)?;
+        return word
+            .parse::<f32>()
+            .ok()
+            .filter(|number| number.is_finite() && *number >= 0.0)
+            .ok_or(format!(
+                "Expected a non-negative number for {}, got '{}'",
+                what, word
+            ));
+    }
+
+    fn vector(&mut self, what: &str) -> Result<Vector3<f32>, String> {
+        return Ok(Vector3::new(
+            self.number(what)?,
+            self.number(what)?,
+            self.number(what)?,
+        ));
+    }
+
+    fn boolean(&mut self, what: &str) -> Result<bool, String> {
+        return match self.word(what)? {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            word => Err(format!(
+                "Expected true or false for {}, got '{}'",
+                what, word
+            )),
+        };
+    }
+
+    // Also has to fit in the type it is parsed into.
+    fn positive_integer<T: FromStr + PartialOrd + Default>(
+        &mut self,
+        what: &str,
+    ) -> Result<T, String> {
+        let word = self.word(what)?;
+        return word
+            .parse::<T>()
+            .ok()
+            .filter(|number| *number > T::default())
+            .ok_or(format!(
+                "Expected a positive integer for {}, got '{}'",
+                what, word
+            ));
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        return match self.next() {
+            Some(word) => Err(format!("Unexpected '{}'", word)),
+            None => Ok(()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<SceneDescription, String> {
+        return parse_scene(source, "test.scene", Path::new(""));
+    }
+
+    #[test]
+    fn parses_every_statement() {
+        let description = parse(
+            "# A comment
+            settings width 320 height 240 max-depth 4 samples-per-pass 2 spectral true
+            camera from 0 1 5 at 0 1 0 fov 45 # Trailing comment
+            intersector watertight
+            material red diffuse albedo 1 0 0
+            texture grain fbm space solid scale 4 low 0.2 high 0.6
+            texture tiles checker scale 8 low 0.1 high grain
+            material paint principled base-color tiles roughness grain clearcoat 1 bump grain
+            sphere center 0 0 0 radius 1 material paint
+            mesh model.obj translate 1 2 3 rotate 0 90 0 scale 2 material red
+            light sphere center 0 5 0 radius 0.5 color 1 1 1 intensity 10
+            light quad corner 0 4 0 edge-u 1 0 0 edge-v 0 0 1 visible false
+            light spot position 0 5 0 at 0 0 0 angle 20 falloff 5
+            light directional direction 0 -1 0 intensity 2",
+        )
+        .unwrap();
+
+        assert_eq!(description.settings.width, 320);
+        assert_eq!(description.settings.height, 240);
+        assert_eq!(description.settings.max_depth, 4);
+        assert_eq!(description.settings.samples_per_pass, 2);
+        assert!(description.settings.spectral);
+        assert_eq!(description.camera.fov, 45.0);
+        assert_eq!(
+            description.triangle_intersector,
+            TriangleIntersector::Watertight
+        );
+        assert_eq!(description.spheres.len(), 2);
+        assert!(description.spheres[1].material.is_emissive());
+        let Material::Principled(paint) = &description.spheres[0].material else {
+            panic!("The first sphere should be painted");
+        };
+        assert!(paint.base_color_map.is_some() && paint.roughness_map.is_some());
+        assert!(matches!(paint.normal_map, Some(NormalMap::Bump(_, _))));
+        assert_eq!(description.meshes.len(), 1);
+        assert_eq!(description.meshes[0].line, 10);
+        assert_eq!(description.area_lights.len(), 1);
+        assert!(!description.area_lights[0].visible);
+        assert_eq!(description.lights.len(), 2);
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let errors = [
+            (
+                "camera from 0 0 0 at 0 0 -1\nsphere center 0 0 0 radius 1 material nope",
+                2,
+            ),
+            (
+                "camera from 0 0 0 at 0 0 -1\n\nsphere center 0 0 zero radius 1",
+                3,
+            ),
+            ("camera from 0 0 0 at 0 0 -1\nsettings width -4", 2),
+            ("teapot", 1),
+            ("camera at 0 0 -1", 1),
+            ("camera from 0 0 0 at 0 0 -1\nlight point center 0 0 0", 2),
+            ("camera from 0 0 0 at 0 0 -1\nmesh m.obj scale 0", 2),
+            (
+                "camera from 0 0 0 at 0 0 -1\nsphere center 0 0 0 radius -2",
+                2,
+            ),
+            (
+                "camera from 0 0 0 at 0 0 -1\nlight sphere center 0 0 0 radius 0",
+                2,
+            ),
+            (
+                "camera from 0 0 0 at 0 0 -1\nlight disk center 0 0 0 normal 0 1 0 radius -1",
+                2,
+            ),
+            (
+                "material a diffuse\ncamera from 0 0 0 at 0 0 -1\nmaterial a metal",
+                3,
+            ),
+            ("texture t checker\ntexture t noise", 2),
+            (
+                "medium m color 1 1 1\n\nmedium m color 0 0 0\ncamera from 0 0 0 at 0 0 -1",
+                3,
+            ),
+            ("camera from 0 0 0 at 0 0 -1 aperture -0.5", 1),
+            ("\ncamera from 0 0 0 at 0 0 -1 focus-distance 0", 2),
+            ("camera from 0 0 0 at 0 0 -1 fov 0", 1),
+            ("camera from 0 0 0 at 0 0 -1 fov 180", 1),
+            (
+                "camera projection orthographic view-height -1 from 0 0 0 at 0 0 -1",
+                1,
+            ),
+        ];
+
+        for (source, line) in errors {
+            let error = parse(source).err().unwrap();
+            assert!(
+                error.starts_with(&format!("test.scene:{}: ", line)),
+                "Expected an error on line {}, got '{}'",
+                line,
+                error
+            );
+        }
+
+        assert!(parse("material red diffuse").is_err());
+        assert!(parse("material red diffuse albedo nope").is_err());
+    }
+}
@@ -0,0 +1,30 @@
+/**
+ * Everything about how a scene gets rendered, rather than what is in it.
+ * Lets the same binary render quick previews and high quality finals.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    // Number of bounces after which a path is terminated.
+    pub max_depth: i32,
+    // Samples per pixel taken in a single render pass.
+    pub samples_per_pass: i32,
+    // Renders a heatmap of the bounding boxes each ray passes through instead of the scene.
+    pub debug_aabb: bool,
+    // Traces wavelengths rather than RGB colors, so glass disperses light into rainbows.
+    pub spectral: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        return RenderSettings {
+            width: 400,
+            height: 400,
+            max_depth: 8,
+            samples_per_pass: 35,
+            debug_aabb: false,
+            spectral: false,
+        };
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Matrix, Matrix4, SquareMatrix, Vector3, Vector4};
+use gltf::image::Format;
+use gltf::mesh::Mode;
+use gltf::texture::WrappingMode;
+use wavefront_obj::obj::{Geometry, ObjSet, Object, Primitive, Shape, TVertex, Vertex};
+
+use crate::materials::material::{Material, MaterialSet};
+use crate::materials::normal_map::NormalMap;
+use crate::materials::principled::Principled;
+use crate::materials::texture::{ImageTexture, Texture, WrapMode};
+
+// Loads a .gltf or .glb file, flattening the meshes of its default scene into an ObjSet in the
+// file's world space, so it can be used like any obj. Its metallic-roughness materials become
+// principled ones.
+pub fn load_and_parse_gltf(path: &str) -> Result<(ObjSet, MaterialSet), String> {
+    let (document, buffers, images) =
+        gltf::import(path).map_err(|e| format!("There was an error loading '{}': {}", path, e))?;
+    return convert_document(&document, &buffers, &images)
+        .map_err(|e| format!("There was an error loading '{}': {}", path, e));
+}
+
+fn convert_document(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+    images: &[gltf::image::Data],
+) -> Result<(ObjSet, MaterialSet), String> {
+    let mut textures = GltfTextures {
+        images,
+        loaded: HashMap::new(),
+    };
+    let mut material_set = MaterialSet::new();
+    for material in document.materials() {
+        let Some(index) = material.index() else {
+            continue;
+        };
+        material_set.add(
+            material_name(index),
+            Material::Principled(convert_material(&material, &mut textures)),
+        );
+    }
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or("The file doesn't have any scene".to_string())?;
+
+    let mut objects = Vec::new();
+    let mut stack: Vec<(gltf::Node, Matrix4<f32>)> = scene
+        .nodes()
+        .map(|node| (node, Matrix4::identity()))
+        .collect();
+    while let Some((node, parent_transform)) = stack.pop() {
+        let transform = parent_transform * Matrix4::from(node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if let Some(object) = convert_primitive(&primitive, buffers, transform) {
+                    objects.push(object);
+                }
+            }
+        }
+        stack.extend(node.children().map(|child| (child, transform)));
+    }
+
+    return Ok((
+        ObjSet {
+            material_library: None,
+            objects,
+        },
+        material_set,
+    ));
+}
+
+// glTF materials don't need names, they are referred to by index.
+fn material_name(index: usize) -> String {
+    return format!("__gltf_material_{}__", index);
+}
+
+// Converts a triangle list into an object with a single geometry, its vertices transformed by
+// `transform`. Other primitive modes (points, lines, strips) are skipped.
+fn convert_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    transform: Matrix4<f32>,
+) -> Option<Object> {
+    if primitive.mode() != Mode::Triangles {
+        return None;
+    }
+
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions: Vec<[f32; 3]> = reader.read_positions()?.collect();
+    // Normals are transformed by the inverse transpose, so non-uniform scales keep them perpendicular.
+    let normal_transform = transform.invert()?.transpose();
+
+    let vertices = positions
+        .iter()
+        .map(|p| {
+            let point = transform * Vector4::new(p[0], p[1], p[2], 1.0);
+            to_vertex(point.truncate() / point.w)
+        })
+        .collect();
+    let normals: Vec<Vertex> = reader.read_normals().map_or(Vec::new(), |normals| {
+        normals
+            .map(|n| {
+                let normal = (normal_transform * Vector4::new(n[0], n[1], n[2], 0.0)).truncate();
+                to_vertex(normal.normalize())
+            })
+            .collect()
+    });
+    // The origin of glTF texture coordinates is the top left corner, obj's is the bottom left.
+    let tex_vertices: Vec<TVertex> = reader.read_tex_coords(0).map_or(Vec::new(), |uvs| {
+        uvs.into_f32()
+            .map(|uv| TVertex {
+                u: uv[0] as f64,
+                v: 1.0 - uv[1] as f64,
+                w: 0.0,
+            })
+            .collect()
+    });
+    let indices: Vec<usize> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().map(|index| index as usize).collect(),
+        None => (0..positions.len()).collect(),
+    };
+
+    let has_normals = normals.len() == positions.len();
+    let has_uvs = tex_vertices.len() == positions.len();
+    let corner = |index: usize| {
+        (
+            index,
+            if has_uvs { Some(index) } else { None },
+            if has_normals { Some(index) } else { None },
+        )
+    };
+    let shapes = indices
+        .chunks_exact(3)
+        .filter(|triangle| triangle.iter().all(|index| *index < positions.len()))
+        .map(|triangle| Shape {
+            primitive: Primitive::Triangle(
+                corner(triangle[0]),
+                corner(triangle[1]),
+                corner(triangle[2]),
+            ),
+            groups: Vec::new(),
+            smoothing_groups: Vec::new(),
+        })
+        .collect();
+
+    return Some(Object {
+        name: String::new(),
+        vertices,
+        tex_vertices,
+        normals,
+        geometry: vec![Geometry {
+            material_name: primitive.material().index().map(material_name),
+            shapes,
+        }],
+    });
+}
+
+fn convert_material(material: &gltf::Material, textures: &mut GltfTextures) -> Principled {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+
+    let mut principled = Principled::new(Vector3::new(r, g, b));
+    principled.metallic = pbr.metallic_factor();
+    principled.roughness = pbr.roughness_factor();
+    principled.base_color_map = pbr
+        .base_color_texture()
+        .and_then(|info| textures.get(&info.texture(), true));
+    principled.metallic_roughness_map = pbr
+        .metallic_roughness_texture()
+        .and_then(|info| textures.get(&info.texture(), false));
+
+    principled.emission = Vector3::from(material.emissive_factor());
+    principled.emission_map = material
+        .emissive_texture()
+        .and_then(|info| textures.get(&info.texture(), true));
+
+    principled.normal_map = material.normal_texture().and_then(|info| {
+        let texture = textures.get(&info.texture(), false)?;
+        return Some(NormalMap::Normal(texture, info.scale()));
+    });
+
+    if let Some(transmission) = material.transmission() {
+        principled.transmission = transmission.transmission_factor();
+    }
+    if let Some(ior) = material.ior() {
+        principled.ior = ior;
+    }
+    return principled;
+}
+
+// The images of a glTF file, converted into textures when materials first use them.
+struct GltfTextures<'a> {
+    images: &'a [gltf::image::Data],
+    // By image index and whether it holds colors.
+    loaded: HashMap<(usize, bool), Arc<ImageTexture>>,
+}
+
+impl GltfTextures<'_> {
+    // Images in formats other than 8 bits per channel are reported and left out.
+    fn get(&mut self, texture: &gltf::Texture, is_color: bool) -> Option<Texture> {
+        let index = texture.source().index();
+        let wrap = match texture.sampler().wrap_s() {
+            WrappingMode::ClampToEdge => WrapMode::Clamp,
+            WrappingMode::MirroredRepeat => WrapMode::Mirror,
+            WrappingMode::Repeat => WrapMode::Repeat,
+        };
+
+        if !self.loaded.contains_key(&(index, is_color)) {
+            let data = self.images.get(index)?;
+            let channels = match data.format {
+                Format::R8 => 1,
+                Format::R8G8 => 2,
+                Format::R8G8B8 => 3,
+                Format::R8G8B8A8 => 4,
+                format => {
+                    eprintln!(
+                        "Unsupported format {:?} of image {}, rendering without it",
+                        format, index
+                    );
+                    return None;
+                }
+            };
+            let image = ImageTexture::from_bytes(
+                data.width as usize,
+                data.height as usize,
+                channels,
+                &data.pixels,
+                is_color,
+            )
+            .map_err(|e| eprintln!("{}, rendering without it", e))
+            .ok()?;
+            self.loaded.insert((index, is_color), Arc::new(image));
+        }
+
+        return Some(Texture::Image(
+            self.loaded[&(index, is_color)].clone(),
+            wrap,
+        ));
+    }
+}
+
+#[inline]
+fn to_vertex(vector: Vector3<f32>) -> Vertex {
+    return Vertex {
+        x: vector.x as f64,
+        y: vector.y as f64,
+        z: vector.z as f64,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flattens_nodes_into_world_space() {
+        // A single triangle (0 0 0, 1 0 0, 0 1 0), moved up by its parent node and scaled by its own.
+        let buffer = "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA";
+        let source = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [0] }}],
+                "nodes": [
+                    {{ "children": [1], "translation": [0, 2, 0] }},
+                    {{ "mesh": 0, "scale": [3, 3, 3] }}
+                ],
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "material": 0 }}] }}],
+                "materials": [{{
+                    "pbrMetallicRoughness": {{ "baseColorFactor": [1, 0.5, 0, 1], "metallicFactor": 1 }}
+                }}],
+                "buffers": [{{ "byteLength": 36, "uri": "data:application/octet-stream;base64,{}" }}],
+                "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}],
+                "accessors": [{{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0, 0, 0], "max": [1, 1, 0]
+                }}]
+            }}"#,
+            buffer
+        );
+
+        let (document, buffers, images) = gltf::import_slice(source.as_bytes()).unwrap();
+        let (obj_set, material_set) = convert_document(&document, &buffers, &images).unwrap();
+
+        let object = &obj_set.objects[0];
+        assert_eq!(object.vertices.len(), 3);
+        assert_eq!(object.geometry[0].shapes.len(), 1);
+        assert_eq!(object.vertices[1].x, 3.0);
+        assert_eq!(object.vertices[2].y, 5.0);
+
+        let material_name = object.geometry[0].material_name.as_ref().unwrap();
+        let Material::Principled(principled) = material_set.get(material_name) else {
+            panic!("glTF materials should be principled");
+        };
+        assert_eq!(principled.metallic, 1.0);
+        assert_eq!(principled.base_color, Vector3::new(1.0, 0.5, 0.0));
+    }
+}
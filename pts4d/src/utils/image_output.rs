@@ -0,0 +1,42 @@
+use std::path::Path;
+
+use image::{ImageFormat, RgbImage};
+
+use crate::scene::screen::Screen;
+use crate::utils::rendering_utils::preprocess_color;
+
+// Converts a screen holding the sum of `iterations` passes into an 8 bit image.
+pub fn screen_to_image(screen: &Screen, iterations: i32) -> RgbImage {
+    let height = screen.len();
+    let width = if height > 0 { screen[0].len() } else { 0 };
+
+    return RgbImage::from_fn(width as u32, height as u32, |x, y| {
+        let color = preprocess_color(screen[y as usize][x as usize] / iterations as f32);
+        image::Rgb([color.x as u8, color.y as u8, color.z as u8])
+    });
+}
+
+// The format an image is written in, PNG or binary PPM depending on the extension of `path`.
+pub fn image_format(path: &str) -> Result<ImageFormat, String> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    return match extension.as_deref() {
+        Some("png") => Ok(ImageFormat::Png),
+        Some("ppm") => Ok(ImageFormat::Pnm),
+        _ => Err(format!(
+            "Can't tell which format to write '{}' in, use a .png or .ppm extension",
+            path
+        )),
+    };
+}
+
+// Writes the screen to disk, in the format given by the extension of `path`.
+pub fn save_screen(screen: &Screen, iterations: i32, path: &str) -> Result<(), String> {
+    let format = image_format(path)?;
+    return screen_to_image(screen, iterations)
+        .save_with_format(path, format)
+        .map_err(|e| format!("There was an error writing '{}': {}", path, e));
+}
@@ -1,13 +1,16 @@
 use cgmath::Vector3;
 use sdl2::{
-    event::Event, keyboard::Keycode, pixels::Color, rect::Point, render::Canvas, video::Window,
+    event::Event, keyboard::Keycode, mouse::MouseButton, pixels::Color, rect::Point,
+    render::Canvas, video::Window,
 };
 
 use crate::scene::{
     camera::{Camera, CameraConfig},
     scene::Scene,
-    screen::{Screen, HEIGHT, WIDTH},
+    screen::Screen,
+    settings::RenderSettings,
 };
+use crate::utils::vector_utils::Interval;
 
 pub fn preprocess_color(color: Vector3<f32>) -> Vector3<f32> {
     let mut r = color.x;
@@ -26,20 +29,20 @@ pub fn preprocess_color(color: Vector3<f32>) -> Vector3<f32> {
     return Vector3::new(r * 255.0, g * 255.0, b * 255.0);
 }
 
-pub fn initialize_screen() -> Vec<Vec<Vector3<f32>>> {
-    return vec![vec![Vector3::new(0.0, 0.0, 0.0); WIDTH]; HEIGHT];
+pub fn initialize_screen(settings: &RenderSettings) -> Vec<Vec<Vector3<f32>>> {
+    return vec![vec![Vector3::new(0.0, 0.0, 0.0); settings.width]; settings.height];
 }
 
 pub fn add_screens(screen1: Screen, screen2: Screen) -> Screen {
     return screen1
         .iter()
         .zip(screen2.iter())
-        .map(|(&ref row1, &ref row2)| add_rows(&row1, &row2))
+        .map(|(row1, row2)| add_rows(row1, row2))
         .collect();
 }
 
 #[inline]
-fn add_rows(row1: &Vec<Vector3<f32>>, row2: &Vec<Vector3<f32>>) -> Vec<Vector3<f32>> {
+fn add_rows(row1: &[Vector3<f32>], row2: &[Vector3<f32>]) -> Vec<Vector3<f32>> {
     return row1.iter().zip(row2.iter()).map(|(&x, &y)| x + y).collect();
 }
 
@@ -89,6 +92,29 @@ pub fn handle_input(event: Event, scene: &mut Scene) -> bool {
             scene.camera = renew_camera(&scene.camera.camera_config);
             return true;
         }
+        Event::MouseButtonDown {
+            mouse_btn: MouseButton::Left,
+            x,
+            y,
+            ..
+        } => {
+            // Focus on whatever surface is under the cursor.
+            let config = &scene.camera.camera_config;
+            let Some(ray) = scene.camera.center_ray(
+                x as f32 / config.image_width,
+                y as f32 / config.image_height,
+            ) else {
+                return false;
+            };
+            let Some(hit) = scene.intersect(&ray, Interval::new(0.001, f32::MAX)) else {
+                return false;
+            };
+            let focus_distance = scene.camera.depth_of(hit.point);
+
+            scene.camera.camera_config.focus_distance = focus_distance;
+            scene.camera = renew_camera(&scene.camera.camera_config);
+            return true;
+        }
         _ => {
             return false;
         }
@@ -96,14 +122,7 @@ pub fn handle_input(event: Event, scene: &mut Scene) -> bool {
 }
 
 pub fn renew_camera(config: &CameraConfig) -> Camera {
-    return Camera::new(
-        config.image_height,
-        config.image_width,
-        config.fov,
-        config.look_from,
-        config.look_at,
-        config.up,
-    );
+    return Camera::from_config(config.clone());
 }
 
 pub fn present_screen(screen: &Screen, sdl_canvas: &mut Canvas<Window>, iteration: i32) {
@@ -1,64 +1,130 @@
 use std::fs;
+use std::path::Path;
+use std::sync::Arc;
 
 use cgmath::Vector3;
 use wavefront_obj::obj::ObjSet;
 
+use crate::materials::ior::Ior;
 use crate::materials::material::{Material, MaterialSet};
+use crate::materials::mtl::parse_mtl;
+use crate::materials::principled::Principled;
+use crate::materials::texture::Texture;
+use crate::object::instance::{MeshInstance, Transform};
 use crate::object::mesh::Mesh;
 use crate::object::sphere::Sphere;
+use crate::object::triangle::TriangleIntersector;
 use crate::scene::camera::Camera;
 use crate::scene::scene::Scene;
-use crate::scene::screen::{HEIGHT, WIDTH};
+use crate::scene::settings::RenderSettings;
+use crate::utils::gltf_loader::load_and_parse_gltf;
+
+// Loads a model, as a glTF (.gltf, .glb) or obj file depending on its extension.
+pub fn load_model(path: &str) -> Result<(ObjSet, MaterialSet), String> {
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    return match extension.as_deref() {
+        Some("gltf") | Some("glb") => load_and_parse_gltf(path),
+        _ => load_and_parse_obj(path),
+    };
+}
 
-// Loads an obj file into memory and parses it into an ObjSet
-pub fn load_and_parse_obj(path: &str) -> (ObjSet, MaterialSet) {
-    let obj_string = fs::read_to_string(path);
-    if obj_string.is_err() {
-        panic!("There was an error opening and reading '{}'", path);
-    }
+// Loads an obj file into memory and parses it into an ObjSet,
+// along with the materials of the mtl library it references (if any).
+pub fn load_and_parse_obj(path: &str) -> Result<(ObjSet, MaterialSet), String> {
+    let obj_string = fs::read_to_string(path)
+        .map_err(|e| format!("There was an error opening and reading '{}': {}", path, e))?;
 
-    let loaded_obj = wavefront_obj::obj::parse(obj_string.unwrap());
-    if loaded_obj.is_err() {
-        panic!("There was an error parsing '{}'", path);
-    }
+    let loaded_obj = wavefront_obj::obj::parse(obj_string)
+        .map_err(|e| format!("There was an error parsing '{}': {:?}", path, e))?;
 
-    let mtl_string = fs::read_to_string(path.replace(".obj", ".mtl"));
-    if mtl_string.is_err() {
-        panic!(
-            "There was an error opening and reading '{}'",
-            path.replace(".obj", ".mtl")
-        );
+    let mut material_set = MaterialSet::new();
+    if let Some(material_library) = &loaded_obj.material_library {
+        // Material libraries are relative to the obj file.
+        let mtl_path = Path::new(path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(material_library);
+
+        // A missing library isn't fatal, everything just gets the default material.
+        let mtl_string = match fs::read_to_string(&mtl_path) {
+            Ok(mtl_string) => mtl_string,
+            Err(e) => {
+                eprintln!(
+                    "Couldn't read the materials of '{}' from '{}' ({}), using the default material",
+                    path,
+                    mtl_path.display(),
+                    e
+                );
+                return Ok((loaded_obj, material_set));
+            }
+        };
+
+        let loaded_mtl = parse_mtl(
+            &mtl_string,
+            &mtl_path.to_string_lossy(),
+            mtl_path.parent().unwrap_or(Path::new("")),
+        )?;
+
+        for mat in loaded_mtl {
+            let name = mat.name.to_string();
+            let material = if mat.is_physically_based() {
+                Material::Principled(Principled::from_mtl(&mat))
+            } else {
+                Material::WavefrontObjMaterial(mat)
+            };
+            material_set.add(name, material);
+        }
     }
 
-    let loaded_mtl = wavefront_obj::mtl::parse(mtl_string.unwrap());
-    let mut material_set = MaterialSet::new();
+    return Ok((loaded_obj, material_set));
+}
 
-    for mat in loaded_mtl.unwrap().materials {
-        material_set.add(mat.name.to_string(), Material::WavefrontObjMaterial(mat));
+// Picks one of the scenes above by name.
+pub fn generate_named_scene(
+    name: &str,
+    obj_path: Option<&str>,
+    settings: &RenderSettings,
+) -> Result<Scene, String> {
+    match name {
+        "cornell-box" => generate_cornell_box_scene(settings),
+        "polygon" => match obj_path {
+            Some(path) => generate_polygon_scene(path, settings),
+            None => Err("The polygon scene needs a model, pass one with --obj".to_string()),
+        },
+        _ => Err(format!("Unknown scene '{}'", name)),
     }
-
-    return (loaded_obj.unwrap(), material_set);
 }
 
-#[allow(dead_code)]
 // Creates a scene including complex polygon models.
-pub fn generate_polygon_scene(path: &str) -> Scene {
-    let (mesh, _mesh_materials) = load_and_parse_obj(path);
+pub fn generate_polygon_scene(path: &str, settings: &RenderSettings) -> Result<Scene, String> {
+    let (mesh, _mesh_materials) = load_and_parse_obj(path)?;
     let look_from = Vector3::new(5.0, 2.0, 5.0);
     let look_at = Vector3::new(0.0, 3.0, 0.0);
     let up = Vector3::new(0.0, -1.0, 0.0); // TODO: WTF?
-    let camera: Camera = Camera::new(HEIGHT as f32, WIDTH as f32, 60.0, look_from, look_at, up);
+    let camera: Camera = Camera::new(settings, 60.0, look_from, look_at, up);
 
-    let loaded_mesh = Mesh::new_override_material(
-        Vector3::new(0.0, 1.0, 0.0),
-        1.0,
-        mesh.clone(),
-        Material::Diffuse(Vector3::new(0.8, 0.6, 0.7)),
+    let loaded_mesh = Arc::new(
+        Mesh::new_override_material(mesh, Material::Diffuse(Vector3::new(0.8, 0.6, 0.7)))
+            .map_err(|e| format!("There was an error loading '{}': {}", path, e))?,
+    );
+    let mesh_instance = MeshInstance::new(
+        loaded_mesh,
+        Transform::from_translation_and_scale(Vector3::new(0.0, 1.0, 0.0), 1.0)?,
     );
 
-    let sphere1 = Sphere::new(Vector3::new(5.0, 1.0, 0.0), 2.0, Material::Dielectric(2.0));
+    let sphere1 = Sphere::new(
+        Vector3::new(5.0, 1.0, 0.0),
+        2.0,
+        Material::Dielectric(Ior::Constant(2.0)),
+    );
 
-    let sphere2 = Sphere::new(Vector3::new(5.0, 1.0, 0.0), 1.8, Material::Dielectric(2.0));
+    let sphere2 = Sphere::new(
+        Vector3::new(5.0, 1.0, 0.0),
+        1.8,
+        Material::Dielectric(Ior::Constant(2.0)),
+    );
 
     let ground_sphere = Sphere::new(
         Vector3::new(0.0, -501.0, 0.0),
@@ -66,54 +132,53 @@ pub fn generate_polygon_scene(path: &str) -> Scene {
         Material::Diffuse(Vector3::new(0.8, 0.6, 0.7)),
     );
 
-    return Scene::build_complex_scene(
-        vec![loaded_mesh],
+    return Ok(Scene::build_complex_scene(
+        vec![mesh_instance],
         vec![ground_sphere, sphere1, sphere2],
         camera,
-    );
+    ));
 }
 
-pub fn generate_cornell_box_scene() -> Scene {
+pub fn generate_cornell_box_scene(settings: &RenderSettings) -> Result<Scene, String> {
     let look_from = Vector3::new(-0.2, 3.5, 4.2);
     let look_at = Vector3::new(-0.2, 3.5, 0.5);
     let up = Vector3::new(0.0, -1.0, 0.0); // TODO: WTF?
-    let camera: Camera = Camera::new(HEIGHT as f32, WIDTH as f32, 60.0, look_from, look_at, up);
+    let camera: Camera = Camera::new(settings, 60.0, look_from, look_at, up);
 
-    let (mesh, mesh_materials) = load_and_parse_obj("./objs/benchmark/cornell-box.obj");
+    let (mesh, mesh_materials) = load_and_parse_obj("./objs/benchmark/cornell-box.obj")?;
 
-    let loaded_mesh = Mesh::new_override_material_set(
-        Vector3::new(0.0, 1.0, 0.0),
-        1.0,
-        mesh.clone(),
-        mesh_materials,
+    let loaded_mesh = Arc::new(Mesh::new_override_material_set(mesh, mesh_materials)?);
+    let mesh_instance = MeshInstance::new(
+        loaded_mesh,
+        Transform::from_translation_and_scale(Vector3::new(0.0, 1.0, 0.0), 1.0)?,
     );
 
     let green_light_sphere = Sphere::new(
         Vector3::new(-1.8, 5.5, -3.5),
         0.35,
-        Material::Emissive(Vector3::new(0.2, 1.0, 0.2), 15.0),
+        Material::Emissive(Texture::Constant(Vector3::new(0.2, 1.0, 0.2)), 15.0),
     );
 
     let red_light_sphere = Sphere::new(
         Vector3::new(1.3, 5.5, -3.5),
         0.35,
-        Material::Emissive(Vector3::new(1.0, 0.2, 0.2), 15.0),
+        Material::Emissive(Texture::Constant(Vector3::new(1.0, 0.2, 0.2)), 15.0),
     );
 
     let dielectric_sphere = Sphere::new(
         Vector3::new(-1.5, 2.2, -1.5),
         1.0,
-        Material::Dielectric(1.4),
+        Material::Dielectric(Ior::Constant(1.4)),
     );
 
     let metal_sphere = Sphere::new(
         Vector3::new(1.0, 3.7, -3.0),
         1.0,
-        Material::Metallic(Vector3::new(1.0, 1.0, 1.0), 0.0),
+        Material::Metallic(Texture::uniform(1.0), Texture::uniform(0.0)),
     );
 
-    return Scene::build_complex_scene(
-        vec![loaded_mesh],
+    let mut scene = Scene::build_complex_scene(
+        vec![mesh_instance],
         vec![
             metal_sphere,
             dielectric_sphere,
@@ -122,27 +187,8 @@ pub fn generate_cornell_box_scene() -> Scene {
         ],
         camera,
     );
-}
-
-#[allow(dead_code)]
-pub fn generate_sphere_scene() -> Scene {
-    let look_from = Vector3::new(0.0, 5.0, 30.0);
-    let look_at = Vector3::new(0.0, 5.0, 0.0);
-    let up = Vector3::new(0.0, -1.0, 0.0); // TODO: WTF?
-    let _camera: Camera = Camera::new(HEIGHT as f32, WIDTH as f32, 40.0, look_from, look_at, up);
-
-    let _ground_sphere = Sphere::new(
-        Vector3::new(0.0, 5.0, 0.0),
-        5.0,
-        Material::Diffuse(Vector3::new(0.999, 0.0, 0.0)),
-    );
-
-    let _main_sphere = Sphere::new(
-        Vector3::new(0.0, -500.0, 0.0),
-        500.0,
-        Material::Diffuse(Vector3::new(0.9, 0.9, 0.1)),
-    );
+    // The walls of the box share edges, which Möller-Trumbore lets rays slip through.
+    scene.triangle_intersector = TriangleIntersector::Watertight;
 
-    todo!();
-    // return Scene::_build_sphere_scene(vec![main_sphere, ground_sphere], camera);
+    return Ok(scene);
 }
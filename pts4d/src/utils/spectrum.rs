@@ -0,0 +1,217 @@
+use std::sync::OnceLock;
+
+use cgmath::{ElementWise, Vector3};
+use rand::Rng;
+
+// The visible range traced in spectral mode, in nanometers.
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 720.0;
+const WAVELENGTH_RANGE: f32 = MAX_WAVELENGTH - MIN_WAVELENGTH;
+
+// Smits' basis spectra for turning RGB colors into spectra ("An RGB to Spectrum Conversion for
+// Reflectances", 1999), sampled in 10 bins evenly spread over the visible range.
+const SMITS_WHITE: [f32; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f32; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f32; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f32; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f32; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f32; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f32; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/**
+ * The wavelengths a path is traced at in spectral mode: a random hero wavelength, and two more
+ * spread evenly over the visible range from it. The values of a spectrum at them are kept in a
+ * Vector3, so the renderer carries them around where it would carry RGB colors otherwise.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Wavelengths {
+    // In nanometers, the hero wavelength first.
+    pub nanometers: [f32; 3],
+    // Whether the other wavelengths were dropped, see `only_hero`.
+    pub is_hero_only: bool,
+}
+
+impl Wavelengths {
+    pub fn sample() -> Wavelengths {
+        let offset = rand::thread_rng().gen::<f32>();
+        let at = |i: usize| MIN_WAVELENGTH + (offset + i as f32 / 3.0).fract() * WAVELENGTH_RANGE;
+        return Wavelengths {
+            nanometers: [at(0), at(1), at(2)],
+            is_hero_only: false,
+        };
+    }
+
+    pub fn hero(&self) -> f32 {
+        return self.nanometers[0];
+    }
+
+    // The spectrum of an RGB color (a reflectance, emission, transmittance...) at the wavelengths.
+    pub fn uplift(&self, rgb: Vector3<f32>) -> Vector3<f32> {
+        return Vector3::new(
+            rgb_to_spectrum(rgb, self.nanometers[0]),
+            rgb_to_spectrum(rgb, self.nanometers[1]),
+            rgb_to_spectrum(rgb, self.nanometers[2]),
+        );
+    }
+
+    // Dispersion sends every wavelength its own way, so only the hero one can be followed on.
+    // It stands in for the other two from then on, which is what the returned weight is for.
+    pub fn only_hero(&self) -> (Wavelengths, Vector3<f32>) {
+        if self.is_hero_only {
+            return (*self, Vector3::new(1.0, 1.0, 1.0));
+        }
+        let hero_only = Wavelengths {
+            is_hero_only: true,
+            ..*self
+        };
+        return (hero_only, Vector3::new(3.0, 0.0, 0.0));
+    }
+
+    // Radiance at the wavelengths, as the linear sRGB color it contributes to the pixel.
+    // A constant spectrum of 1 is white (1, 1, 1) on average, as in RGB mode.
+    pub fn to_rgb(self, radiance: Vector3<f32>) -> Vector3<f32> {
+        let mut xyz = Vector3::new(0.0, 0.0, 0.0);
+        for (i, wavelength) in self.nanometers.iter().enumerate() {
+            xyz += xyz_matching(*wavelength) * radiance[i];
+        }
+        // Divided by the density the wavelengths were sampled with.
+        xyz *= WAVELENGTH_RANGE / 3.0;
+        return xyz_to_linear_srgb(xyz).div_element_wise(*white_balance());
+    }
+}
+
+// Smits' RGB to spectrum conversion: the color is built out of white, then the secondary color
+// and the primary color it has the most of, whose spectra are smooth and positive.
+pub fn rgb_to_spectrum(rgb: Vector3<f32>, wavelength: f32) -> f32 {
+    let (r, g, b) = (rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0));
+    let basis = |spectrum: &[f32; 10]| smits_basis(spectrum, wavelength);
+
+    if r <= g && r <= b {
+        let white = r * basis(&SMITS_WHITE);
+        if g <= b {
+            return white + (g - r) * basis(&SMITS_CYAN) + (b - g) * basis(&SMITS_BLUE);
+        }
+        return white + (b - r) * basis(&SMITS_CYAN) + (g - b) * basis(&SMITS_GREEN);
+    }
+    if g <= r && g <= b {
+        let white = g * basis(&SMITS_WHITE);
+        if r <= b {
+            return white + (r - g) * basis(&SMITS_MAGENTA) + (b - r) * basis(&SMITS_BLUE);
+        }
+        return white + (b - g) * basis(&SMITS_MAGENTA) + (r - b) * basis(&SMITS_RED);
+    }
+    let white = b * basis(&SMITS_WHITE);
+    if r <= g {
+        return white + (r - b) * basis(&SMITS_YELLOW) + (g - r) * basis(&SMITS_GREEN);
+    }
+    return white + (g - b) * basis(&SMITS_YELLOW) + (r - g) * basis(&SMITS_RED);
+}
+
+// One of Smits' spectra at the wavelength, interpolated between the centers of its bins.
+fn smits_basis(spectrum: &[f32; 10], wavelength: f32) -> f32 {
+    let bins = spectrum.len();
+    let position = (wavelength - MIN_WAVELENGTH) / WAVELENGTH_RANGE * bins as f32 - 0.5;
+    let position = position.clamp(0.0, (bins - 1) as f32);
+    let bin = (position as usize).min(bins - 2);
+    let t = position - bin as f32;
+    return spectrum[bin] * (1.0 - t) + spectrum[bin + 1] * t;
+}
+
+// The CIE 1931 color matching functions, by the multi-lobe gaussian fit of Wyman, Sloan and
+// Shirley ("Simple Analytic Approximations to the CIE XYZ Color Matching Functions", 2013).
+pub fn xyz_matching(wavelength: f32) -> Vector3<f32> {
+    let lobe = |mean: f32, left_width: f32, right_width: f32| {
+        let width = if wavelength < mean {
+            left_width
+        } else {
+            right_width
+        };
+        let t = (wavelength - mean) / width;
+        return (-0.5 * t * t).exp();
+    };
+
+    return Vector3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    );
+}
+
+#[inline]
+fn xyz_to_linear_srgb(xyz: Vector3<f32>) -> Vector3<f32> {
+    return Vector3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    );
+}
+
+// The color of a constant spectrum of 1, which the film divides by so that it comes out white.
+fn white_balance() -> &'static Vector3<f32> {
+    static WHITE: OnceLock<Vector3<f32>> = OnceLock::new();
+    return WHITE.get_or_init(|| {
+        let steps = 1000;
+        let step = WAVELENGTH_RANGE / steps as f32;
+        let mut xyz = Vector3::new(0.0, 0.0, 0.0);
+        for i in 0..steps {
+            xyz += xyz_matching(MIN_WAVELENGTH + (i as f32 + 0.5) * step) * step;
+        }
+        return xyz_to_linear_srgb(xyz);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uplifted_colors_come_back_as_themselves() {
+        let colors = [
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(0.5, 0.5, 0.5),
+            Vector3::new(0.9, 0.1, 0.1),
+            Vector3::new(0.1, 0.8, 0.2),
+            Vector3::new(0.2, 0.3, 0.9),
+            Vector3::new(0.8, 0.7, 0.2),
+        ];
+        for color in colors {
+            // Integrated over the whole range, with evenly spaced sets of wavelengths.
+            let mut rgb = Vector3::new(0.0, 0.0, 0.0);
+            let sets = 300;
+            for i in 0..sets {
+                let hero = MIN_WAVELENGTH + (i as f32 + 0.5) / sets as f32 * WAVELENGTH_RANGE / 3.0;
+                let wavelengths = Wavelengths {
+                    nanometers: [
+                        hero,
+                        hero + WAVELENGTH_RANGE / 3.0,
+                        hero + 2.0 * WAVELENGTH_RANGE / 3.0,
+                    ],
+                    is_hero_only: false,
+                };
+                rgb += wavelengths.to_rgb(wavelengths.uplift(color)) / sets as f32;
+            }
+            let error = rgb - color;
+            assert!(
+                error.x.abs() < 0.1 && error.y.abs() < 0.1 && error.z.abs() < 0.1,
+                "{:?} came back as {:?}",
+                color,
+                rgb
+            );
+        }
+    }
+}
@@ -9,7 +9,7 @@ pub struct Ray {
 
 impl Ray {
     pub fn point_at(&self, t: f32) -> Vector3<f32> {
-        return &self.origin + t * &self.direction;
+        return self.origin + t * self.direction;
     }
 }
 
@@ -26,6 +26,7 @@ pub fn random_point_in_unit_sphere() -> Vector3<f32> {
     };
 }
 
+#[derive(Copy, Clone, Debug)]
 pub struct Interval {
     pub min: f32,
     pub max: f32,
@@ -70,3 +71,21 @@ pub fn correct_face_normal(ray: &Ray, normal: Vector3<f32>) -> Vector3<f32> {
         return -normal;
     }
 }
+
+// Builds two unit vectors which, together with the unit vector `n`, form an orthonormal basis.
+// (Duff et al. 2017, "Building an Orthonormal Basis, Revisited")
+#[inline]
+pub fn orthonormal_basis(n: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let sign = 1.0_f32.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    return (
+        Vector3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3::new(b, sign + n.y * n.y * a, -n.y),
+    );
+}
+
+#[inline]
+pub fn random_unit_vector() -> Vector3<f32> {
+    return random_point_in_unit_sphere().normalize();
+}